@@ -1,6 +1,7 @@
 pub mod evaluation;
 mod history;
 pub mod movegen;
+pub mod packed;
 pub mod validation;
 
 use std::{
@@ -9,7 +10,7 @@ use std::{
 };
 
 use rand::prelude::SliceRandom;
-use rand::rngs::ThreadRng;
+use rand::Rng;
 use regex::Regex;
 
 use crate::{
@@ -25,7 +26,7 @@ use crate::{
     errors::{FenParseError, MoveParseError},
     lookups::{PIECE_BIG, PIECE_MAJ},
     macros,
-    makemove::{hash_castling, hash_ep, hash_piece, hash_side, CASTLE_PERM_MASKS},
+    makemove::{hash_castling, hash_ep, hash_piece, hash_side},
     nnue::network::{Activate, Deactivate},
     piece::{Colour, Piece, PieceType},
     piecesquaretable::pst_value,
@@ -58,6 +59,12 @@ fn get_san_regex() -> &'static Regex {
     }
 }
 
+/// The squares from `a` to `b` inclusive, which must lie on the same rank.
+const fn back_rank_span(a: Square, b: Square) -> u64 {
+    let (lo, hi) = if a.index() < b.index() { (a, b) } else { (b, a) };
+    (u64::MAX >> (63 - hi.index())) & (u64::MAX << lo.index())
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct Board {
     /// The bitboards of all the pieces on the board.
@@ -70,6 +77,10 @@ pub struct Board {
     ep_sq: Square,
     /// The castling permissions.
     castle_perm: u8,
+    /// The starting square of the rook used by each castling permission, in bit order.
+    castling_rooks: [Square; 4],
+    /// The castling permissions that survive a move to or from each square.
+    castle_perm_masks: [u8; 64],
     /// The number of half moves made since the last capture or pawn advance.
     fifty_move_counter: u8,
     /// The number of half moves made since the start of the game.
@@ -105,6 +116,7 @@ impl Debug for Board {
             .field("minor_piece_counts", &self.minor_piece_counts)
             .field("material", &self.material)
             .field("castle_perm", &self.castle_perm)
+            .field("castling_rooks", &self.castling_rooks)
             .field("pst_vals", &self.pst_vals)
             .finish_non_exhaustive()
     }
@@ -113,6 +125,7 @@ impl Debug for Board {
 impl Board {
     pub const STARTING_FEN: &'static str =
         "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const STANDARD_CASTLING_ROOKS: [Square; 4] = [Square::H1, Square::A1, Square::H8, Square::A8];
 
     pub fn new() -> Self {
         let mut out = Self {
@@ -129,6 +142,8 @@ impl Board {
             minor_piece_counts: [0; 2],
             material: [S(0, 0); 2],
             castle_perm: 0,
+            castling_rooks: Self::STANDARD_CASTLING_ROOKS,
+            castle_perm_masks: [0b1111; 64],
             history: Vec::new(),
             repetition_cache: Vec::new(),
             pst_vals: S(0, 0),
@@ -150,6 +165,30 @@ impl Board {
         self.pieces.occupied().count_ones() as u8
     }
 
+    /// The material on the board, as in `KRPPvKR`, with White's pieces first.
+    pub fn material_signature(&self) -> String {
+        let mut signature = String::new();
+        for colour in [Colour::WHITE, Colour::BLACK] {
+            if colour == Colour::BLACK {
+                signature.push('v');
+            }
+            for piece_type in [
+                PieceType::KING,
+                PieceType::QUEEN,
+                PieceType::ROOK,
+                PieceType::BISHOP,
+                PieceType::KNIGHT,
+                PieceType::PAWN,
+            ] {
+                let symbol = Piece::new(Colour::WHITE, piece_type).char().unwrap();
+                for _ in 0..self.num(Piece::new(colour, piece_type)) {
+                    signature.push(symbol);
+                }
+            }
+        }
+        signature
+    }
+
     pub const fn ply(&self) -> usize {
         self.ply
     }
@@ -237,6 +276,8 @@ impl Board {
         self.height = 0;
         self.ply = 0;
         self.castle_perm = 0;
+        self.castling_rooks = Self::STANDARD_CASTLING_ROOKS;
+        self.castle_perm_masks = [0b1111; 64];
         self.key = 0;
         self.pst_vals = S(0, 0);
         self.history.clear();
@@ -374,26 +415,112 @@ impl Board {
         Ok(())
     }
 
+    /// Reads castling rights in X-FEN or Shredder-FEN form.
+    ///
+    /// `K`/`Q` castle with the outermost rook on that side of the king, while a file letter
+    /// names the rook's file directly, as Chess960 positions sometimes require.
     fn set_castling(&mut self, castling_part: Option<&[u8]>) -> Result<(), FenParseError> {
         match castling_part {
             None => return Err("FEN string is invalid, expected castling part.".into()),
             Some([b'-']) => self.castle_perm = 0,
             Some(castling) => {
+                let invalid = || {
+                    format!(
+                        "FEN string is invalid, expected castling part to be of the form 'KQkq' or 'HAha', got \"{}\"",
+                        std::str::from_utf8(castling).unwrap_or("<invalid utf8>")
+                    )
+                };
                 for &c in castling {
-                    match c {
-                        b'K' => self.castle_perm |= WKCA,
-                        b'Q' => self.castle_perm |= WQCA,
-                        b'k' => self.castle_perm |= BKCA,
-                        b'q' => self.castle_perm |= BQCA,
-                        _ => return Err(format!("FEN string is invalid, expected castling part to be of the form 'KQkq', got \"{}\"", std::str::from_utf8(castling).unwrap_or("<invalid utf8>"))),
+                    let colour = if c.is_ascii_uppercase() { Colour::WHITE } else { Colour::BLACK };
+                    let king_sq = self.back_rank_king(colour);
+                    let rook_sq = match c.to_ascii_uppercase() {
+                        b'K' => king_sq.and_then(|k| self.outermost_rook(colour, k, true)),
+                        b'Q' => king_sq.and_then(|k| self.outermost_rook(colour, k, false)),
+                        file @ b'A'..=b'H' => {
+                            let rook_sq = Square::from_rank_file(
+                                king_sq.ok_or_else(invalid)?.rank(),
+                                file - b'A',
+                            );
+                            if self.piece_at(rook_sq) != Piece::new(colour, PieceType::ROOK) {
+                                return Err(invalid());
+                            }
+                            Some(rook_sq)
+                        }
+                        _ => return Err(invalid()),
+                    };
+                    // rights to a rook that isn't there can never be used, so they are dropped.
+                    if let (Some(king_sq), Some(rook_sq)) = (king_sq, rook_sq) {
+                        let right = match (colour, rook_sq > king_sq) {
+                            (Colour::WHITE, true) => WKCA,
+                            (Colour::WHITE, false) => WQCA,
+                            (_, true) => BKCA,
+                            (_, false) => BQCA,
+                        };
+                        self.castle_perm |= right;
+                        self.castling_rooks[right.trailing_zeros() as usize] = rook_sq;
                     }
                 }
             }
         }
+        self.update_castle_perm_masks();
 
         Ok(())
     }
 
+    /// The square of `colour`'s king, if it stands on its back rank.
+    fn back_rank_king(&self, colour: Colour) -> Option<Square> {
+        let back_rank = if colour == Colour::WHITE { Rank::RANK_1 } else { Rank::RANK_8 };
+        let king = Piece::new(colour, PieceType::KING);
+        (File::FILE_A..=File::FILE_H)
+            .map(|file| Square::from_rank_file(back_rank, file))
+            .find(|&sq| self.piece_at(sq) == king)
+    }
+
+    /// The square of `colour`'s rook furthest from `king_sq` on the given wing of the back rank.
+    fn outermost_rook(&self, colour: Colour, king_sq: Square, kingside: bool) -> Option<Square> {
+        let rook = Piece::new(colour, PieceType::ROOK);
+        let on_rank = |file| Square::from_rank_file(king_sq.rank(), file);
+        if kingside {
+            (king_sq.file() + 1..=File::FILE_H)
+                .rev()
+                .map(on_rank)
+                .find(|&sq| self.piece_at(sq) == rook)
+        } else {
+            (File::FILE_A..king_sq.file()).map(on_rank).find(|&sq| self.piece_at(sq) == rook)
+        }
+    }
+
+    /// Rebuilds the table of permissions lost by moving to or from each square,
+    /// from the current castling permissions and rook squares.
+    fn update_castle_perm_masks(&mut self) {
+        self.castle_perm_masks = [0b1111; 64];
+        for (i, right) in [WKCA, WQCA, BKCA, BQCA].into_iter().enumerate() {
+            if self.castle_perm & right == 0 {
+                continue;
+            }
+            let (colour, side_rights) = if right & (WKCA | WQCA) != 0 {
+                (Colour::WHITE, WKCA | WQCA)
+            } else {
+                (Colour::BLACK, BKCA | BQCA)
+            };
+            self.castle_perm_masks[self.castling_rooks[i].index()] &= !right;
+            if let Some(king_sq) = self.back_rank_king(colour) {
+                self.castle_perm_masks[king_sq.index()] &= !side_rights;
+            }
+        }
+    }
+
+    /// The squares that the rook moves from and to when the king castles to `king_to`.
+    fn castling_rook_move(&self, king_to: Square) -> (Square, Square) {
+        match king_to {
+            Square::G1 => (self.castling_rooks[0], Square::F1),
+            Square::C1 => (self.castling_rooks[1], Square::D1),
+            Square::G8 => (self.castling_rooks[2], Square::F8),
+            Square::C8 => (self.castling_rooks[3], Square::D8),
+            _ => panic!("Invalid castle move"),
+        }
+    }
+
     fn set_ep(&mut self, ep_part: Option<&[u8]>) -> Result<(), FenParseError> {
         match ep_part {
             None => return Err("FEN string is invalid, expected en passant part.".to_string()),
@@ -538,6 +665,12 @@ impl Board {
             return false;
         }
 
+        // in Chess960, the king may castle onto a square held by its own rook.
+        if m.is_castle() {
+            return moved_piece.piece_type() == PieceType::KING
+                && self.is_pseudo_legal_castling(to);
+        }
+
         if is_capture && captured_piece.colour() == self.side {
             return false;
         }
@@ -548,18 +681,10 @@ impl Board {
             return false;
         }
 
-        if moved_piece.piece_type() != PieceType::KING && m.is_castle() {
-            return false;
-        }
-
         if is_capture && is_pawn_double_push {
             return false;
         }
 
-        if m.is_castle() {
-            return self.is_pseudo_legal_castling(to);
-        }
-
         if moved_piece.piece_type() == PieceType::PAWN {
             let should_be_promoting = to > Square::H7 || to < Square::A2;
             if should_be_promoting && !m.is_promo() {
@@ -587,49 +712,38 @@ impl Board {
     }
 
     pub fn is_pseudo_legal_castling(&self, to: Square) -> bool {
-        const WK_FREESPACE: u64 = Square::F1.bitboard() | Square::G1.bitboard();
-        const WQ_FREESPACE: u64 =
-            Square::B1.bitboard() | Square::C1.bitboard() | Square::D1.bitboard();
-        const BK_FREESPACE: u64 = Square::F8.bitboard() | Square::G8.bitboard();
-        const BQ_FREESPACE: u64 =
-            Square::B8.bitboard() | Square::C8.bitboard() | Square::D8.bitboard();
-        let occupied = self.pieces.occupied();
-
-        assert!(to == Square::C1 || to == Square::G1 || to == Square::C8 || to == Square::G8);
-
         // illegal if
         // - we don't have castling rights on the target square
         // - we're in check
-        // - there are pieces between the king and the rook
+        // - any piece other than the king and rook stands between them and their destinations
         // - the king passes through a square that is attacked by the opponent
         // - the king ends up in check (not checked here)
 
-        let (target_castling_perm, occ_mask, from_sq, next_sq) = match (self.side, to) {
-            (Colour::WHITE, Square::C1) => {
-                (self.castle_perm & WQCA, WQ_FREESPACE, Square::E1, Square::D1)
-            }
-            (Colour::WHITE, Square::G1) => {
-                (self.castle_perm & WKCA, WK_FREESPACE, Square::E1, Square::F1)
-            }
-            (Colour::BLACK, Square::C8) => {
-                (self.castle_perm & BQCA, BQ_FREESPACE, Square::E8, Square::D8)
-            }
-            (Colour::BLACK, Square::G8) => {
-                (self.castle_perm & BKCA, BK_FREESPACE, Square::E8, Square::F8)
-            }
+        let right = match (self.side, to) {
+            (Colour::WHITE, Square::G1) => WKCA,
+            (Colour::WHITE, Square::C1) => WQCA,
+            (Colour::BLACK, Square::G8) => BKCA,
+            (Colour::BLACK, Square::C8) => BQCA,
             (_, _) => return false,
         };
-        if target_castling_perm == 0 {
+        if self.castle_perm & right == 0 {
             return false;
         }
-        if occupied & occ_mask != 0 {
-            return false;
-        }
-        if self.sq_attacked(from_sq, self.side.flip()) {
+
+        let king_from = self.king_sq(self.side);
+        let (rook_from, rook_to) = self.castling_rook_move(to);
+        let blockers = self.pieces.occupied() ^ king_from.bitboard() ^ rook_from.bitboard();
+        if blockers & (back_rank_span(king_from, to) | back_rank_span(rook_from, rook_to)) != 0 {
             return false;
         }
-        if self.sq_attacked(next_sq, self.side.flip()) {
-            return false;
+
+        let them = self.side.flip();
+        let mut sq = king_from;
+        while sq != to {
+            if self.sq_attacked(sq, them) {
+                return false;
+            }
+            sq = if to > sq { sq.add(1) } else { sq.sub(1) };
         }
 
         true
@@ -754,6 +868,9 @@ impl Board {
     /// Gets the piece that will be captured by the given move.
     pub fn captured_piece(&self, m: Move) -> Piece {
         debug_assert!(m.to().on_board());
+        if m.is_castle() {
+            return Piece::EMPTY;
+        }
         unsafe { *self.piece_array.get_unchecked(m.to().index()) }
     }
 
@@ -820,15 +937,15 @@ impl Board {
                 self.clear_piece(to.add(8));
             }
         } else if m.is_castle() {
-            match to {
-                Square::C1 => self.move_piece(Square::A1, Square::D1),
-                Square::C8 => self.move_piece(Square::A8, Square::D8),
-                Square::G1 => self.move_piece(Square::H1, Square::F1),
-                Square::G8 => self.move_piece(Square::H8, Square::F8),
-                _ => {
-                    panic!("Invalid castle move");
-                }
+            // the king and rook may land on each other's squares in Chess960,
+            // so the rook is lifted off the board while the king moves.
+            let (rook_from, rook_to) = self.castling_rook_move(to);
+            let rook = self.piece_at(rook_from);
+            self.clear_piece(rook_from);
+            if from != to {
+                self.move_piece(from, to);
             }
+            self.add_piece(rook_to, rook);
         }
 
         if self.ep_sq != Square::NO_SQUARE {
@@ -847,8 +964,8 @@ impl Board {
         });
         self.repetition_cache.push(saved_key);
 
-        self.castle_perm &= unsafe { *CASTLE_PERM_MASKS.get_unchecked(from.index()) };
-        self.castle_perm &= unsafe { *CASTLE_PERM_MASKS.get_unchecked(to.index()) };
+        self.castle_perm &= unsafe { *self.castle_perm_masks.get_unchecked(from.index()) };
+        self.castle_perm &= unsafe { *self.castle_perm_masks.get_unchecked(to.index()) };
         self.ep_sq = Square::NO_SQUARE;
 
         // reinsert the castling rights
@@ -883,7 +1000,7 @@ impl Board {
             debug_assert!(promo.piece_type().legal_promo());
             self.clear_piece(from);
             self.add_piece(to, promo);
-        } else {
+        } else if !m.is_castle() {
             self.move_piece(from, to);
         }
 
@@ -943,15 +1060,13 @@ impl Board {
                 self.add_piece(to.add(8), Piece::WP);
             }
         } else if m.is_castle() {
-            match to {
-                Square::C1 => self.move_piece(Square::D1, Square::A1),
-                Square::C8 => self.move_piece(Square::D8, Square::A8),
-                Square::G1 => self.move_piece(Square::F1, Square::H1),
-                Square::G8 => self.move_piece(Square::F8, Square::H8),
-                _ => {
-                    panic!("Invalid castle move");
-                }
+            let (rook_from, rook_to) = self.castling_rook_move(to);
+            let rook = self.piece_at(rook_to);
+            self.clear_piece(rook_to);
+            if from != to {
+                self.move_piece(to, from);
             }
+            self.add_piece(rook_from, rook);
         }
 
         if m.is_promo() {
@@ -960,7 +1075,7 @@ impl Board {
             debug_assert_eq!(promotion.colour(), self.piece_at(to).colour());
             self.clear_piece(to);
             self.add_piece(from, if self.side == Colour::WHITE { Piece::WP } else { Piece::BP });
-        } else {
+        } else if !m.is_castle() {
             self.move_piece(to, from);
         }
 
@@ -1051,42 +1166,9 @@ impl Board {
             let ep_sq = if colour == Colour::WHITE { to.sub(8) } else { to.add(8) };
             t.nnue.efficiently_update_manual::<Deactivate>(PieceType::PAWN, colour.flip(), ep_sq);
         } else if m.is_castle() {
-            match to {
-                Square::C1 => {
-                    t.nnue.efficiently_update_from_move(
-                        PieceType::ROOK,
-                        colour,
-                        Square::A1,
-                        Square::D1,
-                    );
-                }
-                Square::C8 => {
-                    t.nnue.efficiently_update_from_move(
-                        PieceType::ROOK,
-                        colour,
-                        Square::A8,
-                        Square::D8,
-                    );
-                }
-                Square::G1 => {
-                    t.nnue.efficiently_update_from_move(
-                        PieceType::ROOK,
-                        colour,
-                        Square::H1,
-                        Square::F1,
-                    );
-                }
-                Square::G8 => {
-                    t.nnue.efficiently_update_from_move(
-                        PieceType::ROOK,
-                        colour,
-                        Square::H8,
-                        Square::F8,
-                    );
-                }
-                _ => {
-                    panic!("Invalid castle move");
-                }
+            let (rook_from, rook_to) = self.castling_rook_move(to);
+            if rook_from != rook_to {
+                t.nnue.efficiently_update_from_move(PieceType::ROOK, colour, rook_from, rook_to);
             }
         }
 
@@ -1099,7 +1181,7 @@ impl Board {
             debug_assert!(promo.legal_promo());
             t.nnue.efficiently_update_manual::<Deactivate>(PieceType::PAWN, colour, from);
             t.nnue.efficiently_update_manual::<Activate>(promo, colour, to);
-        } else {
+        } else if from != to {
             t.nnue.efficiently_update_from_move(piece_type, colour, from, to);
         }
 
@@ -1122,23 +1204,8 @@ impl Board {
             // deactivate pawn on ep_sq
             self.deactivate_psqt(info, PieceType::PAWN, colour.flip(), ep_sq);
         } else if m.is_castle() {
-            match to {
-                Square::C1 => {
-                    self.move_psqt(info, PieceType::ROOK, colour, Square::A1, Square::D1);
-                }
-                Square::C8 => {
-                    self.move_psqt(info, PieceType::ROOK, colour, Square::A8, Square::D8);
-                }
-                Square::G1 => {
-                    self.move_psqt(info, PieceType::ROOK, colour, Square::H1, Square::F1);
-                }
-                Square::G8 => {
-                    self.move_psqt(info, PieceType::ROOK, colour, Square::H8, Square::F8);
-                }
-                _ => {
-                    panic!("Invalid castle move");
-                }
-            }
+            let (rook_from, rook_to) = self.castling_rook_move(to);
+            self.move_psqt(info, PieceType::ROOK, colour, rook_from, rook_to);
         }
 
         if capture != Piece::EMPTY {
@@ -1174,14 +1241,8 @@ impl Board {
                 let ep_sq = if colour == Colour::WHITE { to.sub(8) } else { to.add(8) };
                 t.nnue.update_pov_manual::<Activate>(PieceType::PAWN, colour.flip(), ep_sq);
             } else if m.is_castle() {
-                let (rook_from, rook_to) = match to {
-                    Square::C1 => (Square::D1, Square::A1),
-                    Square::G8 => (Square::F8, Square::H8),
-                    Square::C8 => (Square::D8, Square::A8),
-                    Square::G1 => (Square::F1, Square::H1),
-                    _ => panic!("Invalid castle move"),
-                };
-                t.nnue.update_pov_move(PieceType::ROOK, colour, rook_from, rook_to);
+                let (rook_from, rook_to) = self.castling_rook_move(to);
+                t.nnue.update_pov_move(PieceType::ROOK, colour, rook_to, rook_from);
             }
             if m.is_promo() {
                 let promo = m.promotion_type();
@@ -1210,14 +1271,8 @@ impl Board {
             let ep_sq = if colour == Colour::WHITE { to.sub(8) } else { to.add(8) };
             self.activate_psqt(info, PieceType::PAWN, colour.flip(), ep_sq);
         } else if m.is_castle() {
-            let (rook_from, rook_to) = match to {
-                Square::C1 => (Square::D1, Square::A1),
-                Square::G8 => (Square::F8, Square::H8),
-                Square::C8 => (Square::D8, Square::A8),
-                Square::G1 => (Square::F1, Square::H1),
-                _ => panic!("Invalid castle move"),
-            };
-            self.move_psqt(info, PieceType::ROOK, colour, rook_from, rook_to);
+            let (rook_from, rook_to) = self.castling_rook_move(to);
+            self.move_psqt(info, PieceType::ROOK, colour, rook_to, rook_from);
         }
         if m.is_promo() {
             let promo = m.promotion_type();
@@ -1257,7 +1312,7 @@ impl Board {
 
     pub fn make_random_move<const NNUE: bool>(
         &mut self,
-        rng: &mut ThreadRng,
+        rng: &mut impl Rng,
         t: &mut ThreadData,
         info: &SearchInfo,
     ) -> Option<Move> {
@@ -1315,7 +1370,7 @@ impl Board {
             .copied()
             .find(|&m| {
                 m.from() == from
-                    && m.to() == to
+                    && self.uci_target_matches(m, to)
                     && (san_bytes.len() == 4
                         || m.safe_promotion_type().promo_char().unwrap() == san_bytes[4] as char)
             })
@@ -1324,6 +1379,17 @@ impl Board {
         res
     }
 
+    /// Whether `to` names the destination of `m` in UCI notation.
+    /// Castling may also be written as the king capturing its own rook, which is the only
+    /// unambiguous form when the king moves by less than two squares in Chess960.
+    fn uci_target_matches(&self, m: Move, to: Square) -> bool {
+        if !m.is_castle() {
+            return m.to() == to;
+        }
+        (m.to() == to && Square::distance(m.from(), to) > 1)
+            || self.castling_rook_move(m.to()).0 == to
+    }
+
     #[allow(clippy::too_many_lines)]
     pub fn parse_san(&mut self, san: &str) -> Result<Move, MoveParseError> {
         use crate::errors::MoveParseError::{AmbiguousSAN, IllegalMove, InvalidSAN};
@@ -1409,7 +1475,8 @@ impl Board {
 
         let mut legal_move = None;
         for &m in ml.iter() {
-            if !self.make_move_base(m) {
+            // castling is only ever written as O-O or O-O-O.
+            if m.is_castle() || !self.make_move_base(m) {
                 continue;
             }
             self.unmake_move_base();
@@ -1554,6 +1621,26 @@ impl Board {
         self.num(Piece::new(Colour::WHITE, pt)) + self.num(Piece::new(Colour::BLACK, pt))
     }
 
+    /// Writes `m` in UCI notation.
+    /// Chess960 castles are written as the king taking its own rook, as the king's destination
+    /// alone can be ambiguous in those arrangements, or even be the square it starts on.
+    pub fn uci_move(&self, m: Move) -> String {
+        if m.is_castle() {
+            let (rook_from, _) = self.castling_rook_move(m.to());
+            if !matches!(m.from(), Square::E1 | Square::E8)
+                || !matches!(rook_from, Square::A1 | Square::H1 | Square::A8 | Square::H8)
+            {
+                return format!("{}{rook_from}", m.from());
+            }
+        }
+        m.to_string()
+    }
+
+    /// Writes a principal variation from this position in UCI notation.
+    pub fn pv_uci(&self, pv: &PVariation) -> String {
+        pv.moves().iter().map(|&m| self.uci_move(m)).collect::<Vec<_>>().join(" ")
+    }
+
     pub fn pv_san(&mut self, pv: &PVariation) -> Result<String, fmt::Error> {
         let mut out = String::new();
         let mut moves_made = 0;
@@ -1702,11 +1789,30 @@ impl Board {
         if self.castle_perm == 0 {
             bytes_written += f.write(b"-")?;
         } else {
-            bytes_written += [WKCA, WQCA, BKCA, BQCA]
-                .into_iter()
-                .zip(b"KQkq")
-                .filter(|(m, _)| self.castle_perm & m != 0)
-                .try_fold(0, |acc, (_, &ch)| f.write(&[ch]).map(|n| acc + n))?;
+            // X-FEN: the outermost rook on each wing keeps the standard letter,
+            // any other rook is named by its file.
+            for (i, (right, standard)) in
+                [WKCA, WQCA, BKCA, BQCA].into_iter().zip(b"KQkq").enumerate()
+            {
+                if self.castle_perm & right == 0 {
+                    continue;
+                }
+                let rook_sq = self.castling_rooks[i];
+                let colour = if right & (WKCA | WQCA) != 0 { Colour::WHITE } else { Colour::BLACK };
+                let kingside = right & (WKCA | BKCA) != 0;
+                let ch = if self
+                    .back_rank_king(colour)
+                    .and_then(|k| self.outermost_rook(colour, k, kingside))
+                    == Some(rook_sq)
+                {
+                    *standard
+                } else if colour == Colour::WHITE {
+                    b'A' + rook_sq.file()
+                } else {
+                    b'a' + rook_sq.file()
+                };
+                bytes_written += f.write(&[ch])?;
+            }
         }
         bytes_written += f.write(b" ")?;
         if self.ep_sq == Square::NO_SQUARE {
@@ -1774,6 +1880,21 @@ impl GameOutcome {
             Self::Ongoing => panic!("Game is not over!"),
         }
     }
+
+    /// The outcome as a marlinformat WDL byte: 2 for a white win, 1 for a draw, 0 for a black win.
+    pub const fn as_packed_wdl(self) -> u8 {
+        match self {
+            Self::WhiteWinMate | Self::WhiteWinTB | Self::WhiteWinAdjudication => 2,
+            Self::BlackWinMate | Self::BlackWinTB | Self::BlackWinAdjudication => 0,
+            Self::DrawFiftyMoves
+            | Self::DrawRepetition
+            | Self::DrawStalemate
+            | Self::DrawInsufficientMaterial
+            | Self::DrawTB
+            | Self::DrawAdjudication => 1,
+            Self::Ongoing => panic!("Game is not over!"),
+        }
+    }
 }
 
 impl Default for Board {
@@ -1880,6 +2001,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn chess960_castling() {
+        use super::Board;
+        use crate::{chessmove::Move, definitions::Square};
+        crate::magic::initialise();
+
+        // shredder-FEN rights are read, and written back in X-FEN.
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/RR3K1R w HBha - 0 1").unwrap();
+        assert_eq!(board.fen(), "r3k2r/8/8/8/8/8/8/RR3K1R w KBkq - 0 1");
+        assert_eq!(Board::from_fen(&board.fen()).unwrap(), board);
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w H - 0 1").is_err());
+
+        // a one-square castle is written as the king taking its rook.
+        let king_move = board.parse_uci("f1g1").unwrap();
+        assert_eq!(king_move, Move::new(Square::F1, Square::G1));
+        let castle = board.parse_uci("f1h1").unwrap();
+        assert!(castle.is_kingside_castling());
+        assert_eq!(board.uci_move(castle), "f1h1");
+        assert_eq!(board.parse_san("O-O").unwrap(), castle);
+        assert_eq!(board.parse_san("Kg1").unwrap(), king_move);
+        assert!(board.make_move_base(castle));
+        assert_eq!(board.fen(), "r3k2r/8/8/8/8/8/8/RR3RK1 b kq - 1 1");
+        board.unmake_move_base();
+
+        // queenside, the king crosses to c1 and the b1 rook lands on d1.
+        let castle = board.parse_uci("f1b1").unwrap();
+        assert!(castle.is_queenside_castling());
+        assert_eq!(board.uci_move(castle), "f1b1");
+        assert_eq!(board.parse_uci("f1c1").unwrap(), castle);
+        assert!(board.make_move_base(castle));
+        assert_eq!(board.fen(), "r3k2r/8/8/8/8/8/8/R1KR3R b kq - 1 1");
+
+        // standard castles keep the usual notation.
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(board.uci_move(board.parse_uci("e1h1").unwrap()), "e1g1");
+    }
+
     #[test]
     fn test_num_pt() {
         use super::Board;
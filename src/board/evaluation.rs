@@ -213,12 +213,14 @@ impl Board {
         self.material[Colour::WHITE.index()] - self.material[Colour::BLACK.index()]
     }
 
-    pub fn evaluate_nnue(&self, t: &ThreadData, nodes: u64) -> i32 {
+    pub fn evaluate_nnue(&self, t: &mut ThreadData, nodes: u64) -> i32 {
         if !self.pieces.any_pawns() && self.is_material_draw() {
             return if self.side == Colour::WHITE { draw_score(nodes) } else { -draw_score(nodes) };
         }
 
-        let v = t.nnue.evaluate(self.side);
+        t.nnue.materialise(self);
+        debug_assert!(t.nnue.check_against_refresh(self), "lazy accumulator diverged on {self}");
+        let v = t.nnue.evaluate(self.side, self.pieces.occupied().count_ones());
 
         v * (100 - i32::from(self.fifty_move_counter)) / 100
     }
@@ -226,7 +228,7 @@ impl Board {
     pub fn evaluate<const USE_NNUE: bool>(
         &self,
         i: &SearchInfo,
-        t: &ThreadData,
+        t: &mut ThreadData,
         nodes: u64,
     ) -> i32 {
         if USE_NNUE {
@@ -376,60 +376,18 @@ impl Board {
     }
 
     fn generate_castling_moves_for<const IS_WHITE: bool>(&self, move_list: &mut MoveList) {
-        const WK_FREESPACE: u64 = Square::F1.bitboard() | Square::G1.bitboard();
-        const WQ_FREESPACE: u64 =
-            Square::B1.bitboard() | Square::C1.bitboard() | Square::D1.bitboard();
-        const BK_FREESPACE: u64 = Square::F8.bitboard() | Square::G8.bitboard();
-        const BQ_FREESPACE: u64 =
-            Square::B8.bitboard() | Square::C8.bitboard() | Square::D8.bitboard();
-        let occupied = self.pieces.occupied();
-        if IS_WHITE {
-            if self.castle_perm & WKCA != 0
-                && occupied & WK_FREESPACE == 0
-                && !self.sq_attacked_by::<false>(Square::E1)
-                && !self.sq_attacked_by::<false>(Square::F1)
-            {
-                move_list.push::<false>(Move::new_with_flags(
-                    Square::E1,
-                    Square::G1,
-                    Move::CASTLE_FLAG,
-                ));
-            }
-
-            if self.castle_perm & WQCA != 0
-                && occupied & WQ_FREESPACE == 0
-                && !self.sq_attacked_by::<false>(Square::E1)
-                && !self.sq_attacked_by::<false>(Square::D1)
-            {
-                move_list.push::<false>(Move::new_with_flags(
-                    Square::E1,
-                    Square::C1,
-                    Move::CASTLE_FLAG,
-                ));
-            }
+        let (rights, targets) = if IS_WHITE {
+            (WKCA | WQCA, [Square::G1, Square::C1])
         } else {
-            if self.castle_perm & BKCA != 0
-                && occupied & BK_FREESPACE == 0
-                && !self.sq_attacked_by::<true>(Square::E8)
-                && !self.sq_attacked_by::<true>(Square::F8)
-            {
-                move_list.push::<false>(Move::new_with_flags(
-                    Square::E8,
-                    Square::G8,
-                    Move::CASTLE_FLAG,
-                ));
-            }
-
-            if self.castle_perm & BQCA != 0
-                && occupied & BQ_FREESPACE == 0
-                && !self.sq_attacked_by::<true>(Square::E8)
-                && !self.sq_attacked_by::<true>(Square::D8)
-            {
-                move_list.push::<false>(Move::new_with_flags(
-                    Square::E8,
-                    Square::C8,
-                    Move::CASTLE_FLAG,
-                ));
+            (BKCA | BQCA, [Square::G8, Square::C8])
+        };
+        if self.castle_perm & rights == 0 {
+            return;
+        }
+        let king_sq = self.king_sq(self.side);
+        for to in targets {
+            if self.is_pseudo_legal_castling(to) {
+                move_list.push::<false>(Move::new_with_flags(king_sq, to, Move::CASTLE_FLAG));
             }
         }
     }
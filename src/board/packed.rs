@@ -0,0 +1,248 @@
+use crate::{
+    definitions::{Square, BKCA, BQCA, WKCA, WQCA},
+    errors::PackedBoardError,
+    piece::{Colour, Piece, PieceType},
+};
+
+use super::{movegen::bitboards::BitLoop, Board};
+
+/// Nibble used to mark a rook that still carries castling rights.
+const UNMOVED_ROOK: u8 = 6;
+/// Bit set in a piece nibble when the piece is black.
+const BLACK_BIT: u8 = 1 << 3;
+/// Bit set in `stm_ep_square` when black is to move.
+const BLACK_TO_MOVE: u8 = 1 << 7;
+
+/// A fixed-size binary encoding of a position.
+///
+/// The layout matches the board part of marlinformat: an occupancy bitboard,
+/// one nibble per occupied square (in square order), the side to move packed
+/// together with the en passant square, and the two move counters.
+/// Castling rights are stored by marking the relevant rooks as unmoved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedBoard {
+    occupancy: u64,
+    pieces: u128,
+    stm_ep_square: u8,
+    halfmove_clock: u8,
+    fullmove_number: u16,
+}
+
+impl PackedBoard {
+    /// The size of a packed board in bytes.
+    pub const SIZE: usize = 28;
+
+    /// Packs a board into its compact representation.
+    pub fn pack(board: &Board) -> Self {
+        #![allow(clippy::cast_possible_truncation)]
+        let occupancy = board.pieces.occupied();
+        debug_assert!(occupancy.count_ones() <= 32, "cannot pack more than 32 pieces");
+
+        let mut pieces = 0u128;
+        for (i, sq) in BitLoop::new(occupancy).enumerate() {
+            let piece = board.piece_at(sq);
+            let mut code = if piece.piece_type() == PieceType::ROOK
+                && [WKCA, WQCA, BKCA, BQCA]
+                    .into_iter()
+                    .zip(board.castling_rooks)
+                    .any(|(right, rook_sq)| board.castle_perm & right != 0 && rook_sq == sq)
+            {
+                UNMOVED_ROOK
+            } else {
+                piece.piece_type().inner() - 1
+            };
+            if piece.colour() == Colour::BLACK {
+                code |= BLACK_BIT;
+            }
+            pieces |= u128::from(code) << (4 * i);
+        }
+
+        let ep = if board.ep_sq == Square::NO_SQUARE { 64 } else { board.ep_sq.index() as u8 };
+        let stm = if board.side == Colour::BLACK { BLACK_TO_MOVE } else { 0 };
+
+        Self {
+            occupancy,
+            pieces,
+            stm_ep_square: stm | ep,
+            halfmove_clock: board.fifty_move_counter,
+            fullmove_number: (board.ply / 2 + 1) as u16,
+        }
+    }
+
+    /// Reconstructs the board that this packed representation describes.
+    pub fn unpack(&self) -> Result<Board, PackedBoardError> {
+        #![allow(clippy::cast_possible_truncation)]
+        let n_pieces = self.occupancy.count_ones();
+        if n_pieces > 32 {
+            return Err(PackedBoardError::TooManyPieces(n_pieces));
+        }
+        if n_pieces < 32 && self.pieces >> (4 * n_pieces) != 0 {
+            return Err(PackedBoardError::TrailingPieceData);
+        }
+
+        let mut board = Board::new();
+        let mut unmoved_rooks = Vec::new();
+        for (i, sq) in BitLoop::new(self.occupancy).enumerate() {
+            let code = ((self.pieces >> (4 * i)) & 0xF) as u8;
+            let colour = if code & BLACK_BIT == 0 { Colour::WHITE } else { Colour::BLACK };
+            let piece_type = match code & !BLACK_BIT {
+                UNMOVED_ROOK => {
+                    unmoved_rooks.push((colour, sq));
+                    PieceType::ROOK
+                }
+                t @ 0..=5 => PieceType::new(t + 1),
+                _ => return Err(PackedBoardError::InvalidPieceCode(code)),
+            };
+            board.add_piece(sq, Piece::new(colour, piece_type));
+        }
+
+        // each unmoved rook castles towards its own side of the king.
+        for (colour, sq) in unmoved_rooks {
+            let king_sq = board
+                .back_rank_king(colour)
+                .filter(|king_sq| king_sq.rank() == sq.rank())
+                .ok_or_else(|| PackedBoardError::InvalidUnmovedRook(sq.to_string()))?;
+            let right = match (colour, sq > king_sq) {
+                (Colour::WHITE, true) => WKCA,
+                (Colour::WHITE, false) => WQCA,
+                (_, true) => BKCA,
+                (_, false) => BQCA,
+            };
+            if board.castle_perm & right != 0 {
+                return Err(PackedBoardError::InvalidUnmovedRook(sq.to_string()));
+            }
+            board.castle_perm |= right;
+            board.castling_rooks[right.trailing_zeros() as usize] = sq;
+        }
+        board.update_castle_perm_masks();
+
+        board.side =
+            if self.stm_ep_square & BLACK_TO_MOVE == 0 { Colour::WHITE } else { Colour::BLACK };
+        board.ep_sq = match self.stm_ep_square & !BLACK_TO_MOVE {
+            64 => Square::NO_SQUARE,
+            sq @ 0..=63 => Square::new(sq),
+            sq => return Err(PackedBoardError::InvalidEnPassantSquare(sq)),
+        };
+        board.fifty_move_counter = self.halfmove_clock;
+        if self.fullmove_number == 0 {
+            return Err(PackedBoardError::InvalidFullmoveNumber);
+        }
+        board.ply = (usize::from(self.fullmove_number) - 1) * 2;
+        if board.side == Colour::BLACK {
+            board.ply += 1;
+        }
+        board.key = board.generate_pos_key();
+
+        Ok(board)
+    }
+
+    /// Serialises the packed board into little-endian bytes.
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut out = [0; Self::SIZE];
+        out[0..8].copy_from_slice(&self.occupancy.to_le_bytes());
+        out[8..24].copy_from_slice(&self.pieces.to_le_bytes());
+        out[24] = self.stm_ep_square;
+        out[25] = self.halfmove_clock;
+        out[26..28].copy_from_slice(&self.fullmove_number.to_le_bytes());
+        out
+    }
+
+    /// Deserialises a packed board from little-endian bytes.
+    /// No validation is done here, that happens in `unpack`.
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            occupancy: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pieces: u128::from_le_bytes(bytes[8..24].try_into().unwrap()),
+            stm_ep_square: bytes[24],
+            halfmove_clock: bytes[25],
+            fullmove_number: u16::from_le_bytes(bytes[26..28].try_into().unwrap()),
+        }
+    }
+}
+
+impl From<&Board> for PackedBoard {
+    fn from(board: &Board) -> Self {
+        Self::pack(board)
+    }
+}
+
+impl TryFrom<PackedBoard> for Board {
+    type Error = PackedBoardError;
+
+    fn try_from(packed: PackedBoard) -> Result<Self, Self::Error> {
+        packed.unpack()
+    }
+}
+
+impl From<PackedBoard> for [u8; PackedBoard::SIZE] {
+    fn from(packed: PackedBoard) -> Self {
+        packed.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board::Board, errors::PackedBoardError};
+    use std::{
+        fs::File,
+        io::{BufRead, BufReader},
+    };
+
+    #[test]
+    fn packed_round_trip() {
+        crate::magic::initialise();
+        let fens = BufReader::new(File::open("epds/perftsuite.epd").unwrap())
+            .lines()
+            .map(|l| l.unwrap().split_once(';').unwrap().0.trim().to_owned())
+            .collect::<Vec<_>>();
+        for fen in fens {
+            let board = Board::from_fen(&fen).unwrap();
+            let packed = PackedBoard::from(&board);
+            let bytes = packed.to_bytes();
+            let unpacked = Board::try_from(PackedBoard::from_bytes(&bytes)).unwrap();
+            assert_eq!(unpacked.fen(), fen);
+            assert_eq!(unpacked.hashkey(), board.hashkey());
+        }
+    }
+
+    #[test]
+    fn packed_round_trip_counters() {
+        crate::magic::initialise();
+        for fen in [
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+            "8/8/5k2/8/8/2K5/8/8 b - - 99 250",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 3 17",
+            "1r2k1r1/8/8/8/8/8/8/R5KR w KQkq - 0 1",
+            "4k3/8/8/8/8/8/8/RR2K3 w B - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let unpacked = PackedBoard::pack(&board).unpack().unwrap();
+            assert_eq!(unpacked.fen(), fen);
+        }
+    }
+
+    #[test]
+    fn packed_rejects_garbage() {
+        let mut bytes = [0; PackedBoard::SIZE];
+        bytes[0] = 0b1_0001; // pieces on a1 and e1
+        bytes[8] = 0x50 | 7; // a white king on e1, and nibble 7 is not a piece
+        bytes[27] = 1;
+        assert_eq!(
+            PackedBoard::from_bytes(&bytes).unpack(),
+            Err(PackedBoardError::InvalidPieceCode(7))
+        );
+        bytes[8] = 0x50 | 6; // unmoved white rook on a1 is fine
+        assert!(PackedBoard::from_bytes(&bytes).unpack().is_ok());
+        bytes[8] = 6; // but not without a king to castle with
+        assert!(matches!(
+            PackedBoard::from_bytes(&bytes).unpack(),
+            Err(PackedBoardError::InvalidUnmovedRook(_))
+        ));
+        bytes[8] = 0x50 | 6 | 8; // unmoved black rook on a1 is not
+        assert!(matches!(
+            PackedBoard::from_bytes(&bytes).unpack(),
+            Err(PackedBoardError::InvalidUnmovedRook(_))
+        ));
+    }
+}
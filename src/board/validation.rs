@@ -2,21 +2,19 @@
 
 use crate::{
     board::evaluation::score::S,
-    definitions::{Rank, Square},
-    lookups::{PIECE_BIG, PIECE_MAJ},
+    definitions::{Rank, Square, BKCA, BQCA, WKCA, WQCA},
+    errors::PositionValidityError,
+    lookups::{PIECE_BIG, PIECE_MAJ, PIECE_MIN},
     nnue::network::NNUEState,
     piece::{Colour, Piece},
     piecesquaretable::pst_value,
     searchinfo::SearchInfo,
 };
 
-#[cfg(debug_assertions)]
-use crate::{errors::PositionValidityError, lookups::PIECE_MIN};
-
 use super::{evaluation::parameters::EvalParams, movegen::bitboards::BitLoop, Board};
 
 impl Board {
-    #[cfg(debug_assertions)]
+    /// Checks that the board's internal state is consistent, and that the position is one that can arise in a game.
     #[allow(clippy::cognitive_complexity, clippy::too_many_lines)]
     pub fn check_validity(&self) -> Result<(), PositionValidityError> {
         #![allow(clippy::similar_names, clippy::cast_possible_truncation)]
@@ -159,6 +157,22 @@ impl Board {
             ));
         }
 
+        // check that every castling right still has its rook
+        for (i, (right, rook)) in [WKCA, WQCA, BKCA, BQCA]
+            .into_iter()
+            .zip([Piece::WR, Piece::WR, Piece::BR, Piece::BR])
+            .enumerate()
+        {
+            if self.castle_perm & right != 0 && self.piece_at(self.castling_rooks[i]) != rook {
+                return Err(format!(
+                    "castling rook is corrupt: expected {:?} on {}, got {:?}",
+                    rook,
+                    self.castling_rooks[i],
+                    self.piece_at(self.castling_rooks[i])
+                ));
+            }
+        }
+
         Ok(())
     }
 
@@ -50,6 +50,16 @@ impl Move {
         Self { data: u16::from(from) | (u16::from(to) << Self::TO_SHIFT) }
     }
 
+    /// The raw 16-bit representation of the move.
+    pub const fn inner(self) -> u16 {
+        self.data
+    }
+
+    /// Reconstructs a move from its raw 16-bit representation.
+    pub const fn from_raw(data: u16) -> Self {
+        Self { data }
+    }
+
     pub const fn from(self) -> Square {
         Square::new((self.data & Self::SQ_MASK) as u8)
     }
@@ -34,7 +34,7 @@ pub struct Cli {
     /// Visualise the evaluation parameters
     #[clap(long)]
     pub visparams: bool,
-    /// Visualise the Piece-Square Tables
+    /// Visualise the Piece-Square Tables, printing them, and rendering them as an image to the output path if one is given
     #[clap(long)]
     pub vispsqt: bool,
     /// Path to an Extended Position Description file to run as a test suite.
@@ -64,24 +64,162 @@ pub struct Cli {
     /// Depth at which to do NNUE data generation.
     #[clap(long, value_name = "DEPTH", default_value = "8")]
     pub nnuedepth: i32,
+    /// How to score positions when converting or reanalysing NNUE data:
+    /// depth (a search to --nnuedepth), nodes (a search of --nnuenodes nodes), qsearch, or static (the evaluation alone).
+    #[clap(long, value_name = "MODE", default_value = "depth")]
+    pub nnuemode: String,
+    /// Number of nodes to search per position when scoring NNUE data with --nnuemode nodes.
+    #[clap(long, value_name = "NODES", default_value = "5000")]
+    pub nnuenodes: u64,
     /// Output path.
     #[clap(short, long, value_name = "PATH")]
     pub output: Option<std::path::PathBuf>,
     /// Do conversion from NNUE json to NNUE binary. Arg1 is the input path, arg2 is the output path. Due to clap's idiosyncrasies, this must be passed as two arguments, e.g. --jsontobin network.json --jsontobin binfolder
     #[clap(long)]
     pub jsontobin: Vec<std::path::PathBuf>,
+    /// Quantise an NNUE json network, report any clipped parameters or possible overflows, and compare its evaluations against the float network's over a data file. Arg1 is the network path, arg2 is the data path, e.g. --quantreport network.json --quantreport data.txt
+    #[clap(long)]
+    pub quantreport: Vec<std::path::PathBuf>,
+    /// Compare the evaluations of two NNUE networks over a data file. Arg1 and arg2 are the networks (each a json file, a directory of binary net files, or "embedded"), arg3 is the data path, e.g. --netdiff old.json --netdiff new.json --netdiff data.txt
+    #[clap(long)]
+    pub netdiff: Vec<std::path::PathBuf>,
+    /// Print the architecture and weight statistics of an NNUE network (a json file, a directory of binary net files, or "embedded"). A data path can be passed as a second argument to report neuron saturation over it, e.g. --netinfo network.json --netinfo data.txt
+    #[clap(long)]
+    pub netinfo: Vec<std::path::PathBuf>,
     /// Deduplicate an NNUE data file by removing duplicate positions.
     #[clap(long, value_name = "PATH")]
     pub dedup: Option<std::path::PathBuf>,
-    /// Merge and deduplicate two NNUE data files
+    /// Merge and deduplicate NNUE data files. Pass once for each file, e.g. --merge a.txt --merge b.txt --merge c.txt
     #[clap(long, value_name = "PATH")]
     pub merge: Vec<std::path::PathBuf>,
-    /// Visualise the NNUE.
+    /// Shuffle NNUE data files into a single file. Pass once for each file.
+    #[clap(long, value_name = "PATH")]
+    pub shuffle: Vec<std::path::PathBuf>,
+    /// When deduplicating or merging, treat positions that only differ in their move counters as duplicates.
+    #[clap(long)]
+    pub dedupposition: bool,
+    /// Memory in MB to use when deduplicating, merging, or shuffling data files. Larger files are spilled to disk.
+    #[clap(long, value_name = "MB", default_value = "1024")]
+    pub datamemory: usize,
+    /// Seed for shuffling data files - if omitted, a random seed is used.
+    #[clap(long, value_name = "SEED")]
+    pub shuffleseed: Option<u64>,
+    /// Convert a marlinflow-format text data file into marlinformat binary records.
+    #[clap(long, value_name = "PATH")]
+    pub txttobin: Option<std::path::PathBuf>,
+    /// Convert a file of marlinformat binary records into marlinflow-format text.
+    #[clap(long, value_name = "PATH")]
+    pub bintotxt: Option<std::path::PathBuf>,
+    /// Expand a file of datagen game records into marlinformat binary records.
+    #[clap(long, value_name = "PATH")]
+    pub expandgames: Option<std::path::PathBuf>,
+    /// Rescore a data file with Syzygy tablebases, relabelling every position within the tablebases.
+    /// Files with a .bin extension are treated as marlinformat binary records, and anything else as marlinflow-format text.
+    #[clap(long, value_name = "PATH")]
+    pub tbrescore: Option<std::path::PathBuf>,
+    /// Path to the Syzygy tablebases to use when rescoring data.
+    #[clap(long, value_name = "PATH")]
+    pub tbpath: Option<std::path::PathBuf>,
+    /// Score given to tablebase wins when rescoring data.
+    #[clap(long, value_name = "SCORE", default_value = "2000")]
+    pub tbwinscore: i32,
+    /// Label cursed wins and blessed losses as wins and losses when rescoring data, rather than as draws.
+    #[clap(long)]
+    pub tbcursedaswins: bool,
+    /// Validate a data file and report statistics about it.
+    /// The format is given by --dataformat, or taken from the file extension.
+    #[clap(long, value_name = "PATH")]
+    pub datastats: Option<std::path::PathBuf>,
+    /// Output the data file statistics as JSON rather than text.
+    #[clap(long)]
+    pub datastatsjson: bool,
+    /// Filter a data file, writing the records that pass every --keep predicate to the output path,
+    /// and the rest to the --rejected path.
+    #[clap(long, value_name = "PATH")]
+    pub filter: Option<std::path::PathBuf>,
+    /// A predicate that records must pass to be kept when filtering. Can be passed multiple times.
+    /// One of men:<RANGE>, material:<SIGNATURE>[,...], eval:<RANGE>, phase:<RANGE>, result:<white|draw|black>[,...], or quiet[:<DEPTH>],
+    /// optionally prefixed with ! to negate it. Ranges are inclusive, as in 3..6, ..-200, or 200..
+    #[clap(long, value_name = "PREDICATE")]
+    pub keep: Vec<String>,
+    /// Path for the records that are rejected when filtering.
+    #[clap(long, value_name = "PATH")]
+    pub rejected: Option<std::path::PathBuf>,
+    /// Format of the data files to filter, deduplicate, merge, shuffle, or report statistics on: texel, marlinflow, or marlinformat.
+    /// If omitted, files with a .bin extension are treated as marlinformat, and anything else as marlinflow.
+    #[clap(long, value_name = "FORMAT")]
+    pub dataformat: Option<String>,
+    /// Skip invalid records in data files, printing a summary of them at the end, rather than stopping at the first one.
+    #[clap(long = "skip-invalid", conflicts_with = "strict")]
+    pub skipinvalid: bool,
+    /// Stop at the first invalid record in a data file. This is the default.
+    #[clap(long)]
+    pub strict: bool,
+    /// Visualise the NNUE, writing an image of the input weights of each neuron to the output directory (by default, nnue-visualisations).
     #[clap(long)]
     pub visnnue: bool,
+    /// With --visnnue, tile every neuron into a single labelled atlas image, rather than writing an image per neuron.
+    #[clap(long)]
+    pub visnnueatlas: bool,
+    /// Train an NNUE on a marlinflow-format text data file, writing checkpoints and the final
+    /// network to the output directory (by default, nnue-training).
+    #[clap(long, value_name = "PATH")]
+    pub train: Option<std::path::PathBuf>,
+    /// Number of epochs to train for.
+    #[clap(long, value_name = "N_EPOCHS", default_value = "10")]
+    pub trainepochs: usize,
+    /// Number of positions in each training batch.
+    #[clap(long, value_name = "N_POSITIONS", default_value = "16384")]
+    pub trainbatch: usize,
+    /// Initial learning rate for training.
+    #[clap(long, value_name = "LR", default_value = "0.001")]
+    pub trainlr: f32,
+    /// Number of epochs between drops in the learning rate. Zero keeps the learning rate constant.
+    #[clap(long, value_name = "N_EPOCHS", default_value = "4")]
+    pub trainlrstep: usize,
+    /// Factor that the learning rate is multiplied by at each drop.
+    #[clap(long, value_name = "FACTOR", default_value = "0.1")]
+    pub trainlrgamma: f32,
+    /// Weight given to the game result in the training target, from 0.0 (eval only) to 1.0 (result only).
+    #[clap(long, value_name = "LAMBDA", default_value = "0.1")]
+    pub trainwdl: f32,
+    /// Scale in centipawns of the sigmoid that maps evals to expected scores when training.
+    #[clap(long, value_name = "CP", default_value = "400")]
+    pub trainscale: f32,
+    /// Fraction of the training data held out to compute the validation loss.
+    #[clap(long, value_name = "FRACTION", default_value = "0.01")]
+    pub trainvalidation: f32,
+    /// Number of threads to train with - if omitted, one per CPU is used.
+    #[clap(long, value_name = "N_THREADS")]
+    pub trainthreads: Option<usize>,
+    /// Checkpoint directory to resume training from.
+    #[clap(long, value_name = "PATH")]
+    pub trainresume: Option<std::path::PathBuf>,
+    /// Seed for initialising the network and shuffling the training data.
+    #[clap(long, value_name = "SEED", default_value = "0")]
+    pub trainseed: u64,
     /// Generate training data for the NNUE.
     #[clap(long)]
     pub datagen: Option<Option<String>>,
+    /// Resume an interrupted data generation run from its directory.
+    #[clap(long, value_name = "PATH")]
+    pub datagen_resume: Option<std::path::PathBuf>,
+    /// Coordinate a distributed data generation run, listening on HOST:PORT or unix:PATH.
+    /// The run is configured with --datagen, as for a local run.
+    #[clap(long, value_name = "ADDRESS")]
+    pub datagen_coordinator: Option<String>,
+    /// Generate data for the coordinator at HOST:PORT or unix:PATH.
+    #[clap(long, value_name = "ADDRESS")]
+    pub datagen_worker: Option<String>,
+    /// Number of games in each work unit handed out by a datagen coordinator.
+    #[clap(long, value_name = "N_GAMES", default_value = "64")]
+    pub datagen_unit_games: usize,
+    /// Seconds that a datagen coordinator waits for a work unit before reassigning it.
+    #[clap(long, value_name = "SECONDS", default_value = "3600")]
+    pub datagen_unit_timeout: u64,
+    /// Number of threads (each with its own connection) for a datagen worker.
+    #[clap(long, value_name = "N_THREADS", default_value = "1")]
+    pub datagen_worker_threads: usize,
     /// Output node benchmark for OpenBench.
     /// Implemented as a subcommand because that's what OpenBench expects.
     #[clap(subcommand)]
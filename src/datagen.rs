@@ -1,42 +1,195 @@
+mod distributed;
+pub mod gameformat;
+mod manifest;
+pub mod marlinformat;
+pub mod openings;
+
 use std::{
     cmp::Reverse,
     collections::HashMap,
     fmt::Display,
-    fs::File,
+    fs::OpenOptions,
     hash::Hash,
-    io::{BufWriter, Write},
+    io::{BufWriter, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     str::FromStr,
-    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
     time::Instant,
 };
 
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::{json, Value};
+
 use crate::{
     board::{
         evaluation::{is_game_theoretic_score, MINIMUM_MATE_SCORE},
+        packed::PackedBoard,
         Board, GameOutcome,
     },
     definitions::{depth::Depth, MEGABYTE},
     searchinfo::SearchInfo,
     tablebases::{self, probe::WDL},
     threadlocal::ThreadData,
-    timemgmt::{SearchLimit, TimeManager},
+    timemgmt::SearchLimit,
     transpositiontable::TT,
     uci::{SYZYGY_ENABLED, SYZYGY_PATH},
 };
 
+pub use self::distributed::{coordinator_main, worker_main, CoordinatorConfig};
+use self::{
+    gameformat::Game,
+    manifest::{RunManifest, ThreadProgress},
+    marlinformat::PackedRecord,
+    openings::{BookOrder, OpeningBook, StartPositions},
+};
+
 static FENS_GENERATED: AtomicU64 = AtomicU64::new(0);
 static STOP_GENERATION: AtomicBool = AtomicBool::new(false);
 
+/// Every way a datagen game can end.
+const GAME_OUTCOMES: [GameOutcome; 12] = [
+    GameOutcome::WhiteWinMate,
+    GameOutcome::BlackWinMate,
+    GameOutcome::WhiteWinTB,
+    GameOutcome::BlackWinTB,
+    GameOutcome::DrawFiftyMoves,
+    GameOutcome::DrawRepetition,
+    GameOutcome::DrawStalemate,
+    GameOutcome::DrawInsufficientMaterial,
+    GameOutcome::DrawTB,
+    GameOutcome::WhiteWinAdjudication,
+    GameOutcome::BlackWinAdjudication,
+    GameOutcome::DrawAdjudication,
+];
+
+/// How many games each thread plays between checkpoints of the run manifest.
+const CHECKPOINT_INTERVAL: usize = 16;
+
 /// Whether to limit searches by depth or by nodes.
 #[derive(Clone, Debug, Hash)]
 enum DataGenLimit {
     Depth(i32),
     Nodes(u64),
+    /// Stop after the first iteration that completes past `soft` nodes,
+    /// or part way through an iteration once `hard` nodes have been searched.
+    SoftNodes {
+        soft: u64,
+        hard: u64,
+    },
+}
+
+impl DataGenLimit {
+    /// The search limit to use for a game, with node limits scaled by a random factor
+    /// of up to `jitter` percent in either direction. Depth limits are never jittered.
+    fn search_limit(&self, jitter: u32, rng: &mut impl Rng) -> SearchLimit {
+        #![allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let factor = if jitter == 0 {
+            1.0
+        } else {
+            let jitter = f64::from(jitter.min(100)) / 100.0;
+            rng.gen_range(1.0 - jitter..=1.0 + jitter)
+        };
+        let scale = |nodes: u64| ((nodes as f64 * factor).round() as u64).max(1);
+        match *self {
+            Self::Depth(depth) => SearchLimit::Depth(Depth::new(depth)),
+            Self::Nodes(nodes) => SearchLimit::Nodes(scale(nodes)),
+            Self::SoftNodes { soft, hard } => {
+                SearchLimit::SoftNodes { soft_limit: scale(soft), hard_limit: scale(hard) }
+            }
+        }
+    }
+
+    /// The short form of the limit, as used in the short def string.
+    fn short_name(&self) -> String {
+        match self {
+            Self::Depth(depth) => format!("d{depth}"),
+            Self::Nodes(nodes) => format!("n{nodes}"),
+            Self::SoftNodes { soft, hard } => format!("s{soft}h{hard}"),
+        }
+    }
+
+    /// Parses the short form of the limit.
+    fn from_short_name(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid limit: {s}");
+        let parse_nodes = |n: &str| n.parse::<u64>().map_err(|_| invalid());
+        match s.split_at(s.len().min(1)) {
+            ("d", depth) => Ok(Self::Depth(depth.parse().map_err(|_| invalid())?)),
+            ("n", nodes) => Ok(Self::Nodes(parse_nodes(nodes)?)),
+            ("s", nodes) => {
+                let (soft, hard) = nodes.split_once('h').ok_or_else(invalid)?;
+                Self::soft_nodes(parse_nodes(soft)?, parse_nodes(hard)?)
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Creates a soft node limit, checking that the hard limit is at least the soft limit.
+    fn soft_nodes(soft: u64, hard: u64) -> Result<Self, String> {
+        if hard < soft {
+            return Err(format!(
+                "The hard node limit ({hard}) must be at least the soft node limit ({soft})"
+            ));
+        }
+        Ok(Self::SoftNodes { soft, hard })
+    }
+}
+
+/// The format in which generated positions are written to disk.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum OutputFormat {
+    /// `<FEN> | <EVAL> | <WDL>` text lines, as consumed by marlinflow.
+    Text,
+    /// Fixed-size marlinformat records, 32 bytes per position.
+    Binary,
+    /// Game records: the opening position, then every move with its score and a keep/skip flag.
+    Game,
+}
+
+/// An upper bound on the number of moves in a game. The fifty-move rule ends every game
+/// in under 6,000 moves, and game records can't hold more than `u16::MAX` moves anyway.
+const MAX_GAME_MOVES: usize = u16::MAX as usize;
+/// An upper bound on the length of a `<FEN> | <EVAL> | <WDL>` line of text output.
+const MAX_TEXT_RECORD_SIZE: usize = 128;
+
+impl OutputFormat {
+    /// The short name of the format, as used in the short def string.
+    const fn short_name(self) -> &'static str {
+        match self {
+            Self::Text => "txt",
+            Self::Binary => "bin",
+            Self::Game => "game",
+        }
+    }
+
+    /// The most bytes that a single game can take up in this format.
+    const fn max_game_size(self) -> usize {
+        match self {
+            Self::Text => MAX_GAME_MOVES * MAX_TEXT_RECORD_SIZE,
+            Self::Binary => MAX_GAME_MOVES * PackedRecord::SIZE,
+            Self::Game => Game::serialised_size(MAX_GAME_MOVES),
+        }
+    }
+
+    /// The file extension for data files in this format.
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Text => "txt",
+            Self::Binary => "bin",
+            Self::Game => "games",
+        }
+    }
 }
 
 /// Configuration options for Viri's self-play data generation.
 #[derive(Clone, Debug, Hash)]
+#[allow(clippy::struct_excessive_bools)]
 struct DataGenOptions {
     // The number of games to generate.
     num_games: usize,
@@ -48,6 +201,42 @@ struct DataGenOptions {
     use_nnue: bool,
     // The depth or node limit for searches.
     limit: DataGenLimit,
+    // The maximum percentage by which node limits are randomly scaled for each game.
+    limit_jitter: u32,
+    // The format of the output files.
+    output_format: OutputFormat,
+    // The (optional) path to an EPD or FEN file of starting positions.
+    book: Option<PathBuf>,
+    // How to pick positions from the opening book.
+    book_order: BookOrder,
+    // The kind of starting position to use when no opening book is given.
+    start_positions: StartPositions,
+    // The number of random moves to play from the starting position.
+    random_plies: usize,
+    // The number of random moves to play from opening book positions, used instead of
+    // `random_plies` when a book is given, so that curated openings are kept as they are by default.
+    book_random_plies: usize,
+    // The depth of the search used to check that the starting position is balanced.
+    // Zero disables the check.
+    verification_depth: i32,
+    // The largest absolute eval for a starting position to be used.
+    balance_threshold: i32,
+    // The number of consecutive plies with an absolute score of at least `win_adj_score`
+    // needed to adjudicate a game as won. Zero disables win adjudication.
+    win_adj_plies: u32,
+    // The absolute score at which a ply counts towards win adjudication.
+    win_adj_score: i32,
+    // The number of consecutive plies with an absolute score of at most `draw_adj_score`
+    // needed to adjudicate a game as drawn. Zero disables draw adjudication.
+    draw_adj_plies: u32,
+    // The absolute score at which a ply counts towards draw adjudication.
+    draw_adj_score: i32,
+    // Whether to skip positions where the best move is tactical (a capture or promotion).
+    filter_tactical: bool,
+    // Whether to skip positions where the side to move is in check.
+    filter_in_check: bool,
+    // Whether to skip positions with a game-theoretic (mate or TB-win) score.
+    filter_game_theoretic: bool,
     // log level
     log_level: u8,
 }
@@ -61,6 +250,22 @@ impl DataGenOptions {
             tablebases_path: None,
             use_nnue: true,
             limit: DataGenLimit::Depth(8),
+            limit_jitter: 0,
+            output_format: OutputFormat::Text,
+            book: None,
+            book_order: BookOrder::Random,
+            start_positions: StartPositions::Standard,
+            random_plies: 12,
+            book_random_plies: 0,
+            verification_depth: 10,
+            balance_threshold: 1000,
+            win_adj_plies: 4,
+            win_adj_score: 2000,
+            draw_adj_plies: 12,
+            draw_adj_score: 4,
+            filter_tactical: true,
+            filter_in_check: true,
+            filter_game_theoretic: true,
             log_level: 1,
         }
     }
@@ -68,7 +273,7 @@ impl DataGenOptions {
     /// Gives a summarised string representation of the options.
     fn summary(&self) -> String {
         format!(
-            "{}g-{}t-{}-{}-{}",
+            "{}g-{}t-{}-{}-{}-{}-{}",
             self.num_games,
             self.num_threads,
             self.tablebases_path.as_ref().map_or_else(
@@ -76,27 +281,257 @@ impl DataGenOptions {
                 |tablebases_path| tablebases_path.to_string_lossy()
             ),
             if self.use_nnue { "nnue" } else { "hce" },
-            match self.limit {
-                DataGenLimit::Depth(depth) => format!("d{depth}"),
-                DataGenLimit::Nodes(nodes) => format!("n{nodes}"),
-            }
+            if self.limit_jitter == 0 {
+                self.limit.short_name()
+            } else {
+                format!("{}j{}", self.limit.short_name(), self.limit_jitter)
+            },
+            self.output_format.short_name(),
+            self.start_positions.short_name()
         )
     }
+
+    /// Serialises the options into a JSON object.
+    fn to_json(&self) -> Value {
+        json!({
+            "num_games": self.num_games,
+            "num_threads": self.num_threads,
+            "tablebases_path": self.tablebases_path,
+            "use_nnue": self.use_nnue,
+            "limit": self.limit.to_string(),
+            "limit_jitter": self.limit_jitter,
+            "output_format": self.output_format.to_string(),
+            "book": self.book,
+            "book_order": self.book_order.to_string(),
+            "start_positions": self.start_positions.to_string(),
+            "random_plies": self.random_plies,
+            "book_random_plies": self.book_random_plies,
+            "verification_depth": self.verification_depth,
+            "balance_threshold": self.balance_threshold,
+            "win_adj_plies": self.win_adj_plies,
+            "win_adj_score": self.win_adj_score,
+            "draw_adj_plies": self.draw_adj_plies,
+            "draw_adj_score": self.draw_adj_score,
+            "filter_tactical": self.filter_tactical,
+            "filter_in_check": self.filter_in_check,
+            "filter_game_theoretic": self.filter_game_theoretic,
+            "log_level": self.log_level,
+        })
+    }
+
+    /// Deserialises options from a JSON object.
+    /// Any options that are missing from the object keep their default values.
+    fn from_json(json: &Value) -> Result<Self, String> {
+        #![allow(clippy::too_many_lines)]
+        fn field<T>(
+            json: &Value,
+            key: &str,
+            parse: impl FnOnce(&Value) -> Option<T>,
+        ) -> Result<Option<T>, String> {
+            json.get(key)
+                .map(|value| {
+                    parse(value).ok_or_else(|| format!("Invalid value for {key}: {value}"))
+                })
+                .transpose()
+        }
+        fn parsed<T: FromStr<Err = String>>(json: &Value, key: &str) -> Result<Option<T>, String> {
+            json.get(key)
+                .map(|value| {
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("Invalid value for {key}: {value}"))?
+                        .parse()
+                })
+                .transpose()
+        }
+        fn path(json: &Value, key: &str) -> Result<Option<PathBuf>, String> {
+            match json.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(value) => value
+                    .as_str()
+                    .map(|path| Some(PathBuf::from(path)))
+                    .ok_or_else(|| format!("Invalid value for {key}: {value}")),
+            }
+        }
+        let as_usize = |value: &Value| value.as_u64().and_then(|n| usize::try_from(n).ok());
+        let as_i32 = |value: &Value| value.as_i64().and_then(|n| i32::try_from(n).ok());
+        let as_unsigned = |value: &Value| value.as_u64().and_then(|n| u32::try_from(n).ok());
+
+        if !json.is_object() {
+            return Err(format!("Expected datagen options to be a JSON object, got {json}"));
+        }
+        let mut options = Self::new();
+        if let Some(num_games) = field(json, "num_games", as_usize)? {
+            options.num_games = num_games;
+        }
+        if let Some(num_threads) = field(json, "num_threads", as_usize)? {
+            options.num_threads = num_threads;
+        }
+        options.tablebases_path = path(json, "tablebases_path")?;
+        if let Some(use_nnue) = field(json, "use_nnue", Value::as_bool)? {
+            options.use_nnue = use_nnue;
+        }
+        if let Some(limit) = parsed(json, "limit")? {
+            options.limit = limit;
+        }
+        if let Some(limit_jitter) = field(json, "limit_jitter", as_unsigned)? {
+            options.limit_jitter = limit_jitter;
+        }
+        if let Some(output_format) = parsed(json, "output_format")? {
+            options.output_format = output_format;
+        }
+        options.book = path(json, "book")?;
+        if let Some(book_order) = parsed(json, "book_order")? {
+            options.book_order = book_order;
+        }
+        if let Some(start_positions) = parsed(json, "start_positions")? {
+            options.start_positions = start_positions;
+        }
+        if let Some(random_plies) = field(json, "random_plies", as_usize)? {
+            options.random_plies = random_plies;
+        }
+        if let Some(book_random_plies) = field(json, "book_random_plies", as_usize)? {
+            options.book_random_plies = book_random_plies;
+        }
+        if let Some(verification_depth) = field(json, "verification_depth", as_i32)? {
+            options.verification_depth = verification_depth;
+        }
+        if let Some(balance_threshold) = field(json, "balance_threshold", as_i32)? {
+            options.balance_threshold = balance_threshold;
+        }
+        if let Some(win_adj_plies) = field(json, "win_adj_plies", as_unsigned)? {
+            options.win_adj_plies = win_adj_plies;
+        }
+        if let Some(win_adj_score) = field(json, "win_adj_score", as_i32)? {
+            options.win_adj_score = win_adj_score;
+        }
+        if let Some(draw_adj_plies) = field(json, "draw_adj_plies", as_unsigned)? {
+            options.draw_adj_plies = draw_adj_plies;
+        }
+        if let Some(draw_adj_score) = field(json, "draw_adj_score", as_i32)? {
+            options.draw_adj_score = draw_adj_score;
+        }
+        if let Some(filter_tactical) = field(json, "filter_tactical", Value::as_bool)? {
+            options.filter_tactical = filter_tactical;
+        }
+        if let Some(filter_in_check) = field(json, "filter_in_check", Value::as_bool)? {
+            options.filter_in_check = filter_in_check;
+        }
+        if let Some(filter_game_theoretic) = field(json, "filter_game_theoretic", Value::as_bool)? {
+            options.filter_game_theoretic = filter_game_theoretic;
+        }
+        if let Some(log_level) =
+            field(json, "log_level", |value| value.as_u64().and_then(|n| u8::try_from(n).ok()))?
+        {
+            options.log_level = log_level;
+        }
+        if options.num_threads == 0 {
+            return Err("num_threads must be at least 1".into());
+        }
+        Ok(options)
+    }
+
+    /// Loads options from a JSON config file.
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read datagen config {}: {e}", path.display()))?;
+        let json = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse datagen config {}: {e}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    /// Saves the options to a JSON config file.
+    fn save_to_file(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(&mut file, &self.to_json())?;
+        writeln!(file)
+    }
 }
 
-pub fn gen_data_main(cli_config: Option<&str>) {
+/// Counts of the openings and positions that were rejected by each datagen filter.
+/// A position that fails several filters is counted against each of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct FilterCounters {
+    // Openings abandoned because a random move left no legal replies.
+    dead_end_openings: u64,
+    // Openings rejected by the verification search as too unbalanced.
+    unbalanced_openings: u64,
+    // Positions searched during self-play.
+    positions_seen: u64,
+    // Positions rejected because the best move was tactical.
+    tactical: u64,
+    // Positions rejected because the side to move was in check.
+    in_check: u64,
+    // Positions rejected because the score was game-theoretic.
+    game_theoretic: u64,
+    // Positions that passed every filter.
+    positions_kept: u64,
+}
+
+impl FilterCounters {
+    /// The counters, along with their names.
+    const fn fields(&self) -> [(&'static str, u64); 7] {
+        [
+            ("dead_end_openings", self.dead_end_openings),
+            ("unbalanced_openings", self.unbalanced_openings),
+            ("positions_seen", self.positions_seen),
+            ("tactical", self.tactical),
+            ("in_check", self.in_check),
+            ("game_theoretic", self.game_theoretic),
+            ("positions_kept", self.positions_kept),
+        ]
+    }
+
+    /// Mutable references to the counters, along with their names.
+    const fn fields_mut(&mut self) -> [(&'static str, &mut u64); 7] {
+        [
+            ("dead_end_openings", &mut self.dead_end_openings),
+            ("unbalanced_openings", &mut self.unbalanced_openings),
+            ("positions_seen", &mut self.positions_seen),
+            ("tactical", &mut self.tactical),
+            ("in_check", &mut self.in_check),
+            ("game_theoretic", &mut self.game_theoretic),
+            ("positions_kept", &mut self.positions_kept),
+        ]
+    }
+
+    /// Adds another set of counters into this one.
+    fn add(&mut self, other: &Self) {
+        for ((_, count), (_, other)) in self.fields_mut().into_iter().zip(other.fields()) {
+            *count += other;
+        }
+    }
+}
+
+fn set_up_stop_handler() {
     FENS_GENERATED.store(0, Ordering::SeqCst);
     ctrlc::set_handler(move || {
         STOP_GENERATION.store(true, Ordering::SeqCst);
         println!("Stopping generation, please don't force quit.");
     })
     .expect("Failed to set Ctrl-C handler");
+}
 
-    let options: DataGenOptions = cli_config.map_or_else(|| {
+/// Gets the options for a run, from a short def string or JSON config file if one
+/// was given on the command line, and interactively otherwise.
+fn get_options(cli_config: Option<&str>) -> DataGenOptions {
+    cli_config.map_or_else(|| {
             let options = DataGenOptions::new();
             show_boot_info(&options);
             config_loop(options)
-        }, |s| s.parse().expect("Failed to parse CLI config, expected short def string (e.g. '100g-2t-<TBPATH>-nnue-d8')"));
+        }, |s| {
+            if Path::new(s).extension().is_some_and(|ext| ext == "json") {
+                DataGenOptions::from_file(Path::new(s)).unwrap_or_else(|e| panic!("{e}"))
+            } else {
+                s.parse().expect("Failed to parse CLI config, expected short def string (e.g. '100g-2t-<TBPATH>-nnue-d8-bin') or a path to a JSON config file")
+            }
+        })
+}
+
+pub fn gen_data_main(cli_config: Option<&str>) {
+    set_up_stop_handler();
+
+    let options = get_options(cli_config);
     if options.log_level > 0 {
         println!("Starting data generation with the following configuration:");
         println!("{options}");
@@ -110,16 +545,6 @@ pub fn gen_data_main(cli_config: Option<&str>) {
             );
         }
     }
-    if let Some(tb_path) = &options.tablebases_path {
-        let tb_path = tb_path.to_string_lossy();
-        tablebases::probe::init(&tb_path);
-        *SYZYGY_PATH.lock().unwrap() = tb_path.to_string();
-        SYZYGY_ENABLED.store(true, Ordering::SeqCst);
-        if options.log_level > 0 {
-            println!("Syzygy tablebases enabled.");
-        }
-    }
-
     // create a new unique identifier for this generation run
     // this is used to create a unique directory for the data
     // and to name the data files.
@@ -136,17 +561,65 @@ pub fn gen_data_main(cli_config: Option<&str>) {
     let data_dir = PathBuf::from("data").join(run_id);
     std::fs::create_dir_all(&data_dir).expect("Failed to create data directory");
 
-    let mut counters = Vec::new();
+    let manifest = RunManifest::new(options, rand::random());
+    run_generation(&data_dir, manifest);
+}
+
+/// Continues an interrupted datagen run from the last checkpoint in its manifest.
+pub fn resume_data_main(data_dir: &Path) {
+    set_up_stop_handler();
+
+    let manifest = RunManifest::load(data_dir).unwrap_or_else(|e| panic!("{e}"));
+    manifest.check_outputs(data_dir).unwrap_or_else(|e| panic!("{e}"));
+    let options = &manifest.options;
+    if options.log_level > 0 {
+        println!(
+            "Resuming data generation in \"{}\" with the following configuration:",
+            data_dir.display()
+        );
+        println!("{options}");
+        let n_games_per_thread = std::cmp::max(options.num_games / options.num_threads, 1);
+        for (id, progress) in manifest.threads.iter().enumerate() {
+            println!(
+                " |> thread {id}: {}/{n_games_per_thread} games completed",
+                progress.games_completed
+            );
+        }
+    }
+
+    run_generation(data_dir, manifest);
+}
+
+/// Runs (or continues) the generation described by `manifest`, saving the data into `data_dir`.
+fn run_generation(data_dir: &Path, manifest: RunManifest) {
+    let options = manifest.options.clone();
+    let seed = manifest.seed;
+    set_up_tablebases(&options);
+    let book = load_book(&options).unwrap_or_else(|e| panic!("{e}"));
+
+    // save the manifest straight away, so that even a run that dies
+    // before its first checkpoint can be resumed.
+    manifest.save(data_dir).expect("Failed to write run manifest");
+    let manifest = Mutex::new(manifest);
+
+    let mut progress = Vec::new();
     std::thread::scope(|s| {
         let thread_handles = (0..options.num_threads)
             .map(|id| {
-                let opt_ref = &options;
-                let path_ref = &data_dir;
-                s.spawn(move || generate_on_thread(id, opt_ref, path_ref))
+                let ctx = ThreadContext {
+                    id,
+                    options: &options,
+                    seed,
+                    game_offset: 0,
+                    data_dir,
+                    book: book.as_ref(),
+                    manifest: &manifest,
+                };
+                s.spawn(move || generate_on_thread(&ctx))
             })
             .collect::<Vec<_>>();
         for handle in thread_handles {
-            counters.push(handle.join().unwrap());
+            progress.push(handle.join().unwrap());
         }
     });
 
@@ -154,16 +627,40 @@ pub fn gen_data_main(cli_config: Option<&str>) {
         println!("Done!");
     }
 
-    let counters = counters.into_iter().reduce(|mut a, b| {
-        for (key, value) in b {
-            *a.entry(key).or_insert(0) += value;
-        }
+    let progress = progress.into_iter().reduce(|mut a, b| {
+        a.merge(&b);
         a
     });
 
-    if let Some(counters) = counters {
-        print_game_stats(&counters);
+    if let Some(progress) = progress {
+        print_game_stats(&progress.counters);
+        print_filter_stats(&progress.filters);
+    }
+}
+
+/// Enables the tablebases given in the options, if any.
+fn set_up_tablebases(options: &DataGenOptions) {
+    if let Some(tb_path) = &options.tablebases_path {
+        let tb_path = tb_path.to_string_lossy();
+        tablebases::probe::init(&tb_path);
+        *SYZYGY_PATH.lock().unwrap() = tb_path.to_string();
+        SYZYGY_ENABLED.store(true, Ordering::SeqCst);
+        if options.log_level > 0 {
+            println!("Syzygy tablebases enabled.");
+        }
+    }
+}
+
+/// Loads the opening book given in the options, if any.
+fn load_book(options: &DataGenOptions) -> Result<Option<OpeningBook>, String> {
+    let Some(path) = &options.book else {
+        return Ok(None);
+    };
+    let book = OpeningBook::load(path)?;
+    if options.log_level > 0 {
+        println!("Loaded {} starting positions from the opening book.", book.num_positions());
     }
+    Ok(Some(book))
 }
 
 fn print_game_stats(counters: &HashMap<GameOutcome, u64>) {
@@ -180,69 +677,133 @@ fn print_game_stats(counters: &HashMap<GameOutcome, u64>) {
     }
 }
 
+fn print_filter_stats(filters: &FilterCounters) {
+    #![allow(clippy::cast_precision_loss)]
+    let percentage = |count: u64, total: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            (count as f64 / total as f64 * 1000.0).round() / 10.0
+        }
+    };
+    eprintln!("Filter stats:");
+    eprintln!(" |> openings with no legal moves: {}", filters.dead_end_openings);
+    eprintln!(" |> unbalanced openings: {}", filters.unbalanced_openings);
+    eprintln!(" |> positions seen: {}", filters.positions_seen);
+    for (name, count) in [
+        ("tactical best move", filters.tactical),
+        ("in check", filters.in_check),
+        ("game-theoretic score", filters.game_theoretic),
+    ] {
+        eprintln!(
+            " |> rejected ({name}): {count} ({}%)",
+            percentage(count, filters.positions_seen)
+        );
+    }
+    eprintln!(
+        " |> positions kept: {} ({}%)",
+        filters.positions_kept,
+        percentage(filters.positions_kept, filters.positions_seen)
+    );
+}
+
+/// The path of the data file that thread `id` writes into `data_dir`.
+fn thread_output_path(data_dir: &Path, id: usize, format: OutputFormat) -> PathBuf {
+    data_dir.join(format!("thread_{id}.{}", format.extension()))
+}
+
+/// Everything a datagen thread needs to know about the run it is part of.
+struct ThreadContext<'a> {
+    id: usize,
+    options: &'a DataGenOptions,
+    seed: u64,
+    // added to the index of every game, so that games in separate work units
+    // of a distributed run get different random number generators.
+    game_offset: usize,
+    data_dir: &'a Path,
+    book: Option<&'a OpeningBook>,
+    manifest: &'a Mutex<RunManifest>,
+}
+
+impl ThreadContext<'_> {
+    /// Records the progress of this thread in the run manifest and writes it to disk.
+    fn checkpoint(&self, progress: ThreadProgress) {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.threads[self.id] = progress;
+        manifest.save(self.data_dir).expect("Failed to write run manifest");
+    }
+}
+
 const EST_FEN_LENGTH: usize = 56;
 const EST_GAME_LENGTH: usize = 500;
 const FEN_BUFFER_SIZE: usize = EST_FEN_LENGTH * EST_GAME_LENGTH * 150 / 100; // 150% of estimated game length, to give some leeway
 
-#[allow(clippy::cognitive_complexity)]
-fn generate_on_thread(
-    id: usize,
-    options: &DataGenOptions,
-    data_dir: &Path,
-) -> HashMap<GameOutcome, u64> {
-    #![allow(clippy::cast_precision_loss, clippy::too_many_lines, clippy::cast_possible_truncation)]
-    // this rng is different between each thread
-    // (https://rust-random.github.io/book/guide-parallel.html)
-    // so no worries :3
-    let mut rng = rand::thread_rng();
+fn generate_on_thread(ctx: &ThreadContext) -> ThreadProgress {
+    #![allow(
+        clippy::cast_precision_loss,
+        clippy::too_many_lines,
+        clippy::cast_possible_truncation,
+        clippy::cognitive_complexity
+    )]
+    let ThreadContext { id, options, book, .. } = *ctx;
+    let progress = ctx.manifest.lock().unwrap().threads[id].clone();
     let mut board = Board::new();
     let mut thread_data = ThreadData::new(id, &board);
     let mut tt = TT::new();
     tt.resize(16 * MEGABYTE);
     let stopped = AtomicBool::new(false);
-    let time_manager = TimeManager {
-        limit: match options.limit {
-            DataGenLimit::Depth(depth) => SearchLimit::Depth(Depth::new(depth)),
-            DataGenLimit::Nodes(nodes) => SearchLimit::Nodes(nodes),
-        },
-        ..TimeManager::default()
-    };
-    let mut info = SearchInfo { time_manager, print_to_stdout: false, ..SearchInfo::new(&stopped) };
+    let mut info = SearchInfo { print_to_stdout: false, ..SearchInfo::new(&stopped) };
 
     let n_games_to_run = std::cmp::max(options.num_games / options.num_threads, 1);
 
-    let mut output_file = File::create(data_dir.join(format!("thread_{id}.txt"))).unwrap();
+    // when resuming, anything written after the last checkpoint (including
+    // any half-written game) is thrown away, and those games are played again.
+    // the file was checked to hold at least that much when the run was loaded.
+    let mut output_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(thread_output_path(ctx.data_dir, id, options.output_format))
+        .unwrap();
+    assert!(output_file.metadata().unwrap().len() >= progress.bytes_written);
+    output_file.set_len(progress.bytes_written).unwrap();
+    output_file.seek(SeekFrom::End(0)).unwrap();
     let mut output_buffer = BufWriter::new(&mut output_file);
 
     let mut single_game_buffer = Vec::new();
 
-    let mut counters = [
-        (GameOutcome::WhiteWinMate, 0),
-        (GameOutcome::BlackWinMate, 0),
-        (GameOutcome::WhiteWinTB, 0),
-        (GameOutcome::BlackWinTB, 0),
-        (GameOutcome::DrawFiftyMoves, 0),
-        (GameOutcome::DrawRepetition, 0),
-        (GameOutcome::DrawStalemate, 0),
-        (GameOutcome::DrawInsufficientMaterial, 0),
-        (GameOutcome::DrawTB, 0),
-        (GameOutcome::WhiteWinAdjudication, 0),
-        (GameOutcome::BlackWinAdjudication, 0),
-        (GameOutcome::DrawAdjudication, 0),
-    ]
-    .into_iter()
-    .collect::<HashMap<_, _>>();
+    let first_game = progress.games_completed;
+    let mut counters = progress.counters;
+    let mut filters = progress.filters;
 
     // to store the FENs of the game
     let mut fen_buffer = Vec::with_capacity(FEN_BUFFER_SIZE);
+    // to store the packed boards of the game, when writing binary data
+    let mut packed_game_buffer = Vec::with_capacity(EST_GAME_LENGTH);
+    // to store the moves of the game, when writing game records
+    let mut game_record = Game::new(&board);
 
     let start = Instant::now();
-    'generation_main_loop: for game in 0..n_games_to_run {
+    let mut games_completed = n_games_to_run;
+    'generation_main_loop: for game in first_game..n_games_to_run {
+        // flush output buffer
+        output_buffer.flush().unwrap();
+        // every game before this one has been written out, so once it's on disk we can checkpoint.
+        if game % CHECKPOINT_INTERVAL == 0 && game > first_game {
+            output_buffer.get_ref().sync_data().unwrap();
+            let bytes_written = output_buffer.get_ref().metadata().unwrap().len();
+            ctx.checkpoint(ThreadProgress {
+                games_completed: game,
+                bytes_written,
+                counters: counters.clone(),
+                filters: filters.clone(),
+            });
+        }
         // report progress
-        if id == 0 && game % 64 == 0 && options.log_level > 0 && game > 0 {
+        if id == 0 && game % 64 == 0 && options.log_level > 0 && game > first_game {
             let percentage = game * 100_000 / n_games_to_run;
             let percentage = percentage as f64 / 1000.0;
-            let time_per_game = start.elapsed().as_secs_f64() / game as f64;
+            let time_per_game = start.elapsed().as_secs_f64() / (game - first_game) as f64;
             eprintln!("[+] Main thread: Generated {game} games ({percentage:.1}%). Time per game: {time_per_game:.2} seconds.");
             eprintln!(
                 " |> FENs generated: {fens} (FENs/sec = {fps:.2})",
@@ -266,13 +827,25 @@ fn generate_on_thread(
                 print_game_stats(&counters);
             }
         }
+        // every game gets its own rng, derived from the run seed and the game's
+        // index across all threads, so that resumed runs pick up the same sequence.
+        let game_index = ctx.game_offset + game * options.num_threads + id;
+        let mut rng = StdRng::seed_from_u64(ctx.seed.wrapping_add(game_index as u64));
+        info.time_manager.limit = options.limit.search_limit(options.limit_jitter, &mut rng);
         // reset everything: board, thread data, tt, search info
-        board.set_startpos();
+        match (book, options.start_positions) {
+            // book positions were validated when the book was loaded.
+            (Some(book), _) => {
+                board.set_from_fen(book.pick(options.book_order, game_index, &mut rng)).unwrap();
+            }
+            (None, StartPositions::Standard) => board.set_startpos(),
+            (None, start_positions) => {
+                board.set_from_fen(&start_positions.random_fen(&mut rng)).unwrap();
+            }
+        }
         thread_data.nnue.refresh_acc(&board);
         tt.clear();
         info.setup_for_search();
-        // flush output buffer
-        output_buffer.flush().unwrap();
         // generate game
         if options.log_level > 1 {
             eprintln!("Generating game {game}...");
@@ -281,39 +854,48 @@ fn generate_on_thread(
         if options.log_level > 2 {
             eprintln!("Making random moves...");
         }
-        for _ in 0..12 {
+        let random_plies =
+            if book.is_some() { options.book_random_plies } else { options.random_plies };
+        for _ in 0..random_plies {
             let res = board.make_random_move::<true>(&mut rng, &mut thread_data, &info);
             if res.is_none() {
                 if options.log_level > 2 {
                     eprintln!("Reached a position with no legal moves, skipping...");
                 }
+                filters.dead_end_openings += 1;
                 continue 'generation_main_loop;
             }
         }
         // STEP 2: evaluate the exit position with reasonable depth
         // to make sure that it isn't silly.
-        if options.log_level > 2 {
-            eprintln!("Evaluating position...");
-        }
-        let temp_limit =
-            std::mem::replace(&mut info.time_manager.limit, SearchLimit::Depth(Depth::new(10)));
-        let (eval, _) = board.search_position::<true>(
-            &mut info,
-            std::array::from_mut(&mut thread_data),
-            tt.view(),
-        );
-        if eval.abs() > 1000 {
+        if options.verification_depth > 0 {
             if options.log_level > 2 {
-                eprintln!("Position is too good or too bad, skipping...");
+                eprintln!("Evaluating position...");
+            }
+            let temp_limit = std::mem::replace(
+                &mut info.time_manager.limit,
+                SearchLimit::Depth(Depth::new(options.verification_depth)),
+            );
+            let (eval, _) = board.search_position::<true>(
+                &mut info,
+                std::array::from_mut(&mut thread_data),
+                tt.view(),
+            );
+            info.time_manager.limit = temp_limit;
+            if eval.abs() > options.balance_threshold {
+                if options.log_level > 2 {
+                    eprintln!("Position is too good or too bad, skipping...");
+                }
+                // if the position is too good or too bad, we don't want it
+                filters.unbalanced_openings += 1;
+                continue 'generation_main_loop;
             }
-            // if the position is too good or too bad, we don't want it
-            continue 'generation_main_loop;
         }
-        info.time_manager.limit = temp_limit;
         // STEP 3: play out to the end of the game
         if options.log_level > 2 {
             eprintln!("Playing out game...");
         }
+        game_record.reset(&board);
         let mut win_adj_counter = 0;
         let mut draw_adj_counter = 0;
         let outcome = loop {
@@ -326,7 +908,7 @@ fn generate_on_thread(
                     break match wdl {
                         WDL::Win => GameOutcome::WhiteWinTB,
                         WDL::Loss => GameOutcome::BlackWinTB,
-                        WDL::Draw => GameOutcome::DrawTB,
+                        WDL::Draw | WDL::CursedWin | WDL::BlessedLoss => GameOutcome::DrawTB,
                     };
                 }
             }
@@ -336,24 +918,38 @@ fn generate_on_thread(
                 std::array::from_mut(&mut thread_data),
                 tt.view(),
             );
-            if !board.is_tactical(best_move)
-                && !is_game_theoretic_score(score)
-                && !board.in_check::<{ Board::US }>()
-            {
-                // we only save FENs where the best move is not tactical (promotions or captures)
-                // and the score is not game theoretic (mate or TB-win),
-                // and the side to move is not in check.
-                let fen_start = fen_buffer.len();
-                let bytes_written = board.write_fen_into(&mut fen_buffer).unwrap();
-                let fen_end = fen_start + bytes_written;
-                single_game_buffer.push((score, fen_start..fen_end));
+            // by default, we only save FENs where the best move is not tactical (promotions or captures)
+            // and the score is not game theoretic (mate or TB-win),
+            // and the side to move is not in check.
+            let tactical = options.filter_tactical && board.is_tactical(best_move);
+            let game_theoretic = options.filter_game_theoretic && is_game_theoretic_score(score);
+            let in_check = options.filter_in_check && board.in_check::<{ Board::US }>();
+            let keep = !tactical && !game_theoretic && !in_check;
+            filters.positions_seen += 1;
+            filters.tactical += u64::from(tactical);
+            filters.game_theoretic += u64::from(game_theoretic);
+            filters.in_check += u64::from(in_check);
+            filters.positions_kept += u64::from(keep);
+            match options.output_format {
+                OutputFormat::Text if keep => {
+                    let fen_start = fen_buffer.len();
+                    let bytes_written = board.write_fen_into(&mut fen_buffer).unwrap();
+                    let fen_end = fen_start + bytes_written;
+                    single_game_buffer.push((score, fen_start..fen_end));
+                }
+                OutputFormat::Binary if keep => {
+                    packed_game_buffer.push((score, PackedBoard::pack(&board)));
+                }
+                // games record every move, so that they can be re-filtered later.
+                OutputFormat::Game => game_record.add_move(best_move, score, keep),
+                _ => {}
             }
 
             let abs_score = score.abs();
-            if abs_score >= 2000 {
+            if abs_score >= options.win_adj_score {
                 win_adj_counter += 1;
                 draw_adj_counter = 0;
-            } else if abs_score <= 4 {
+            } else if abs_score <= options.draw_adj_score {
                 draw_adj_counter += 1;
                 win_adj_counter = 0;
             } else {
@@ -361,7 +957,7 @@ fn generate_on_thread(
                 draw_adj_counter = 0;
             }
 
-            if win_adj_counter >= 4 {
+            if options.win_adj_plies > 0 && win_adj_counter >= options.win_adj_plies {
                 let outcome = if score > 0 {
                     GameOutcome::WhiteWinAdjudication
                 } else {
@@ -369,7 +965,7 @@ fn generate_on_thread(
                 };
                 break outcome;
             }
-            if draw_adj_counter >= 12 {
+            if options.draw_adj_plies > 0 && draw_adj_counter >= options.draw_adj_plies {
                 break GameOutcome::DrawAdjudication;
             }
             if is_game_theoretic_score(score) {
@@ -391,10 +987,14 @@ fn generate_on_thread(
         assert_ne!(outcome, GameOutcome::Ongoing, "Game should be over by now.");
         let outcome_str = outcome.as_float_str();
         // STEP 4: write the game to the output file
+        let count = match options.output_format {
+            OutputFormat::Text => single_game_buffer.len(),
+            OutputFormat::Binary => packed_game_buffer.len(),
+            OutputFormat::Game => game_record.kept_positions(),
+        };
         if options.log_level > 2 {
-            eprintln!("Writing {} moves to output file...", single_game_buffer.len());
+            eprintln!("Writing {count} moves to output file...");
         }
-        let count = single_game_buffer.len();
         #[allow(clippy::iter_with_drain)] // we want to reuse the buffer
         for (score, fen) in single_game_buffer.drain(..) {
             let fen = unsafe {
@@ -403,6 +1003,15 @@ fn generate_on_thread(
             };
             writeln!(output_buffer, "{fen} | {score} | {outcome_str}").unwrap();
         }
+        let wdl = outcome.as_packed_wdl();
+        #[allow(clippy::iter_with_drain)] // we want to reuse the buffer
+        for (score, packed) in packed_game_buffer.drain(..) {
+            output_buffer.write_all(&PackedRecord::new(packed, score, wdl).to_bytes()).unwrap();
+        }
+        if options.output_format == OutputFormat::Game {
+            game_record.set_outcome(outcome);
+            game_record.serialise_into(&mut output_buffer).unwrap();
+        }
         FENS_GENERATED.fetch_add(count as u64, Ordering::SeqCst);
 
         // STEP 5: update the game outcome statistics
@@ -410,11 +1019,18 @@ fn generate_on_thread(
 
         // STEP 6: check if we should stop
         if STOP_GENERATION.load(Ordering::SeqCst) {
+            games_completed = game + 1;
             break 'generation_main_loop;
         }
     }
 
-    counters
+    output_buffer.flush().unwrap();
+    output_buffer.get_ref().sync_data().unwrap();
+    let bytes_written = output_buffer.get_ref().metadata().unwrap().len();
+    let progress = ThreadProgress { games_completed, bytes_written, counters, filters };
+    ctx.checkpoint(progress.clone());
+
+    progress
 }
 
 fn show_boot_info(options: &DataGenOptions) {
@@ -428,12 +1044,13 @@ fn show_boot_info(options: &DataGenOptions) {
         println!("It is recommended that you do not set the number of threads to more than the number of logical cores on your CPU, as performance will suffer.");
         println!("(You have {} logical cores on your CPU)", num_cpus::get());
         println!("To set a parameter, type \"set <PARAM> <VALUE>\"");
+        println!("To save or load a JSON config file, type \"save <PATH>\" or \"load <PATH>\"");
         println!("To start data generation, type \"start\" or \"go\".");
     }
 }
 
 fn config_loop(mut options: DataGenOptions) -> DataGenOptions {
-    #![allow(clippy::option_if_let_else)]
+    #![allow(clippy::option_if_let_else, clippy::too_many_lines)]
     println!();
     let mut user_input = String::new();
     loop {
@@ -446,9 +1063,30 @@ fn config_loop(mut options: DataGenOptions) -> DataGenOptions {
         if matches!(command, "start" | "go") {
             break;
         }
+        if matches!(command, "save" | "load") {
+            let Some(path) = user_input.next().map(Path::new) else {
+                eprintln!("Usage: \"{command} <PATH>\"");
+                continue;
+            };
+            if command == "save" {
+                match options.save_to_file(path) {
+                    Ok(()) => println!("Saved configuration to {}", path.display()),
+                    Err(e) => eprintln!("Failed to save configuration: {e}"),
+                }
+            } else {
+                match DataGenOptions::from_file(path) {
+                    Ok(loaded) => {
+                        options = loaded;
+                        println!("{options}");
+                    }
+                    Err(e) => eprintln!("{e}"),
+                }
+            }
+            continue;
+        }
         if command != "set" {
             eprintln!(
-                "Invalid command, supported commands are \"set <PARAM> <VALUE>\", \"start\", and \"go\""
+                "Invalid command, supported commands are \"set <PARAM> <VALUE>\", \"save <PATH>\", \"load <PATH>\", \"start\", and \"go\""
             );
             continue;
         }
@@ -500,13 +1138,15 @@ fn config_loop(mut options: DataGenOptions) -> DataGenOptions {
                 }
             }
             "limit" => {
-                let Some(limit_size) = user_input.next() else {
+                let limit_size = user_input.collect::<Vec<_>>();
+                if limit_size.is_empty() {
                     eprintln!("Trying to set limit, but only one token was provided");
-                    eprintln!("Usage: \"set limit <TYPE> <NUMBER>\"");
+                    eprintln!("Usage: \"set limit <TYPE> <NUMBER>\" or \"set limit soft_nodes <SOFT> <HARD>\"");
                     eprintln!("Example: \"set limit depth 8\" (sets the limit to 8 plies)");
+                    eprintln!("Example: \"set limit soft_nodes 5000 100000\" (stops after the first depth completed past 5000 nodes, or at 100000 nodes)");
                     continue;
-                };
-                let full_limit = value.to_string() + " " + limit_size;
+                }
+                let full_limit = value.to_string() + " " + &limit_size.join(" ");
                 let limit = match full_limit.parse::<DataGenLimit>() {
                     Ok(limit) => limit,
                     Err(e) => {
@@ -516,6 +1156,123 @@ fn config_loop(mut options: DataGenOptions) -> DataGenOptions {
                 };
                 options.limit = limit;
             }
+            "limit_jitter" => {
+                if let Ok(limit_jitter @ 0..=100) = value.parse::<u32>() {
+                    options.limit_jitter = limit_jitter;
+                } else {
+                    eprintln!("Invalid value for limit_jitter, must be a percentage from 0 to 100");
+                }
+            }
+            "output_format" => {
+                let output_format = match value.parse::<OutputFormat>() {
+                    Ok(output_format) => output_format,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        continue;
+                    }
+                };
+                options.output_format = output_format;
+            }
+            "book" => {
+                let book = PathBuf::from(value);
+                if book.is_file() {
+                    options.book = Some(book);
+                } else {
+                    eprintln!("Invalid value for book, must be the path to an EPD or FEN file");
+                }
+            }
+            "book_order" => {
+                let book_order = match value.parse::<BookOrder>() {
+                    Ok(book_order) => book_order,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        continue;
+                    }
+                };
+                options.book_order = book_order;
+            }
+            "start_positions" => {
+                let start_positions = match value.parse::<StartPositions>() {
+                    Ok(start_positions) => start_positions,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        continue;
+                    }
+                };
+                if options.book.is_some() {
+                    eprintln!(
+                        "Warning: an opening book is set, so start_positions will be ignored."
+                    );
+                }
+                options.start_positions = start_positions;
+            }
+            "random_plies" => {
+                if let Ok(random_plies) = value.parse::<usize>() {
+                    options.random_plies = random_plies;
+                } else {
+                    eprintln!("Invalid value for random_plies, must be a non-negative integer");
+                }
+            }
+            "book_random_plies" => {
+                if let Ok(book_random_plies) = value.parse::<usize>() {
+                    options.book_random_plies = book_random_plies;
+                } else {
+                    eprintln!(
+                        "Invalid value for book_random_plies, must be a non-negative integer"
+                    );
+                }
+            }
+            "verification_depth" => {
+                if let Ok(depth @ 0..) = value.parse::<i32>() {
+                    options.verification_depth = depth;
+                } else {
+                    eprintln!(
+                        "Invalid value for verification_depth, must be a non-negative integer"
+                    );
+                }
+            }
+            "balance_threshold" => {
+                if let Ok(threshold @ 0..) = value.parse::<i32>() {
+                    options.balance_threshold = threshold;
+                } else {
+                    eprintln!(
+                        "Invalid value for balance_threshold, must be a non-negative integer"
+                    );
+                }
+            }
+            "win_adj_plies" | "draw_adj_plies" => {
+                if let Ok(plies) = value.parse::<u32>() {
+                    if param == "win_adj_plies" {
+                        options.win_adj_plies = plies;
+                    } else {
+                        options.draw_adj_plies = plies;
+                    }
+                } else {
+                    eprintln!("Invalid value for {param}, must be a non-negative integer");
+                }
+            }
+            "win_adj_score" | "draw_adj_score" => {
+                if let Ok(score @ 0..) = value.parse::<i32>() {
+                    if param == "win_adj_score" {
+                        options.win_adj_score = score;
+                    } else {
+                        options.draw_adj_score = score;
+                    }
+                } else {
+                    eprintln!("Invalid value for {param}, must be a non-negative integer");
+                }
+            }
+            "filter_tactical" | "filter_in_check" | "filter_game_theoretic" => {
+                if let Ok(enabled) = value.parse::<bool>() {
+                    match param {
+                        "filter_tactical" => options.filter_tactical = enabled,
+                        "filter_in_check" => options.filter_in_check = enabled,
+                        _ => options.filter_game_theoretic = enabled,
+                    }
+                } else {
+                    eprintln!("Invalid value for {param}, must be a boolean");
+                }
+            }
             "log_level" => {
                 let log_level = match value.parse::<u8>() {
                     Ok(log_level) => log_level,
@@ -527,7 +1284,7 @@ fn config_loop(mut options: DataGenOptions) -> DataGenOptions {
                 options.log_level = log_level;
             }
             other => {
-                eprintln!("Invalid parameter (\"{other}\"), supported parameters are \"num_games\", \"num_threads\", \"tablebases_path\", \"use_nnue\", \"limit\", and \"log_level\"");
+                eprintln!("Invalid parameter (\"{other}\"), supported parameters are \"num_games\", \"num_threads\", \"tablebases_path\", \"use_nnue\", \"limit\", \"limit_jitter\", \"output_format\", \"book\", \"book_order\", \"start_positions\", \"random_plies\", \"book_random_plies\", \"verification_depth\", \"balance_threshold\", \"win_adj_plies\", \"win_adj_score\", \"draw_adj_plies\", \"draw_adj_score\", \"filter_tactical\", \"filter_in_check\", \"filter_game_theoretic\", and \"log_level\"");
             }
         }
     }
@@ -540,7 +1297,7 @@ impl FromStr for DataGenOptions {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut options = Self::new();
         let parts = s.split('-').collect::<Vec<_>>();
-        if parts.len() != 5 {
+        if !(5..=7).contains(&parts.len()) {
             return Err(format!("Invalid options string: {s}"));
         }
         options.num_games = parts[0]
@@ -557,24 +1314,19 @@ impl FromStr for DataGenOptions {
             options.tablebases_path = Some(PathBuf::from(parts[2]));
         }
         options.use_nnue = parts[3] == "nnue";
-        let limit = match parts[4].chars().next() {
-            Some('d') => DataGenLimit::Depth(
-                parts[4]
-                    .strip_prefix('d')
-                    .ok_or_else(|| format!("Invalid depth limit: {}", parts[4]))?
-                    .parse()
-                    .map_err(|_| format!("Invalid depth limit: {}", parts[4]))?,
-            ),
-            Some('n') => DataGenLimit::Nodes(
-                parts[4]
-                    .strip_prefix('n')
-                    .ok_or_else(|| format!("Invalid node limit: {}", parts[4]))?
-                    .parse()
-                    .map_err(|_| format!("Invalid node limit: {}", parts[4]))?,
-            ),
-            _ => return Err(format!("Invalid limit: {}", parts[4])),
-        };
-        options.limit = limit;
+        let (limit, jitter) = parts[4].split_once('j').unwrap_or((parts[4], "0"));
+        options.limit = DataGenLimit::from_short_name(limit)?;
+        options.limit_jitter = jitter
+            .parse()
+            .ok()
+            .filter(|&jitter| jitter <= 100)
+            .ok_or_else(|| format!("Invalid limit jitter: {}", parts[4]))?;
+        if let Some(format) = parts.get(5) {
+            options.output_format = format.parse()?;
+        }
+        if let Some(start_positions) = parts.get(6) {
+            options.start_positions = start_positions.parse()?;
+        }
         options.log_level = 1;
         Ok(options)
     }
@@ -593,14 +1345,27 @@ impl Display for DataGenOptions {
                 .map_or_else(|| "None".into(), |path| path.to_string_lossy())
         )?;
         writeln!(f, " |> use_nnue: {}", self.use_nnue)?;
+        writeln!(f, " |> limit: {}", self.limit)?;
+        writeln!(f, " |> limit_jitter: {}%", self.limit_jitter)?;
+        writeln!(f, " |> output_format: {}", self.output_format)?;
         writeln!(
             f,
-            " |> limit: {}",
-            match self.limit {
-                DataGenLimit::Depth(depth) => format!("depth {depth}"),
-                DataGenLimit::Nodes(nodes) => format!("nodes {nodes}"),
-            }
+            " |> book: {}",
+            self.book.as_ref().map_or_else(|| "None".into(), |path| path.to_string_lossy())
         )?;
+        writeln!(f, " |> book_order: {}", self.book_order)?;
+        writeln!(f, " |> start_positions: {}", self.start_positions)?;
+        writeln!(f, " |> random_plies: {}", self.random_plies)?;
+        writeln!(f, " |> book_random_plies: {}", self.book_random_plies)?;
+        writeln!(f, " |> verification_depth: {}", self.verification_depth)?;
+        writeln!(f, " |> balance_threshold: {}", self.balance_threshold)?;
+        writeln!(f, " |> win_adj_plies: {}", self.win_adj_plies)?;
+        writeln!(f, " |> win_adj_score: {}", self.win_adj_score)?;
+        writeln!(f, " |> draw_adj_plies: {}", self.draw_adj_plies)?;
+        writeln!(f, " |> draw_adj_score: {}", self.draw_adj_score)?;
+        writeln!(f, " |> filter_tactical: {}", self.filter_tactical)?;
+        writeln!(f, " |> filter_in_check: {}", self.filter_in_check)?;
+        writeln!(f, " |> filter_game_theoretic: {}", self.filter_game_theoretic)?;
         writeln!(f, " |> log_level: {}", self.log_level)?;
         if self.tablebases_path.is_none() {
             writeln!(f, "    ! Tablebases path not set - this will result in weaker data - are you sure you want to continue?")?;
@@ -616,6 +1381,14 @@ impl FromStr for DataGenLimit {
         #![allow(clippy::cast_possible_truncation)]
         let (limit_type, limit_value) =
             s.split_once(' ').ok_or_else(|| format!("Invalid limit, no space: {s}"))?;
+        if limit_type == "soft_nodes" {
+            let (soft, hard) = limit_value
+                .split_once(' ')
+                .ok_or_else(|| format!("Invalid soft node limit, expected two values: {s}"))?;
+            let soft = soft.parse().map_err(|_| format!("Invalid soft node limit: {soft}"))?;
+            let hard = hard.parse().map_err(|_| format!("Invalid hard node limit: {hard}"))?;
+            return Self::soft_nodes(soft, hard);
+        }
         let limit_value: u64 =
             limit_value.parse().map_err(|_| format!("Invalid limit value: {limit_value}"))?;
         match limit_type {
@@ -630,3 +1403,38 @@ impl FromStr for DataGenLimit {
         }
     }
 }
+
+impl Display for DataGenLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Depth(depth) => write!(f, "depth {depth}"),
+            Self::Nodes(nodes) => write!(f, "nodes {nodes}"),
+            Self::SoftNodes { soft, hard } => write!(f, "soft_nodes {soft} {hard}"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "txt" | "text" => Ok(Self::Text),
+            "bin" | "binary" => Ok(Self::Binary),
+            "game" | "games" => Ok(Self::Game),
+            _ => Err(format!(
+                "Invalid output format: {s}, expected \"text\", \"binary\", or \"game\""
+            )),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Binary => write!(f, "binary"),
+            Self::Game => write!(f, "game"),
+        }
+    }
+}
@@ -0,0 +1,615 @@
+//! Datagen spread over several processes, possibly on several machines.
+//!
+//! A coordinator splits a run into work units of a few games each, and hands them out
+//! to any workers that connect to it, over TCP or (on unix) a Unix domain socket.
+//! Workers generate the games in a unit exactly as a local datagen thread would,
+//! and send back the finished data, which the coordinator appends to a single file.
+//! If a worker disconnects, or goes quiet for longer than the unit timeout,
+//! its unit is put back in the queue and handed to the next worker that asks for one.
+//! A coordinator stopped with Ctrl-C hands out no more units, and saves the data from
+//! those already in flight. A stopped worker finishes its current game, sends back
+//! what it has, and disconnects, so the rest of its unit goes back in the queue.
+//!
+//! The protocol is line-delimited JSON. After the worker says `hello`, the coordinator
+//! sends the run's `options` and seed, then a `work` message for each unit, and finally
+//! `done`. Each `work` message is answered by a `result` message followed by the raw bytes
+//! of the data the worker generated.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use serde_json::{json, Value};
+
+use super::{
+    load_book,
+    manifest::{progress_from_json, progress_to_json, RunManifest, ThreadProgress},
+    openings::OpeningBook,
+    print_filter_stats, print_game_stats, set_up_tablebases, thread_output_path, DataGenOptions,
+    OutputFormat, ThreadContext, STOP_GENERATION,
+};
+
+/// How long an idle connection waits before checking for work again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Where a coordinator listens, and where workers connect to.
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    /// A TCP address, as `host:port`.
+    Tcp(String),
+    /// The path of a Unix domain socket, written as `unix:<PATH>`.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            #[cfg(not(unix))]
+            Some(_) => Err(format!("Unix sockets are not supported on this platform: {s}")),
+            None if s.contains(':') => Ok(Self::Tcp(s.to_owned())),
+            None => Err(format!("Invalid address: {s}, expected HOST:PORT or unix:PATH")),
+        }
+    }
+}
+
+impl Endpoint {
+    fn bind(&self) -> io::Result<Listener> {
+        let listener = match self {
+            Self::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                // a stale socket from a previous run would stop us from binding.
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                Listener::Unix(UnixListener::bind(path)?)
+            }
+        };
+        // the accept loop polls, so that it can notice when the run is finished.
+        match &listener {
+            Listener::Tcp(l) => l.set_nonblocking(true)?,
+            #[cfg(unix)]
+            Listener::Unix(l) => l.set_nonblocking(true)?,
+        }
+        Ok(listener)
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        match self {
+            Self::Tcp(addr) => Ok(Connection::Tcp(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            Self::Unix(path) => Ok(Connection::Unix(UnixStream::connect(path)?)),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Accepts a connection, returning `Ok(None)` if none is waiting.
+    fn accept(&self) -> io::Result<Option<Connection>> {
+        let conn = match self {
+            Self::Tcp(l) => l.accept().map(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                Ok(Connection::Tcp(stream))
+            }),
+            #[cfg(unix)]
+            Self::Unix(l) => l.accept().map(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                Ok(Connection::Unix(stream))
+            }),
+        };
+        match conn {
+            Ok(conn) => conn.map(Some),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The endpoint that workers should connect to.
+    fn endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Self::Tcp(l) => Ok(Endpoint::Tcp(l.local_addr()?.to_string())),
+            #[cfg(unix)]
+            Self::Unix(l) => l
+                .local_addr()?
+                .as_pathname()
+                .map(|path| Endpoint::Unix(path.to_owned()))
+                .ok_or_else(|| io::Error::other("unnamed unix socket")),
+        }
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(s) => s.try_clone().map(Self::Tcp),
+            #[cfg(unix)]
+            Self::Unix(s) => s.try_clone().map(Self::Unix),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Self::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Self::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Self::Unix(s) => s.flush(),
+        }
+    }
+}
+
+fn protocol_error(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn send(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// Reads a message, checking that it has the expected type.
+fn receive(reader: &mut impl BufRead, expected_types: &[&str]) -> io::Result<Value> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let message: Value =
+        serde_json::from_str(&line).map_err(|e| protocol_error(format!("bad message: {e}")))?;
+    let message_type = message["type"].as_str().unwrap_or_default();
+    if !expected_types.contains(&message_type) {
+        return Err(protocol_error(format!(
+            "expected a message of type {expected_types:?}, got {message}"
+        )));
+    }
+    Ok(message)
+}
+
+fn as_usize(value: &Value) -> io::Result<usize> {
+    value
+        .as_u64()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| protocol_error(format!("expected an unsigned integer, got {value}")))
+}
+
+/// A contiguous range of games in a distributed run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct WorkUnit {
+    first_game: usize,
+    games: usize,
+}
+
+/// The coordinator's view of the run, shared between the connections to each worker.
+struct CoordinatorState {
+    pending: VecDeque<WorkUnit>,
+    in_flight: usize,
+    progress: ThreadProgress,
+    output: BufWriter<File>,
+}
+
+impl CoordinatorState {
+    fn is_finished(&self) -> bool {
+        self.pending.is_empty() && self.in_flight == 0
+    }
+
+    /// Takes a unit of work, or returns `None` if the run is finished or has been stopped.
+    /// Waits while every remaining unit is being worked on, in case one of them is abandoned.
+    fn next_unit(state: &Mutex<Self>) -> Option<WorkUnit> {
+        loop {
+            if STOP_GENERATION.load(Ordering::SeqCst) {
+                return None;
+            }
+            let mut state = state.lock().unwrap();
+            if let Some(unit) = state.pending.pop_front() {
+                state.in_flight += 1;
+                return Some(unit);
+            }
+            if state.in_flight == 0 {
+                return None;
+            }
+            drop(state);
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Settings for a coordinator.
+pub struct CoordinatorConfig {
+    /// The number of games in each work unit.
+    pub unit_games: usize,
+    /// How long to wait for a worker to finish a unit before giving it to someone else.
+    pub unit_timeout: Duration,
+}
+
+/// Runs a coordinator for the run described by `options`, listening on `listener`
+/// and writing all the generated data into `data_dir`.
+/// Returns the combined progress of every worker once all the games have been generated,
+/// or once the units in flight when generation was stopped have come back.
+fn coordinate(
+    listener: &Listener,
+    options: &DataGenOptions,
+    seed: u64,
+    data_dir: &Path,
+    config: &CoordinatorConfig,
+) -> io::Result<ThreadProgress> {
+    let unit_games = config.unit_games.max(1);
+    let pending = (0..options.num_games)
+        .step_by(unit_games)
+        .map(|first_game| WorkUnit {
+            first_game,
+            games: unit_games.min(options.num_games - first_game),
+        })
+        .collect();
+    let output =
+        File::create(data_dir.join(format!("data.{}", options.output_format.extension())))?;
+    let state = Mutex::new(CoordinatorState {
+        pending,
+        in_flight: 0,
+        progress: ThreadProgress::new(),
+        output: BufWriter::new(output),
+    });
+    let welcome = json!({ "type": "options", "options": options.to_json(), "seed": seed });
+
+    std::thread::scope(|s| -> io::Result<()> {
+        while !state.lock().unwrap().is_finished() && !STOP_GENERATION.load(Ordering::SeqCst) {
+            let Some(conn) = listener.accept()? else {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+            let (state, welcome) = (&state, &welcome);
+            s.spawn(move || {
+                if let Err(e) =
+                    serve_worker(conn, state, welcome, options.output_format, config.unit_timeout)
+                {
+                    if options.log_level > 0 {
+                        eprintln!("Lost a worker ({e}), its work will be reassigned.");
+                    }
+                }
+            });
+        }
+        Ok(())
+    })?;
+
+    let mut state = state.into_inner().unwrap();
+    state.output.flush()?;
+    Ok(state.progress)
+}
+
+/// Hands out work units to one worker until the run is finished.
+fn serve_worker(
+    conn: Connection,
+    state: &Mutex<CoordinatorState>,
+    welcome: &Value,
+    format: OutputFormat,
+    unit_timeout: Duration,
+) -> io::Result<()> {
+    conn.set_read_timeout(Some(unit_timeout))?;
+    let mut reader = BufReader::new(conn.try_clone()?);
+    let mut writer = conn;
+    receive(&mut reader, &["hello"])?;
+    send(&mut writer, welcome)?;
+    while let Some(unit) = CoordinatorState::next_unit(state) {
+        match run_unit_remotely(&mut reader, &mut writer, unit, format) {
+            Ok((progress, data)) => {
+                let mut state = state.lock().unwrap();
+                state.in_flight -= 1;
+                state.output.write_all(&data)?;
+                state.progress.merge(&progress);
+                // a worker that was stopped part way through a unit sends what it has,
+                // and the rest of the unit goes back in the queue.
+                if progress.games_completed < unit.games {
+                    state.pending.push_back(WorkUnit {
+                        first_game: unit.first_game + progress.games_completed,
+                        games: unit.games - progress.games_completed,
+                    });
+                }
+            }
+            Err(e) => {
+                let mut state = state.lock().unwrap();
+                state.in_flight -= 1;
+                state.pending.push_front(unit);
+                drop(state);
+                return Err(e);
+            }
+        }
+    }
+    send(&mut writer, &json!({ "type": "done" }))
+}
+
+/// Gives a unit to a worker, and reads back its progress and the data it generated.
+/// Results that couldn't have come from the unit are rejected, so that the unit is requeued.
+fn run_unit_remotely(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    unit: WorkUnit,
+    format: OutputFormat,
+) -> io::Result<(ThreadProgress, Vec<u8>)> {
+    send(writer, &json!({ "type": "work", "first_game": unit.first_game, "games": unit.games }))?;
+    let result = receive(reader, &["result"])?;
+    if as_usize(&result["first_game"])? != unit.first_game {
+        return Err(protocol_error("worker returned the result of a different unit"));
+    }
+    let progress = progress_from_json(&result["progress"]).map_err(protocol_error)?;
+    if progress.games_completed > unit.games {
+        return Err(protocol_error("worker completed more games than it was given"));
+    }
+    let bytes = as_usize(&result["bytes"])?;
+    if bytes > progress.games_completed.saturating_mul(format.max_game_size()) {
+        return Err(protocol_error(format!(
+            "worker sent {bytes} bytes for {} games, which is more than they can hold",
+            progress.games_completed
+        )));
+    }
+    let mut data = vec![0; bytes];
+    reader.read_exact(&mut data)?;
+    Ok((progress, data))
+}
+
+/// Connects to a coordinator and generates games for it until it says the run is finished.
+/// Returns the number of units that were completed.
+fn work(endpoint: &Endpoint) -> io::Result<usize> {
+    static TABLEBASES: Once = Once::new();
+    static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+    let conn = endpoint.connect()?;
+    let mut reader = BufReader::new(conn.try_clone()?);
+    let mut writer = conn;
+    send(&mut writer, &json!({ "type": "hello" }))?;
+    let welcome = receive(&mut reader, &["options"])?;
+    let options = DataGenOptions::from_json(&welcome["options"]).map_err(protocol_error)?;
+    let seed = welcome["seed"].as_u64().ok_or_else(|| protocol_error("missing seed"))?;
+    // tablebases and books are loaded from the same paths as on the coordinator,
+    // so they need to be available at those paths on every worker machine.
+    TABLEBASES.call_once(|| set_up_tablebases(&options));
+    let book = load_book(&options).map_err(protocol_error)?;
+
+    let scratch_dir = std::env::temp_dir().join(format!(
+        "viri_datagen_worker_{}_{}",
+        std::process::id(),
+        CONNECTIONS.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::create_dir_all(&scratch_dir)?;
+
+    let mut units_completed = 0;
+    let result = loop {
+        // disconnecting instead of taking another unit puts it back in the coordinator's queue.
+        if STOP_GENERATION.load(Ordering::SeqCst) {
+            break Ok(units_completed);
+        }
+        let message = match receive(&mut reader, &["work", "done"]) {
+            Ok(message) => message,
+            Err(e) => break Err(e),
+        };
+        if message["type"] == "done" {
+            break Ok(units_completed);
+        }
+        if let Err(e) = run_unit(&message, &options, seed, book.as_ref(), &scratch_dir, &mut writer)
+        {
+            break Err(e);
+        }
+        units_completed += 1;
+    };
+    std::fs::remove_dir_all(&scratch_dir)?;
+    result
+}
+
+/// Generates the games in the unit described by a `work` message, and sends back the result.
+fn run_unit(
+    message: &Value,
+    options: &DataGenOptions,
+    seed: u64,
+    book: Option<&OpeningBook>,
+    scratch_dir: &Path,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    let unit = WorkUnit {
+        first_game: as_usize(&message["first_game"])?,
+        games: as_usize(&message["games"])?,
+    };
+    // each unit is played exactly like a single-threaded local run,
+    // with its game indices offset to where the unit starts.
+    let unit_options = DataGenOptions { num_games: unit.games, num_threads: 1, ..options.clone() };
+    let manifest = Mutex::new(RunManifest::new(unit_options.clone(), seed));
+    let ctx = ThreadContext {
+        id: 0,
+        options: &unit_options,
+        seed,
+        game_offset: unit.first_game,
+        data_dir: scratch_dir,
+        book,
+        manifest: &manifest,
+    };
+    let progress = super::generate_on_thread(&ctx);
+    let data = std::fs::read(thread_output_path(scratch_dir, 0, options.output_format))?;
+    send(
+        writer,
+        &json!({
+            "type": "result",
+            "first_game": unit.first_game,
+            "progress": progress_to_json(&progress),
+            "bytes": data.len(),
+        }),
+    )?;
+    writer.write_all(&data)?;
+    writer.flush()
+}
+
+/// Runs a coordinator on `address`, for the run configured by `cli_config`.
+pub fn coordinator_main(address: &str, cli_config: Option<&str>, config: &CoordinatorConfig) {
+    super::set_up_stop_handler();
+    let endpoint = address.parse::<Endpoint>().unwrap_or_else(|e| panic!("{e}"));
+    let options = super::get_options(cli_config);
+    let run_id = format!(
+        "run_{}_{}_distributed",
+        chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S"),
+        options.summary()
+    );
+    let data_dir = PathBuf::from("data").join(run_id);
+    std::fs::create_dir_all(&data_dir).expect("Failed to create data directory");
+    let listener = endpoint.bind().expect("Failed to bind coordinator address");
+    if options.log_level > 0 {
+        println!("Coordinating {} games in units of {}.", options.num_games, config.unit_games);
+        println!(
+            "Workers can connect to {:?}, and the data will be saved to \"{}\"",
+            listener.endpoint().expect("Failed to get coordinator address"),
+            data_dir.display()
+        );
+    }
+    let progress = coordinate(&listener, &options, rand::random(), &data_dir, config)
+        .expect("Coordinator failed");
+    if options.log_level > 0 {
+        if STOP_GENERATION.load(Ordering::SeqCst) {
+            println!("Stopped after {} games.", progress.games_completed);
+        } else {
+            println!("Done!");
+        }
+    }
+    print_game_stats(&progress.counters);
+    print_filter_stats(&progress.filters);
+}
+
+/// Runs `threads` worker connections to the coordinator on `address`.
+pub fn worker_main(address: &str, threads: usize) {
+    super::set_up_stop_handler();
+    let endpoint = address.parse::<Endpoint>().unwrap_or_else(|e| panic!("{e}"));
+    std::thread::scope(|s| {
+        for _ in 0..threads.max(1) {
+            s.spawn(|| match work(&endpoint) {
+                Ok(units) => println!("Worker finished after completing {units} units."),
+                Err(e) => eprintln!("Worker lost its connection to the coordinator: {e}"),
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datagen::{marlinformat::PackedRecord, DataGenOptions};
+    use serde_json::json;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        time::Duration,
+    };
+
+    #[test]
+    fn oversized_results_are_rejected() {
+        let unit = WorkUnit { first_game: 10, games: 2 };
+        let progress = ThreadProgress { games_completed: 1, ..ThreadProgress::new() };
+        let most = OutputFormat::Binary.max_game_size();
+        let result = |bytes: usize| {
+            let message = json!({
+                "type": "result",
+                "first_game": 10,
+                "progress": progress_to_json(&progress),
+                "bytes": bytes,
+            });
+            let mut input = format!("{message}\n").into_bytes();
+            input.resize(input.len() + bytes.min(most), 0);
+            run_unit_remotely(&mut input.as_slice(), &mut Vec::new(), unit, OutputFormat::Binary)
+        };
+        assert_eq!(result(most).unwrap().1.len(), most);
+        let error = result(most + 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(result(usize::MAX).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn loopback_run_with_a_lost_worker() {
+        crate::magic::initialise();
+        let dir =
+            std::env::temp_dir().join(format!("viri_distributed_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut options = "6g-1t-no_tb-nnue-d2-bin".parse::<DataGenOptions>().unwrap();
+        options.verification_depth = 0;
+        options.log_level = 0;
+        let listener = Endpoint::Tcp("127.0.0.1:0".into()).bind().unwrap();
+        let endpoint = listener.endpoint().unwrap();
+        let config = CoordinatorConfig { unit_games: 2, unit_timeout: Duration::from_secs(30) };
+
+        let progress = std::thread::scope(|s| {
+            let coordinator = s.spawn(|| coordinate(&listener, &options, 12345, &dir, &config));
+            // a worker that takes a unit and then vanishes without answering.
+            let conn = endpoint.connect().unwrap();
+            let mut reader = BufReader::new(conn.try_clone().unwrap());
+            let mut writer = conn;
+            writeln!(writer, "{}", json!({ "type": "hello" })).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.contains("\"work\""));
+            drop((reader, writer));
+            // two real workers pick up all the units, including the abandoned one.
+            let workers = [s.spawn(|| work(&endpoint)), s.spawn(|| work(&endpoint))];
+            let units = workers.map(|w| w.join().unwrap().unwrap());
+            assert_eq!(units.iter().sum::<usize>(), 3);
+            coordinator.join().unwrap().unwrap()
+        });
+
+        assert_eq!(progress.games_completed, 6);
+        let data = std::fs::read(dir.join("data.bin")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(data.len() as u64 / PackedRecord::SIZE as u64, progress.filters.positions_kept);
+        for record in data.chunks_exact(PackedRecord::SIZE) {
+            PackedRecord::from_bytes(record.try_into().unwrap()).unpack().unwrap();
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    board::{packed::PackedBoard, Board, GameOutcome},
+    chessmove::Move,
+};
+
+use super::marlinformat::PackedRecord;
+
+/// Set in a move's flag byte when the position before the move passed the datagen filter.
+const KEEP_FLAG: u8 = 1;
+
+/// A single move in a game record, along with the search score
+/// of the position before the move was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MoveEntry {
+    mv: Move,
+    score: i16,
+    flags: u8,
+}
+
+impl MoveEntry {
+    /// The size of a move entry in bytes.
+    const SIZE: usize = 5;
+}
+
+/// A self-play game, stored as the opening position followed by every move played.
+///
+/// On disk, a game is a marlinformat record of the opening position (whose WDL byte
+/// holds the result of the game), a little-endian `u16` move count, and then for each move
+/// the raw move, the white-relative search score as an `i16`, and a flag byte.
+/// This is roughly an order of magnitude smaller than storing every kept position as a FEN,
+/// and it allows the positions to be re-filtered later without regenerating the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    initial_position: PackedBoard,
+    wdl: u8,
+    moves: Vec<MoveEntry>,
+}
+
+impl Game {
+    /// Starts a new game record from the given position.
+    pub fn new(initial_position: &Board) -> Self {
+        Self {
+            initial_position: PackedBoard::pack(initial_position),
+            wdl: PackedRecord::DRAW,
+            moves: Vec::new(),
+        }
+    }
+
+    /// Resets the record to start from a new position.
+    pub fn reset(&mut self, initial_position: &Board) {
+        self.initial_position = PackedBoard::pack(initial_position);
+        self.wdl = PackedRecord::DRAW;
+        self.moves.clear();
+    }
+
+    /// Records a move, the white-relative score of the position it was played from,
+    /// and whether that position should be kept as a training position.
+    pub fn add_move(&mut self, mv: Move, score: i32, keep: bool) {
+        #![allow(clippy::cast_possible_truncation)]
+        let score = score.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        self.moves.push(MoveEntry { mv, score, flags: if keep { KEEP_FLAG } else { 0 } });
+    }
+
+    /// Records the result of the game.
+    pub const fn set_outcome(&mut self, outcome: GameOutcome) {
+        self.wdl = outcome.as_packed_wdl();
+    }
+
+    /// The size in bytes of a serialised game with `n_moves` moves.
+    pub const fn serialised_size(n_moves: usize) -> usize {
+        PackedRecord::SIZE + std::mem::size_of::<u16>() + n_moves * MoveEntry::SIZE
+    }
+
+    /// The number of moves in the game.
+    pub const fn num_moves(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// The number of positions in the game that were flagged to be kept.
+    pub fn kept_positions(&self) -> usize {
+        self.moves.iter().filter(|entry| entry.flags & KEEP_FLAG != 0).count()
+    }
+
+    /// Writes the game to `writer`.
+    pub fn serialise_into(&self, mut writer: impl Write) -> io::Result<()> {
+        let header = PackedRecord::new(self.initial_position, 0, self.wdl);
+        writer.write_all(&header.to_bytes())?;
+        let n_moves = u16::try_from(self.moves.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "game is too long"))?;
+        writer.write_all(&n_moves.to_le_bytes())?;
+        for entry in &self.moves {
+            writer.write_all(&entry.mv.inner().to_le_bytes())?;
+            writer.write_all(&entry.score.to_le_bytes())?;
+            writer.write_all(&[entry.flags])?;
+        }
+        Ok(())
+    }
+
+    /// Reads a game from `reader`.
+    /// Returns `Ok(None)` if the reader is at the end of its input,
+    /// and an error if the input ends part way through a game.
+    pub fn deserialise_from(mut reader: impl Read) -> io::Result<Option<Self>> {
+        let mut header = [0; PackedRecord::SIZE];
+        let mut filled = 0;
+        while filled < header.len() {
+            match reader.read(&mut header[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+        let header = PackedRecord::from_bytes(&header);
+        let mut n_moves = [0; 2];
+        reader.read_exact(&mut n_moves)?;
+        let n_moves = usize::from(u16::from_le_bytes(n_moves));
+        let mut moves = Vec::with_capacity(n_moves);
+        let mut entry = [0; MoveEntry::SIZE];
+        for _ in 0..n_moves {
+            reader.read_exact(&mut entry)?;
+            moves.push(MoveEntry {
+                mv: Move::from_raw(u16::from_le_bytes([entry[0], entry[1]])),
+                score: i16::from_le_bytes([entry[2], entry[3]]),
+                flags: entry[4],
+            });
+        }
+        Ok(Some(Self { initial_position: header.board(), wdl: header.wdl(), moves }))
+    }
+
+    /// Replays the game, calling `callback` with every position before a move is made,
+    /// along with the move, its white-relative score, and whether it was flagged to be kept.
+    /// Fails if the record contains an illegal move.
+    pub fn replay(&self, mut callback: impl FnMut(&Board, Move, i32, bool)) -> io::Result<()> {
+        let mut board = self
+            .initial_position
+            .unpack()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        for (i, entry) in self.moves.iter().enumerate() {
+            callback(&board, entry.mv, entry.score.into(), entry.flags & KEEP_FLAG != 0);
+            if !board.is_pseudo_legal(entry.mv) || !board.make_move_base(entry.mv) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("illegal move {} at ply {i} in position {}", entry.mv, board.fen()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands the game back into training positions, keeping the positions
+    /// for which `filter` returns true. `filter` is given the position, the move played,
+    /// the white-relative score, and whether the position was originally flagged to be kept.
+    pub fn splat(
+        &self,
+        mut filter: impl FnMut(&Board, Move, i32, bool) -> bool,
+        mut callback: impl FnMut(PackedRecord),
+    ) -> io::Result<()> {
+        let wdl = self.wdl;
+        self.replay(|board, mv, score, keep| {
+            if filter(board, mv, score, keep) {
+                callback(PackedRecord::new(PackedBoard::pack(board), score, wdl));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Board, GameOutcome};
+
+    #[test]
+    fn game_round_trip() {
+        crate::magic::initialise();
+        let mut board = Board::default();
+        let mut game = Game::new(&board);
+        let mut expected = Vec::new();
+        for (i, uci) in ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "e1g1"].iter().enumerate()
+        {
+            let mv = board.parse_uci(uci).unwrap();
+            let keep = i % 2 == 0;
+            if keep {
+                expected.push((board.fen(), i32::try_from(i).unwrap() * 10));
+            }
+            game.add_move(mv, i32::try_from(i).unwrap() * 10, keep);
+            assert!(board.make_move_base(mv));
+        }
+        game.set_outcome(GameOutcome::WhiteWinAdjudication);
+
+        let mut bytes = Vec::new();
+        game.serialise_into(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 32 + 2 + 7 * 5);
+        assert_eq!(bytes.len(), Game::serialised_size(game.num_moves()));
+        let mut reader = bytes.as_slice();
+        let read_back = Game::deserialise_from(&mut reader).unwrap().unwrap();
+        assert_eq!(read_back, game);
+        assert!(Game::deserialise_from(&mut reader).unwrap().is_none());
+
+        let mut positions = Vec::new();
+        read_back
+            .splat(
+                |_, _, _, keep| keep,
+                |record| {
+                    let (board, eval, wdl) = record.unpack().unwrap();
+                    assert_eq!(wdl, 2);
+                    positions.push((board.fen(), eval));
+                },
+            )
+            .unwrap();
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn truncated_game_is_an_error() {
+        crate::magic::initialise();
+        let mut board = Board::default();
+        let mut game = Game::new(&board);
+        let mv = board.parse_uci("d2d4").unwrap();
+        game.add_move(mv, 25, true);
+        assert!(board.make_move_base(mv));
+        let mut bytes = Vec::new();
+        game.serialise_into(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(Game::deserialise_from(bytes.as_slice()).is_err());
+    }
+}
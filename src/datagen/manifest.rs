@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use crate::board::GameOutcome;
+
+use super::{thread_output_path, DataGenOptions, FilterCounters, GAME_OUTCOMES};
+
+/// The name of the manifest file inside a run directory.
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// How far a single datagen thread has got through its share of the games.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadProgress {
+    /// The number of games that have been played (or skipped) and written out.
+    pub games_completed: usize,
+    /// The length of the thread's output file once those games were written.
+    pub bytes_written: u64,
+    /// The outcomes of the completed games.
+    pub counters: HashMap<GameOutcome, u64>,
+    /// How many openings and positions each filter rejected in the completed games.
+    pub filters: FilterCounters,
+}
+
+impl ThreadProgress {
+    /// The progress of a thread that hasn't started yet.
+    pub fn new() -> Self {
+        Self {
+            games_completed: 0,
+            bytes_written: 0,
+            counters: GAME_OUTCOMES.into_iter().map(|outcome| (outcome, 0)).collect(),
+            filters: FilterCounters::default(),
+        }
+    }
+
+    /// Adds the games, outcomes, and filter counts of `other` into this progress.
+    /// The output length is left alone, as it only makes sense for a single file.
+    pub fn merge(&mut self, other: &Self) {
+        self.games_completed += other.games_completed;
+        for (&outcome, &count) in &other.counters {
+            *self.counters.entry(outcome).or_insert(0) += count;
+        }
+        self.filters.add(&other.filters);
+    }
+}
+
+/// Everything needed to pick up a datagen run where it left off.
+///
+/// The manifest is rewritten as threads checkpoint their progress,
+/// always by writing a temporary file and renaming it over the old one,
+/// so a crash can never leave a half-written manifest behind.
+/// The recorded output length of each thread only covers games that had been
+/// flushed to disk, so anything past it is discarded when the run is resumed.
+#[derive(Clone, Debug)]
+pub struct RunManifest {
+    /// The configuration of the run.
+    pub options: DataGenOptions,
+    /// The seed that all per-game random number generators are derived from.
+    pub seed: u64,
+    /// The progress of each thread.
+    pub threads: Vec<ThreadProgress>,
+}
+
+impl RunManifest {
+    /// Creates the manifest for a fresh run.
+    pub fn new(options: DataGenOptions, seed: u64) -> Self {
+        let threads = vec![ThreadProgress::new(); options.num_threads];
+        Self { options, seed, threads }
+    }
+
+    /// The path of the manifest file in the run directory `data_dir`.
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(MANIFEST_NAME)
+    }
+
+    /// Loads the manifest of the run in `data_dir`.
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = Self::path(data_dir);
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read run manifest {}: {e}", path.display()))?;
+        let json: Value = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse run manifest {}: {e}", path.display()))?;
+        let options = DataGenOptions::from_json(&json["options"])?;
+        let seed = json["seed"].as_u64().ok_or("Run manifest has no seed")?;
+        let threads = json["threads"]
+            .as_array()
+            .ok_or("Run manifest has no thread progress")?
+            .iter()
+            .map(progress_from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        if threads.len() != options.num_threads {
+            return Err(format!(
+                "Run manifest records progress for {} threads, but the run uses {}",
+                threads.len(),
+                options.num_threads
+            ));
+        }
+        Ok(Self { options, seed, threads })
+    }
+
+    /// Checks that the output file of every thread in `data_dir` holds at least as much data
+    /// as the manifest says was written, so that the run can be resumed without losing games.
+    pub fn check_outputs(&self, data_dir: &Path) -> Result<(), String> {
+        for (id, progress) in self.threads.iter().enumerate() {
+            let path = thread_output_path(data_dir, id, self.options.output_format);
+            let len = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            if len < progress.bytes_written {
+                return Err(format!(
+                    "{} holds {len} bytes, but the run manifest says {} were written before the last checkpoint, so the run can't be resumed",
+                    path.display(),
+                    progress.bytes_written
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Atomically writes the manifest into `data_dir`.
+    pub fn save(&self, data_dir: &Path) -> std::io::Result<()> {
+        let json = json!({
+            "options": self.options.to_json(),
+            "seed": self.seed,
+            "threads": self.threads.iter().map(progress_to_json).collect::<Vec<_>>(),
+        });
+        let path = Self::path(data_dir);
+        let tmp_path = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer_pretty(&mut file, &json)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        std::fs::rename(tmp_path, path)
+    }
+}
+
+pub fn progress_to_json(progress: &ThreadProgress) -> Value {
+    let counters = GAME_OUTCOMES
+        .iter()
+        .map(|outcome| (format!("{outcome:?}"), json!(progress.counters[outcome])))
+        .collect::<serde_json::Map<_, _>>();
+    let filters = progress
+        .filters
+        .fields()
+        .iter()
+        .map(|&(name, count)| (name.to_owned(), json!(count)))
+        .collect::<serde_json::Map<_, _>>();
+    json!({
+        "games_completed": progress.games_completed,
+        "bytes_written": progress.bytes_written,
+        "counters": counters,
+        "filters": filters,
+    })
+}
+
+pub fn progress_from_json(json: &Value) -> Result<ThreadProgress, String> {
+    let games_completed = json["games_completed"]
+        .as_u64()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or("Thread progress has no completed game count")?;
+    let bytes_written =
+        json["bytes_written"].as_u64().ok_or("Thread progress has no output length")?;
+    let mut progress = ThreadProgress { games_completed, bytes_written, ..ThreadProgress::new() };
+    let counters = json["counters"].as_object().ok_or("Thread progress has no outcome counters")?;
+    for (name, count) in counters {
+        let outcome = GAME_OUTCOMES
+            .into_iter()
+            .find(|outcome| format!("{outcome:?}") == *name)
+            .ok_or_else(|| format!("Unknown game outcome in run manifest: {name}"))?;
+        let count =
+            count.as_u64().ok_or_else(|| format!("Invalid count for {name} in run manifest"))?;
+        progress.counters.insert(outcome, count);
+    }
+    let filters = json["filters"].as_object().ok_or("Thread progress has no filter counters")?;
+    for (name, count) in progress.filters.fields_mut() {
+        *count = filters
+            .get(name)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| format!("Invalid count for {name} in run manifest"))?;
+    }
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board::GameOutcome, datagen::DataGenOptions};
+
+    #[test]
+    fn manifest_round_trip() {
+        let dir = std::env::temp_dir().join(format!("viri_manifest_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = "64g-2t-no_tb-hce-n5000-game-frc".parse::<DataGenOptions>().unwrap();
+        let mut manifest = RunManifest::new(options, 0xDEAD_BEEF_CAFE);
+        manifest.threads[1].games_completed = 17;
+        manifest.threads[1].bytes_written = 12345;
+        *manifest.threads[1].counters.get_mut(&GameOutcome::DrawTB).unwrap() = 9;
+        manifest.threads[0].filters.positions_seen = 300;
+        manifest.threads[0].filters.tactical = 42;
+        manifest.save(&dir).unwrap();
+        let loaded = RunManifest::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.seed, manifest.seed);
+        assert_eq!(loaded.threads, manifest.threads);
+        assert_eq!(loaded.options.summary(), manifest.options.summary());
+        assert_eq!(loaded.options.to_json(), manifest.options.to_json());
+    }
+
+    #[test]
+    fn short_outputs_are_not_resumed() {
+        let dir = std::env::temp_dir().join(format!("viri_outputs_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = "64g-2t-no_tb-hce-n5000-bin".parse::<DataGenOptions>().unwrap();
+        let format = options.output_format;
+        let mut manifest = RunManifest::new(options, 0);
+        assert!(manifest.check_outputs(&dir).is_ok());
+        manifest.threads[1].bytes_written = 64;
+        std::fs::write(thread_output_path(&dir, 1, format), [0; 32]).unwrap();
+        let short = manifest.check_outputs(&dir);
+        std::fs::write(thread_output_path(&dir, 1, format), [0; 96]).unwrap();
+        let long = manifest.check_outputs(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(short.unwrap_err().contains("holds 32 bytes"));
+        assert!(long.is_ok());
+    }
+}
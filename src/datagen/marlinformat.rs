@@ -0,0 +1,100 @@
+use crate::{
+    board::{packed::PackedBoard, Board},
+    errors::PackedBoardError,
+};
+
+/// A single training position in marlinformat.
+///
+/// This is a packed board followed by the evaluation (from white's perspective),
+/// the game result (0 = black win, 1 = draw, 2 = white win), and a padding byte,
+/// for a total of 32 bytes. bulletformat data can be produced from this with bullet's converter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedRecord {
+    board: PackedBoard,
+    eval: i16,
+    wdl: u8,
+    extra: u8,
+}
+
+impl PackedRecord {
+    /// The size of a packed record in bytes.
+    pub const SIZE: usize = PackedBoard::SIZE + 4;
+
+    /// The WDL byte for a white win.
+    pub const WHITE_WIN: u8 = 2;
+    /// The WDL byte for a draw.
+    pub const DRAW: u8 = 1;
+    /// The WDL byte for a black win.
+    pub const BLACK_WIN: u8 = 0;
+
+    /// Creates a new record from a packed board, a white-relative evaluation, and a WDL byte.
+    pub fn new(board: PackedBoard, eval: i32, wdl: u8) -> Self {
+        #![allow(clippy::cast_possible_truncation)]
+        debug_assert!(wdl <= Self::WHITE_WIN);
+        let eval = eval.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        Self { board, eval, wdl, extra: 0 }
+    }
+
+    /// Unpacks the record into a board, a white-relative evaluation, and a WDL byte.
+    pub fn unpack(&self) -> Result<(Board, i32, u8), PackedBoardError> {
+        Ok((self.board.unpack()?, self.eval.into(), self.wdl))
+    }
+
+    /// The packed board of the record.
+    pub const fn board(&self) -> PackedBoard {
+        self.board
+    }
+
+    /// The WDL byte of the record.
+    pub const fn wdl(&self) -> u8 {
+        self.wdl
+    }
+
+    /// The WDL byte as a float, 1.0 for a white win, 0.5 for a draw, 0.0 for a black win.
+    pub fn wdl_float(&self) -> f32 {
+        f32::from(self.wdl) / 2.0
+    }
+
+    /// Serialises the record into little-endian bytes.
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut out = [0; Self::SIZE];
+        out[..PackedBoard::SIZE].copy_from_slice(&self.board.to_bytes());
+        out[PackedBoard::SIZE..PackedBoard::SIZE + 2].copy_from_slice(&self.eval.to_le_bytes());
+        out[PackedBoard::SIZE + 2] = self.wdl;
+        out[PackedBoard::SIZE + 3] = self.extra;
+        out
+    }
+
+    /// Deserialises a record from little-endian bytes.
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            board: PackedBoard::from_bytes(bytes[..PackedBoard::SIZE].try_into().unwrap()),
+            eval: i16::from_le_bytes(
+                bytes[PackedBoard::SIZE..PackedBoard::SIZE + 2].try_into().unwrap(),
+            ),
+            wdl: bytes[PackedBoard::SIZE + 2],
+            extra: bytes[PackedBoard::SIZE + 3],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{packed::PackedBoard, Board};
+
+    #[test]
+    fn record_round_trip() {
+        crate::magic::initialise();
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let record = PackedRecord::new(PackedBoard::pack(&board), -123, PackedRecord::DRAW);
+        let bytes = record.to_bytes();
+        assert_eq!(bytes.len(), 32);
+        let (unpacked, eval, wdl) = PackedRecord::from_bytes(&bytes).unpack().unwrap();
+        assert_eq!(unpacked.fen(), board.fen());
+        assert_eq!(eval, -123);
+        assert_eq!(wdl, PackedRecord::DRAW);
+    }
+}
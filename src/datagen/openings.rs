@@ -0,0 +1,268 @@
+use std::{fmt::Display, path::Path, str::FromStr};
+
+use rand::Rng;
+
+use crate::board::Board;
+
+/// How to pick starting positions from an opening book.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum BookOrder {
+    /// Walk through the book in order, wrapping around at the end.
+    /// Games are numbered across all threads, so every line is used before any is repeated.
+    Sequential,
+    /// Pick a uniformly random line for every game.
+    Random,
+}
+
+/// Where games start from when no opening book is given.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum StartPositions {
+    /// The standard chess starting position.
+    Standard,
+    /// A random Chess960 position, with the same back-rank arrangement for both sides.
+    Chess960,
+    /// A random double-Chess960 position, with independent arrangements for white and black.
+    DoubleChess960,
+}
+
+impl StartPositions {
+    /// The short name of the mode, as used in the short def string.
+    pub const fn short_name(self) -> &'static str {
+        match self {
+            Self::Standard => "std",
+            Self::Chess960 => "frc",
+            Self::DoubleChess960 => "dfrc",
+        }
+    }
+
+    /// Generates the FEN of a random starting position in this mode.
+    pub fn random_fen(self, rng: &mut impl Rng) -> String {
+        match self {
+            Self::Standard => Board::STARTING_FEN.into(),
+            Self::Chess960 => {
+                let index = rng.gen_range(0..960);
+                chess960_fen(index, index)
+            }
+            Self::DoubleChess960 => chess960_fen(rng.gen_range(0..960), rng.gen_range(0..960)),
+        }
+    }
+}
+
+/// Builds the back rank of Chess960 position number `index` (0..960),
+/// using the Scharnagl numbering, in which position 518 is the standard arrangement.
+fn chess960_back_rank(index: usize) -> [u8; 8] {
+    // the placements of the two knights among the five squares left after the bishops and queen.
+    const KNIGHT_PLACEMENTS: [(usize, usize); 10] =
+        [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+    debug_assert!(index < 960, "Chess960 positions are numbered 0 to 959");
+    let mut rank = [0; 8];
+    let mut n = index;
+    rank[n % 4 * 2 + 1] = b'B';
+    n /= 4;
+    rank[n % 4 * 2] = b'B';
+    n /= 4;
+    let mut place_in_nth_empty = |piece: u8, nth: usize| {
+        let file = (0..8).filter(|&file| rank[file] == 0).nth(nth).unwrap();
+        rank[file] = piece;
+    };
+    place_in_nth_empty(b'Q', n % 6);
+    n /= 6;
+    let (first, second) = KNIGHT_PLACEMENTS[n];
+    // place the second knight first, so that placing the first doesn't shift its index.
+    place_in_nth_empty(b'N', second);
+    place_in_nth_empty(b'N', first);
+    // the king always goes between the two rooks.
+    for piece in [b'R', b'K', b'R'] {
+        place_in_nth_empty(piece, 0);
+    }
+    rank
+}
+
+/// Builds the FEN of a double-Chess960 position with the given white and black arrangements.
+///
+/// Castling rights are granted with both rooks, and written in Shredder-FEN form
+/// (the files of the castling rooks), so that no reader can mistake them for standard rights.
+/// The board itself writes rights back out as X-FEN, in which they are `KQkq` again.
+fn chess960_fen(white_index: usize, black_index: usize) -> String {
+    let white = chess960_back_rank(white_index);
+    let black = chess960_back_rank(black_index);
+    let rook_files = |rank: [u8; 8]| {
+        // rooks always flank the king, so the kingside rook is the second one.
+        let mut files = (b'A'..).zip(rank).filter(|&(_, p)| p == b'R').map(|(file, _)| file);
+        let queenside = files.next().unwrap();
+        let kingside = files.next().unwrap();
+        [kingside, queenside]
+    };
+    let castling = rook_files(white)
+        .into_iter()
+        .chain(rook_files(black).map(|file| file.to_ascii_lowercase()))
+        .map(char::from)
+        .collect::<String>();
+    format!(
+        "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w {castling} - 0 1",
+        black.iter().map(|&p| char::from(p.to_ascii_lowercase())).collect::<String>(),
+        white.iter().map(|&p| char::from(p)).collect::<String>(),
+    )
+}
+
+/// A set of starting positions for self-play, loaded from an EPD or FEN file.
+pub struct OpeningBook {
+    positions: Vec<String>,
+}
+
+impl OpeningBook {
+    /// Loads a book with one position per line.
+    /// Lines may be full FENs or EPD records, in which case the move counters default to "0 1".
+    /// Blank lines are ignored, and every position is checked to be a valid FEN.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read opening book {}: {e}", path.display()))?;
+        let mut board = Board::new();
+        let mut positions = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fen = epd_to_fen(line);
+            board.set_from_fen(&fen).map_err(|e| {
+                format!("Invalid position on line {} of {}: {e}", line_no + 1, path.display())
+            })?;
+            positions.push(fen);
+        }
+        if positions.is_empty() {
+            return Err(format!("Opening book {} contains no positions", path.display()));
+        }
+        Ok(Self { positions })
+    }
+
+    /// The number of positions in the book.
+    pub const fn num_positions(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Picks the FEN of the starting position for game number `game_index`.
+    pub fn pick(&self, order: BookOrder, game_index: usize, rng: &mut impl Rng) -> &str {
+        let idx = match order {
+            BookOrder::Sequential => game_index % self.num_positions(),
+            BookOrder::Random => rng.gen_range(0..self.num_positions()),
+        };
+        &self.positions[idx]
+    }
+}
+
+/// Converts an EPD-style line into a full FEN, keeping the move counters if they are present.
+fn epd_to_fen(line: &str) -> String {
+    let mut parts = line.split_whitespace();
+    let mut fen = parts.by_ref().take(4).collect::<Vec<_>>();
+    let counters = parts.take(2).collect::<Vec<_>>();
+    if counters.len() == 2 && counters.iter().all(|c| c.parse::<u32>().is_ok()) {
+        fen.extend(counters);
+    } else {
+        fen.extend(["0", "1"]);
+    }
+    fen.join(" ")
+}
+
+impl FromStr for BookOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" | "seq" => Ok(Self::Sequential),
+            "random" | "rand" => Ok(Self::Random),
+            _ => Err(format!("Invalid book order: {s}, expected \"sequential\" or \"random\"")),
+        }
+    }
+}
+
+impl Display for BookOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sequential => write!(f, "sequential"),
+            Self::Random => write!(f, "random"),
+        }
+    }
+}
+
+impl FromStr for StartPositions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" | "std" => Ok(Self::Standard),
+            "chess960" | "frc" => Ok(Self::Chess960),
+            "double_chess960" | "dfrc" => Ok(Self::DoubleChess960),
+            _ => Err(format!(
+                "Invalid start positions: {s}, expected \"standard\", \"chess960\", or \"double_chess960\""
+            )),
+        }
+    }
+}
+
+impl Display for StartPositions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Standard => write!(f, "standard"),
+            Self::Chess960 => write!(f, "chess960"),
+            Self::DoubleChess960 => write!(f, "double_chess960"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn epd_lines_become_fens() {
+        assert_eq!(
+            epd_to_fen("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6"),
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1"
+        );
+        assert_eq!(
+            epd_to_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(
+            epd_to_fen("r3k2r/8/8/8/8/8/8/R3K2R b Kq - 3 17"),
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 3 17"
+        );
+    }
+
+    #[test]
+    fn chess960_numbering() {
+        crate::magic::initialise();
+        assert_eq!(&chess960_back_rank(518), b"RNBQKBNR");
+        assert_eq!(&chess960_back_rank(0), b"BBQNNRKR");
+        assert_eq!(&chess960_back_rank(959), b"RKRNNQBB");
+        assert_eq!(chess960_fen(518, 518), Board::STARTING_FEN.replace("KQkq", "HAha"));
+        assert_eq!(
+            chess960_fen(0, 959),
+            "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFca - 0 1"
+        );
+        assert_eq!(
+            Board::from_fen(&chess960_fen(518, 518)).unwrap(),
+            Board::from_fen(Board::STARTING_FEN).unwrap()
+        );
+        let mut arrangements = std::collections::HashSet::new();
+        for index in 0..960 {
+            let rank = chess960_back_rank(index);
+            assert!(arrangements.insert(rank));
+            let fen = chess960_fen(index, 959 - index);
+            let mut board = Board::from_fen(&fen).unwrap();
+            assert_eq!(board.castling_rights(), 0b1111);
+            assert_eq!(board.fen(), fen.replace(fen.split(' ').nth(2).unwrap(), "KQkq"));
+            assert_eq!(Board::from_fen(&board.fen()).unwrap(), board);
+            // a king and rook standing on each other's destinations can castle immediately.
+            for m in board.legal_moves() {
+                let before = board.clone();
+                assert!(board.make_move_base(m));
+                board.unmake_move_base();
+                assert_eq!(board, before, "{m} in {fen}");
+            }
+        }
+    }
+}
@@ -28,7 +28,94 @@ impl Display for MoveParseError {
     }
 }
 
-#[cfg(debug_assertions)]
 pub type PositionValidityError = String;
 
 pub type FenParseError = String;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackedBoardError {
+    InvalidPieceCode(u8),
+    TooManyPieces(u32),
+    TrailingPieceData,
+    InvalidEnPassantSquare(u8),
+    InvalidUnmovedRook(String),
+    InvalidFullmoveNumber,
+}
+impl Display for PackedBoardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidPieceCode(code) => write!(f, "Invalid packed piece code {code}"),
+            Self::TooManyPieces(n) => {
+                write!(f, "Occupancy has {n} pieces, at most 32 can be packed")
+            }
+            Self::TrailingPieceData => write!(f, "Piece data present beyond the occupied squares"),
+            Self::InvalidEnPassantSquare(sq) => write!(f, "Invalid en passant square {sq}"),
+            Self::InvalidUnmovedRook(sq) => write!(f, "Unmoved rook on non-castling square {sq}"),
+            Self::InvalidFullmoveNumber => write!(f, "Fullmove number must be at least 1"),
+        }
+    }
+}
+
+/// Where in a data file a record was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataLocation {
+    /// A line of a text file, numbered from 1.
+    Line(usize),
+    /// A record of a binary file, numbered from 0.
+    Record(usize),
+}
+impl Display for DataLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Line(n) => write!(f, "line {n}"),
+            Self::Record(n) => write!(f, "record {n}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataErrorKind {
+    Io(String),
+    Truncated,
+    Malformed(String),
+    InvalidFen(FenParseError),
+    InvalidEval(String),
+    InvalidWdl(String),
+    InvalidPackedBoard(PackedBoardError),
+    InvalidPosition(PositionValidityError),
+}
+impl DataErrorKind {
+    /// Whether the error means that the rest of the file can't be trusted,
+    /// so that it can't be skipped like an invalid record.
+    pub const fn is_fatal(&self) -> bool {
+        matches!(self, Self::Io(_) | Self::Truncated)
+    }
+}
+impl Display for DataErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Truncated => write!(f, "File ends part of the way through a record"),
+            Self::Malformed(e) => write!(f, "Malformed record: {e}"),
+            Self::InvalidFen(e) => write!(f, "Invalid FEN: {e}"),
+            Self::InvalidEval(eval) => write!(f, "Invalid eval \"{eval}\""),
+            Self::InvalidWdl(wdl) => write!(f, "Invalid WDL \"{wdl}\""),
+            Self::InvalidPackedBoard(e) => write!(f, "Invalid packed board: {e}"),
+            Self::InvalidPosition(e) => write!(f, "Invalid position: {e}"),
+        }
+    }
+}
+
+/// An error in a data file, with the file and the place in it where the error was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataError {
+    pub file: std::path::PathBuf,
+    pub location: DataLocation,
+    pub kind: DataErrorKind,
+}
+impl Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}, {}: {}", self.file.display(), self.location, self.kind)
+    }
+}
+impl std::error::Error for DataError {}
@@ -1,5 +1,7 @@
+mod png;
+
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{BufWriter, Write},
 };
 
@@ -83,6 +85,63 @@ pub fn inferno_colour_map(value: u8) -> u32 {
     u32::from(v[2]) | (u32::from(v[1]) << 8) | (u32::from(v[0]) << 16)
 }
 
+/// The colour of text and labels.
+pub const TEXT_COLOUR: u32 = 0xFF_FFFF;
+
+/// Glyphs for a 3x5 pixel font, one row of three bits per line, leftmost pixel highest.
+const fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0; 5],
+    }
+}
+
+/// The height of a line of text drawn at the given scale.
+pub const fn text_height(scale: usize) -> usize {
+    5 * scale
+}
+
+/// The width of a line of text drawn at the given scale.
+pub fn text_width(text: &str, scale: usize) -> usize {
+    (text.chars().count() * 4).saturating_sub(1) * scale
+}
+
 impl Image {
     pub fn zeroed(width: usize, height: usize) -> Self {
         Self { data: vec![0; width * height], height, width }
@@ -96,6 +155,40 @@ impl Image {
         self.data[y * self.width + x] = value;
     }
 
+    /// Fill a rectangle with a colour, clipping it to the image.
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, value: u32) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                self.set(col, row, value);
+            }
+        }
+    }
+
+    /// Copy another image into this one, with its top left corner at `(x, y)`.
+    pub fn blit(&mut self, other: &Self, x: usize, y: usize) {
+        for (row, pixels) in other.rows().enumerate() {
+            for (col, &pixel) in pixels.iter().enumerate() {
+                if x + col < self.width && y + row < self.height {
+                    self.set(x + col, y + row, pixel);
+                }
+            }
+        }
+    }
+
+    /// Draw a line of text, with its top left corner at `(x, y)`.
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize, value: u32) {
+        for (i, c) in text.chars().enumerate() {
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        let px = x + (i * 4 + col) * scale;
+                        self.fill(px, y + row * scale, scale, scale, value);
+                    }
+                }
+            }
+        }
+    }
+
     pub const fn width(&self) -> usize {
         self.width
     }
@@ -104,6 +197,28 @@ impl Image {
         self.height
     }
 
+    /// Write the image to a file, as a PNG or a TGA according to its extension.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tga")) {
+            self.save_as_tga(path);
+        } else {
+            self.save_as_png(path);
+        }
+    }
+
+    /// Write the image to a PNG file with the given name.
+    pub fn save_as_png(&self, path: impl AsRef<std::path::Path>) {
+        #![allow(clippy::cast_possible_truncation)]
+        let rgb = self
+            .data
+            .iter()
+            .flat_map(|&pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])
+            .collect::<Vec<_>>();
+        fs::write(&path, png::encode(self.width, self.height, &rgb)).unwrap();
+        println!("Wrote {}", path.as_ref().display());
+    }
+
     // Write the image to a TGA file with the given name.
     // Format specification: http://www.gamers.org/dEngine/quake3/TGA.txt
     pub fn save_as_tga<P>(&self, filename: P)
@@ -145,3 +260,121 @@ impl Image {
         println!("Wrote {}", filename.as_ref().display());
     }
 }
+
+/// A grid of 8x8 heatmaps, such as the weights of a neuron for each piece or a set of
+/// piece-square tables, with labelled rows and columns.
+///
+/// Each board is coloured on its own scale, from its smallest value to its largest, and
+/// is drawn with the eighth rank at the top. Squares without a value are left black.
+pub struct BoardGrid {
+    columns: Vec<String>,
+    rows: Vec<(String, Vec<[Option<f32>; 64]>)>,
+}
+
+impl BoardGrid {
+    pub fn new<S: Into<String>>(columns: impl IntoIterator<Item = S>) -> Self {
+        Self { columns: columns.into_iter().map(Into::into).collect(), rows: Vec::new() }
+    }
+
+    /// Add a row of boards, one for each column.
+    pub fn add_row(&mut self, label: impl Into<String>, boards: Vec<[Option<f32>; 64]>) {
+        assert_eq!(boards.len(), self.columns.len(), "a row must have a board for each column");
+        self.rows.push((label.into(), boards));
+    }
+
+    /// Render the grid, drawing each square as a `scale` by `scale` block.
+    pub fn render(&self, scale: usize) -> Image {
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let text_scale = (scale / 2).max(1);
+        let gap = scale.max(2);
+        let board_size = 8 * scale;
+        let label_width =
+            self.rows.iter().map(|(label, _)| text_width(label, text_scale)).max().unwrap_or(0);
+        let left = label_width + gap;
+        let top = text_height(text_scale) + gap;
+        let mut image = Image::zeroed(
+            left + self.columns.len() * (board_size + gap),
+            top + self.rows.len() * (board_size + gap),
+        );
+
+        for (col, label) in self.columns.iter().enumerate() {
+            let x = left
+                + col * (board_size + gap)
+                + board_size.saturating_sub(text_width(label, text_scale)) / 2;
+            image.draw_text(x, 0, label, text_scale, TEXT_COLOUR);
+        }
+        for (row, (label, boards)) in self.rows.iter().enumerate() {
+            let y = top + row * (board_size + gap);
+            let label_y = y + board_size.saturating_sub(text_height(text_scale)) / 2;
+            image.draw_text(0, label_y, label, text_scale, TEXT_COLOUR);
+            for (col, board) in boards.iter().enumerate() {
+                let x = left + col * (board_size + gap);
+                let values = board.iter().flatten();
+                let min = values.clone().copied().fold(f32::INFINITY, f32::min);
+                let max = values.copied().fold(f32::NEG_INFINITY, f32::max);
+                for (square, value) in board.iter().enumerate() {
+                    let Some(value) = value else { continue };
+                    // boards where every value is the same are drawn in the middle of the scale.
+                    let intensity = if max > min { (value - min) / (max - min) } else { 0.5 };
+                    let colour = inferno_colour_map((intensity * 255.0).round() as u8);
+                    let (rank, file) = (square / 8, square % 8);
+                    image.fill(x + file * scale, y + (7 - rank) * scale, scale, scale, colour);
+                }
+            }
+        }
+        image
+    }
+}
+
+/// Tile images into rows of `columns`, each with a label above it.
+pub fn atlas(tiles: &[(String, Image)], columns: usize, scale: usize) -> Image {
+    let gap = scale.max(2);
+    let label_height = text_height(scale) + gap;
+    let tile_width = tiles.iter().map(|(_, tile)| tile.width()).max().unwrap_or(0) + gap;
+    let tile_height =
+        tiles.iter().map(|(_, tile)| tile.height()).max().unwrap_or(0) + label_height + gap;
+    let mut image =
+        Image::zeroed(columns * tile_width, tiles.len().div_ceil(columns) * tile_height);
+    for (i, (label, tile)) in tiles.iter().enumerate() {
+        let (x, y) = ((i % columns) * tile_width, (i / columns) * tile_height);
+        image.draw_text(x, y, label, scale, TEXT_COLOUR);
+        image.blit(tile, x, y + label_height);
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_text() {
+        let mut image = Image::zeroed(16, 6);
+        image.draw_text(1, 0, "1-", 1, TEXT_COLOUR);
+        assert_eq!(text_width("1-", 1), 7);
+        let lit = image
+            .rows()
+            .map(|row| row.iter().map(|&p| u8::from(p != 0)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(lit[0][..8], [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(lit[2][..8], [0, 0, 1, 0, 0, 1, 1, 1]);
+        assert_eq!(lit[4][..8], [0, 1, 1, 1, 0, 0, 0, 0]);
+        assert!(lit[5].iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn boards_have_rank_eight_at_the_top() {
+        let mut board = [None; 64];
+        board[0] = Some(0.0); // a1
+        board[63] = Some(1.0); // h8
+        let mut grid = BoardGrid::new(["X"]);
+        grid.add_row("Y", vec![board]);
+        let image = grid.render(1);
+        let rows = image.rows().collect::<Vec<_>>();
+        // the board starts after the label and a gap, in both directions.
+        let (left, top) = (3 + 2, 5 + 2);
+        assert_eq!(rows[top][left + 7], super::inferno_colour_map(255));
+        assert_eq!(rows[top + 7][left], super::inferno_colour_map(0));
+        assert_eq!(rows[top][left], 0);
+    }
+}
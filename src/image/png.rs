@@ -0,0 +1,274 @@
+//! A minimal PNG encoder, for 8-bit RGB images.
+//!
+//! Pixel data is compressed with DEFLATE, using the fixed Huffman codes and a simple
+//! hash-based LZ77 matcher, which does well on the large flat areas of the visualisations.
+//! Format specifications: <https://www.w3.org/TR/png/> and RFC 1950/1951.
+
+/// The eight bytes that every PNG file starts with.
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The size of the DEFLATE sliding window.
+const WINDOW: usize = 1 << 15;
+/// The shortest and longest matches that DEFLATE can encode.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// The number of bits of the hash of the next three bytes, used to find matches.
+const HASH_BITS: u32 = 15;
+
+/// The smallest length for each length code (257..=285), and the number of extra bits it has.
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+/// The smallest distance for each distance code, and the number of extra bits it has.
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+static CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 0 { c >> 1 } else { 0xEDB8_8320 ^ (c >> 1) };
+            k += 1;
+        }
+        table[n as usize] = c;
+        n += 1;
+    }
+    table
+};
+
+/// The CRC-32 checksum that ends each PNG chunk.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &b| CRC_TABLE[((crc ^ u32::from(b)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// The Adler-32 checksum that ends a zlib stream.
+pub fn adler32(bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1, 0);
+    // 5552 is the most bytes that can be summed before `b` could overflow.
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Writes a stream of bits, least significant first, as DEFLATE requires.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    const fn new() -> Self {
+        Self { bytes: Vec::new(), buffer: 0, len: 0 }
+    }
+
+    fn write(&mut self, value: u32, bits: u32) {
+        #![allow(clippy::cast_possible_truncation)]
+        self.buffer |= u64::from(value) << self.len;
+        self.len += bits;
+        while self.len >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is stored most significant bit first.
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    /// Write a literal byte or length code (0..=287) with the fixed Huffman codes.
+    fn write_symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+        self.bytes
+    }
+}
+
+/// The code for a match length or distance, as an index into its tables.
+fn code_for(value: usize, bases: &[u16]) -> usize {
+    bases.partition_point(|&base| usize::from(base) <= value) - 1
+}
+
+/// Compress `data` into a single DEFLATE block with the fixed Huffman codes.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    #![allow(clippy::cast_possible_truncation)]
+    let hash = |i: usize| {
+        let key = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+    // the most recent position at which each hash was seen, plus one.
+    let mut last_seen = vec![0; 1 << HASH_BITS];
+
+    let mut out = BitWriter::new();
+    // a single, final block.
+    out.write(1, 1);
+    out.write(1, 2);
+    let mut i = 0;
+    while i < data.len() {
+        let mut length = 0;
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            let candidate = last_seen[h];
+            last_seen[h] = i + 1;
+            if candidate > 0 && i - (candidate - 1) <= WINDOW {
+                let start = candidate - 1;
+                let limit = MAX_MATCH.min(data.len() - i);
+                length = (0..limit).take_while(|&k| data[start + k] == data[i + k]).count();
+                if length >= MIN_MATCH {
+                    let distance = i - start;
+                    let code = code_for(length, &LENGTH_BASES);
+                    out.write_symbol(257 + code as u32);
+                    out.write(
+                        (length - usize::from(LENGTH_BASES[code])) as u32,
+                        u32::from(LENGTH_EXTRA_BITS[code]),
+                    );
+                    let code = code_for(distance, &DISTANCE_BASES);
+                    out.write_code(code as u32, 5);
+                    out.write(
+                        (distance - usize::from(DISTANCE_BASES[code])) as u32,
+                        u32::from(DISTANCE_EXTRA_BITS[code]),
+                    );
+                    // keep the hashes of the matched bytes, so later matches can find them.
+                    for j in i + 1..(i + length).min(data.len() - MIN_MATCH + 1) {
+                        last_seen[hash(j)] = j + 1;
+                    }
+                }
+            }
+        }
+        if length >= MIN_MATCH {
+            i += length;
+        } else {
+            out.write_symbol(u32::from(data[i]));
+            i += 1;
+        }
+    }
+    out.write_symbol(256);
+    out.finish()
+}
+
+/// Wrap DEFLATE-compressed data in a zlib stream.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32K window, no preset dictionary, and a check value that makes the header a multiple of 31.
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    let start = out.len() + 4;
+    out.extend(u32::try_from(data.len()).unwrap().to_be_bytes());
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// Filter a row of pixels against the row above, prefixed with the filter type used.
+///
+/// The filters tried are none, sub (the difference from the pixel to the left), and up
+/// (the difference from the pixel above), and the one whose output is closest to zero is
+/// chosen, which is the heuristic suggested by the specification.
+fn filter_row(row: &[u8], above: &[u8]) -> Vec<u8> {
+    let sub = (0..row.len()).map(|i| row[i].wrapping_sub(if i < 3 { 0 } else { row[i - 3] }));
+    let up = row.iter().zip(above).map(|(&x, &b)| x.wrapping_sub(b));
+    let cost = |bytes: &[u8]| {
+        bytes.iter().map(|&b| u64::from(b.cast_signed().unsigned_abs())).sum::<u64>()
+    };
+    [
+        std::iter::once(0).chain(row.iter().copied()).collect::<Vec<_>>(),
+        std::iter::once(1).chain(sub).collect(),
+        std::iter::once(2).chain(up).collect(),
+    ]
+    .into_iter()
+    .min_by_key(|filtered| cost(&filtered[1..]))
+    .unwrap()
+}
+
+/// Encode an image as a PNG file, given its pixels as rows of RGB triples.
+pub fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "pixel data doesn't match the image size");
+    let mut header = Vec::with_capacity(13);
+    header.extend(u32::try_from(width).unwrap().to_be_bytes());
+    header.extend(u32::try_from(height).unwrap().to_be_bytes());
+    // 8 bits per channel, truecolour, deflate, adaptive filtering, no interlacing.
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut scanlines = Vec::with_capacity(rgb.len() + height);
+    let blank = vec![0; width * 3];
+    let mut previous = blank.as_slice();
+    for row in rgb.chunks(width * 3) {
+        scanlines.extend(filter_row(row, previous));
+        previous = row;
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, *b"IHDR", &header);
+    write_chunk(&mut out, *b"IDAT", &zlib(&scanlines));
+    write_chunk(&mut out, *b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&vec![0xFF; 100_000]), 0x149A_302C);
+    }
+
+    #[test]
+    fn chunks_are_well_formed() {
+        let rgb = (0..20 * 10 * 3).map(|i| u8::try_from(i % 7).unwrap()).collect::<Vec<_>>();
+        let png = encode(20, 10, &rgb);
+        assert_eq!(png[..8], SIGNATURE);
+        let mut rest = &png[8..];
+        let mut kinds = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (body, tail) = rest[4..].split_at(len + 4);
+            let crc = u32::from_be_bytes(tail[..4].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            kinds.push(String::from_utf8(body[..4].to_vec()).unwrap());
+            if kinds.len() == 1 {
+                assert_eq!(body[4..12], [0, 0, 0, 20, 0, 0, 0, 10]);
+            }
+            rest = &tail[4..];
+        }
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+    }
+}
@@ -142,7 +142,6 @@ pub static PIECE_BIG: [bool; 13] =
 pub static PIECE_MAJ: [bool; 13] =
     [false, false, false, false, true, true, false, false, false, false, true, true, false];
 /// knights and bishops.
-#[cfg(debug_assertions)]
 pub static PIECE_MIN: [bool; 13] =
     [false, false, true, true, false, false, false, false, true, true, false, false, false];
 
@@ -190,8 +189,12 @@ pub fn info_dump() {
         "Number of HCE parameters: {}",
         board::evaluation::parameters::EvalParams::default().vectorise().len()
     );
-    println!("Number of NNUE parameters: {}", nnue::network::NNUEParams::num_params());
-    println!("Size of NNUE network: {} bytes", std::mem::size_of::<nnue::network::NNUEParams>());
+    println!("Number of NNUE parameters: {}", nnue::network::NNUE.num_params());
+    println!(
+        "Size of NNUE network: {} bytes",
+        std::mem::size_of::<nnue::network::NNUEParams>()
+            + std::mem::size_of_val(nnue::network::NNUE.feature_weights.as_slice())
+    );
     println!(
         "Size of a transposition table entry: {} bytes",
         std::mem::size_of::<transpositiontable::TTEntry>()
@@ -6,7 +6,7 @@
 
 use crate::{
     board::evaluation::parameters::EvalParams,
-    nnue::{convert, network},
+    nnue::{convert, inspect, network, quantise, train},
     search::parameters::SearchParams,
 };
 
@@ -46,6 +46,7 @@ pub static NAME: &str = "Viridithas";
 pub static VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
+    #![allow(clippy::too_many_lines)]
     #[cfg(debug_assertions)]
     std::env::set_var("RUST_BACKTRACE", "1");
 
@@ -59,16 +60,35 @@ fn main() {
 
     let cli = <cli::Cli as clap::Parser>::parse();
 
+    if let Some(address) = cli.datagen_coordinator {
+        let config = datagen::CoordinatorConfig {
+            unit_games: cli.datagen_unit_games,
+            unit_timeout: std::time::Duration::from_secs(cli.datagen_unit_timeout),
+        };
+        return datagen::coordinator_main(&address, cli.datagen.flatten().as_deref(), &config);
+    }
+
+    if let Some(address) = cli.datagen_worker {
+        return datagen::worker_main(&address, cli.datagen_worker_threads);
+    }
+
     if let Some(config) = cli.datagen {
         return datagen::gen_data_main(config.as_deref());
     }
 
+    if let Some(data_dir) = cli.datagen_resume {
+        return datagen::resume_data_main(&data_dir);
+    }
+
     let eparams = cli.eparams.clone().map_or_else(EvalParams::default, |p| {
         EvalParams::from_file(p).expect("failed to load evaluation parameters")
     });
 
-    assert!([0, 2].contains(&cli.merge.len()), "merge requires exactly two paths");
+    assert!(cli.merge.len() != 1, "merge requires at least two paths");
     assert!([0, 2].contains(&cli.jsontobin.len()), "jsontobin requires exactly two paths");
+    assert!([0, 2].contains(&cli.quantreport.len()), "quantreport requires exactly two paths");
+    assert!([0, 3].contains(&cli.netdiff.len()), "netdiff requires exactly three paths");
+    assert!(cli.netinfo.len() <= 2, "netinfo takes at most two paths");
 
     if cli.gensource {
         return piecesquaretable::tables::printout_pst_source(&eparams.piece_square_tables);
@@ -78,10 +98,30 @@ fn main() {
         return perft::gamut();
     }
 
+    let policy = if cli.skipinvalid {
+        convert::InvalidDataPolicy::Skip
+    } else {
+        convert::InvalidDataPolicy::Strict
+    };
+
     if let Some(path) = cli.tune {
-        return texel::tune(cli.resume, cli.examples, &eparams, cli.limitparams.as_deref(), path);
+        return texel::tune(
+            cli.resume,
+            cli.examples,
+            &eparams,
+            cli.limitparams.as_deref(),
+            path,
+            policy,
+        )
+        .unwrap();
     }
 
+    let dedup_key =
+        if cli.dedupposition { convert::DedupKey::Position } else { convert::DedupKey::Fen };
+    let data_memory = cli.datamemory * 1_000_000;
+
+    let eval_mode = convert::EvalMode::from_name(&cli.nnuemode, cli.nnuedepth, cli.nnuenodes);
+
     if let Some(input_file) = cli.nnueconversionpath {
         let output_file = cli.output.unwrap_or_else(|| {
             let mut path = input_file.clone();
@@ -91,10 +131,11 @@ fn main() {
         return convert::evaluate_fens(
             input_file,
             output_file,
-            convert::Format::OurTexel,
-            cli.nnuedepth,
+            &convert::OurTexel,
+            eval_mode.unwrap(),
             true,
             cli.nnuefornnue,
+            policy,
         )
         .unwrap();
     } else if let Some(path) = cli.nnuereanalysepath {
@@ -106,10 +147,11 @@ fn main() {
         return convert::evaluate_fens(
             path,
             output_path,
-            convert::Format::Marlinflow,
-            cli.nnuedepth,
+            &convert::Marlinflow,
+            eval_mode.unwrap(),
             true,
             cli.nnuefornnue,
+            policy,
         )
         .unwrap();
     } else if let Some(path) = cli.dedup {
@@ -118,15 +160,128 @@ fn main() {
             path.set_extension("nnuedata");
             path
         });
-        return convert::dedup(path, output_path).unwrap();
-    } else if let [path_1, path_2] = cli.merge.as_slice() {
+        return convert::dedup_files(
+            &[path],
+            &output_path,
+            cli.dataformat.as_deref(),
+            dedup_key,
+            data_memory,
+            policy,
+        )
+        .unwrap();
+    } else if !cli.merge.is_empty() {
         let output_path = cli.output.unwrap_or_else(|| {
             // create merged.nnuedata in the current directory
             let mut path = std::path::PathBuf::from(".");
             path.push("merged.nnuedata");
             path
         });
-        return convert::merge(path_1, path_2, output_path).unwrap();
+        return convert::dedup_files(
+            &cli.merge,
+            &output_path,
+            cli.dataformat.as_deref(),
+            dedup_key,
+            data_memory,
+            policy,
+        )
+        .unwrap();
+    } else if let Some(first) = cli.shuffle.first() {
+        let output_path = cli.output.unwrap_or_else(|| with_suffix(first, "shuffled"));
+        let seed = cli.shuffleseed.unwrap_or_else(rand::random);
+        println!("Shuffling with seed {seed}");
+        let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(seed);
+        return convert::shuffle_files(
+            &cli.shuffle,
+            &output_path,
+            cli.dataformat.as_deref(),
+            data_memory,
+            policy,
+            &mut rng,
+        )
+        .unwrap();
+    } else if let Some(path) = cli.txttobin {
+        let output_path = cli.output.unwrap_or_else(|| {
+            let mut path = path.clone();
+            path.set_extension("bin");
+            path
+        });
+        return convert::text_to_binary(path, output_path, policy).unwrap();
+    } else if let Some(path) = cli.bintotxt {
+        let output_path = cli.output.unwrap_or_else(|| {
+            let mut path = path.clone();
+            path.set_extension("txt");
+            path
+        });
+        return convert::binary_to_text(path, output_path, policy).unwrap();
+    } else if let Some(path) = cli.expandgames {
+        let output_path = cli.output.unwrap_or_else(|| {
+            let mut path = path.clone();
+            path.set_extension("bin");
+            path
+        });
+        return convert::expand_games(path, output_path).unwrap();
+    } else if let Some(path) = cli.filter {
+        let kept_path = cli.output.unwrap_or_else(|| with_suffix(&path, "kept"));
+        let rejected_path = cli.rejected.unwrap_or_else(|| with_suffix(&path, "rejected"));
+        let format = convert::format_for_path(&path, cli.dataformat.as_deref()).unwrap();
+        let predicates = cli
+            .keep
+            .iter()
+            .map(|s| s.parse::<convert::Predicate>())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        return convert::filter(&path, &kept_path, &rejected_path, format, &predicates, policy)
+            .unwrap();
+    } else if let Some(path) = cli.train {
+        let config = train::TrainConfig {
+            data: path,
+            output: cli.output.unwrap_or_else(|| "nnue-training".into()),
+            epochs: cli.trainepochs,
+            batch_size: cli.trainbatch,
+            lr: cli.trainlr,
+            lr_step: cli.trainlrstep,
+            lr_gamma: cli.trainlrgamma,
+            wdl: cli.trainwdl,
+            scale: cli.trainscale,
+            validation: cli.trainvalidation,
+            threads: cli.trainthreads.unwrap_or_else(num_cpus::get),
+            resume: cli.trainresume,
+            seed: cli.trainseed,
+        };
+        return train::train(&config, policy).unwrap();
+    } else if let [net_path, data_path] = cli.quantreport.as_slice() {
+        let format = convert::format_for_path(data_path, cli.dataformat.as_deref()).unwrap();
+        return quantise::accuracy_report(net_path, data_path, format, policy).unwrap();
+    } else if let [a_path, b_path, data_path] = cli.netdiff.as_slice() {
+        let format = convert::format_for_path(data_path, cli.dataformat.as_deref()).unwrap();
+        return inspect::net_diff(a_path, b_path, data_path, format, policy).unwrap();
+    } else if let Some((net_path, data_path)) = cli.netinfo.split_first() {
+        let positions = data_path.first().map(|path| {
+            (path.as_path(), convert::format_for_path(path, cli.dataformat.as_deref()).unwrap())
+        });
+        return inspect::net_info(net_path, positions, policy).unwrap();
+    } else if let Some(path) = cli.datastats {
+        let format = convert::format_for_path(&path, cli.dataformat.as_deref()).unwrap();
+        return convert::data_stats(
+            &path,
+            cli.output.as_deref(),
+            format,
+            policy,
+            cli.datastatsjson,
+        )
+        .unwrap();
+    } else if let Some(path) = cli.tbrescore {
+        let tb_path = cli.tbpath.expect("tbrescore requires a tablebase path (--tbpath)");
+        let output_path = cli.output.unwrap_or_else(|| with_suffix(&path, "tb"));
+        let options = convert::TbRescoreOptions {
+            win_score: cli.tbwinscore,
+            cursed_results: if cli.tbcursedaswins {
+                convert::CursedResults::WinsAndLosses
+            } else {
+                convert::CursedResults::Draws
+            },
+        };
+        return convert::tb_rescore(path, output_path, &tb_path, options, policy).unwrap();
     }
 
     if cli.info {
@@ -140,7 +295,11 @@ fn main() {
     }
 
     if cli.vispsqt {
-        return piecesquaretable::render_pst_table(&eparams.piece_square_tables);
+        piecesquaretable::render_pst_table(&eparams.piece_square_tables);
+        if let Some(path) = cli.output {
+            piecesquaretable::pst_grid(&eparams.piece_square_tables).render(16).save(path);
+        }
+        return;
     }
 
     if let Some(epd_path) = cli.epdpath.as_deref() {
@@ -152,8 +311,19 @@ fn main() {
     }
 
     if cli.visnnue {
-        return network::visualise_nnue();
+        let path = cli.output.unwrap_or_else(|| "nnue-visualisations".into());
+        return network::visualise_nnue(&path, cli.visnnueatlas);
     }
 
     uci::main_loop(eparams, cli.bench.is_some());
 }
+
+/// Adds a suffix to the name of a file, as in `data.bin` to `data_suffix.bin`.
+fn with_suffix(path: &std::path::Path, suffix: &str) -> std::path::PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = path.extension().map_or_else(
+        || format!("{stem}_{suffix}"),
+        |ext| format!("{stem}_{suffix}.{}", ext.to_string_lossy()),
+    );
+    path.with_file_name(name)
+}
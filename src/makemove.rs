@@ -32,15 +32,3 @@ pub fn hash_ep(key: &mut u64, ep_sq: Square) {
         unsafe { *PIECE_KEYS.get_unchecked(Piece::EMPTY.index()).get_unchecked(ep_sq.index()) };
     *key ^= ep_key;
 }
-
-#[rustfmt::skip]
-pub static CASTLE_PERM_MASKS: [u8; 64] = [
-    13, 15, 15, 15, 12, 15, 15, 14, // 0b1101, 0b1100, 0b1110
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-     7, 15, 15, 15,  3, 15, 15, 11, // 0b0111, 0b0011, 0b1011
-];
@@ -1,5 +1,7 @@
 // use crate::{board::Board, piece::PieceType};
 
+use crate::piece::Colour;
+
 use super::network::Align;
 
 /// Activations of the hidden layer.
@@ -10,9 +12,85 @@ pub struct Accumulator<const HIDDEN: usize> {
 }
 
 impl<const HIDDEN: usize> Accumulator<HIDDEN> {
-    /// Initializes the accumulator with the given bias.
-    pub fn init(&mut self, bias: &[i16; HIDDEN]) {
-        self.white.copy_from_slice(bias);
-        self.black.copy_from_slice(bias);
+    /// The activations from the given side's point of view.
+    pub fn perspective_mut(&mut self, colour: Colour) -> &mut [i16; HIDDEN] {
+        if colour == Colour::WHITE {
+            &mut self.white
+        } else {
+            &mut self.black
+        }
+    }
+}
+
+/// A feature, as its (unbucketed) index from white's and black's point of view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Feature {
+    pub white: usize,
+    pub black: usize,
+}
+
+impl Feature {
+    /// The index of this feature from the given side's point of view.
+    pub const fn index(self, perspective: Colour) -> usize {
+        if perspective.index() == Colour::WHITE.index() {
+            self.white
+        } else {
+            self.black
+        }
+    }
+}
+
+/// The features added and removed by a single move.
+///
+/// No move adds or removes more than two features: castling moves two pieces,
+/// and en passant and capture-promotions remove two pieces and add one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpdateBuffer {
+    add: [Feature; 2],
+    sub: [Feature; 2],
+    n_add: usize,
+    n_sub: usize,
+}
+
+impl UpdateBuffer {
+    pub const fn clear(&mut self) {
+        self.n_add = 0;
+        self.n_sub = 0;
+    }
+
+    pub const fn add(&mut self, feature: Feature) {
+        self.add[self.n_add] = feature;
+        self.n_add += 1;
+    }
+
+    pub const fn sub(&mut self, feature: Feature) {
+        self.sub[self.n_sub] = feature;
+        self.n_sub += 1;
+    }
+
+    /// The features added by the move.
+    pub fn adds(&self) -> &[Feature] {
+        &self.add[..self.n_add]
+    }
+
+    /// The features removed by the move.
+    pub fn subs(&self) -> &[Feature] {
+        &self.sub[..self.n_sub]
+    }
+}
+
+/// A cached accumulator for one king bucket and perspective, along with the pieces it was
+/// computed for, so that a refresh only needs to apply the pieces that have changed since.
+#[derive(Debug, Clone)]
+pub struct RefreshEntry<const HIDDEN: usize> {
+    pub accumulator: Align<[i16; HIDDEN]>,
+    /// Piece bitboards, indexed by colour and then piece type.
+    pub bitboards: [[u64; 6]; 2],
+}
+
+impl<const HIDDEN: usize> RefreshEntry<HIDDEN> {
+    /// An entry for the empty board, whose accumulator is just the bias.
+    pub const fn new(bias: &Align<[i16; HIDDEN]>) -> Self {
+        Self { accumulator: Align(bias.0), bitboards: [[0; 6]; 2] }
     }
 }
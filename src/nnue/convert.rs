@@ -1,311 +1,352 @@
+mod evaluate;
+mod external;
+mod filter;
+mod formats;
+mod stats;
+
 use std::{
-    array,
-    collections::hash_map::DefaultHasher,
     error::Error,
     fs::File,
-    io::{self, BufRead, BufReader, BufWriter, Write},
-    ops::Range,
+    io::{BufReader, BufWriter, Write},
     path::Path,
-    sync::atomic::{self, AtomicBool, AtomicU64},
 };
 
 use crate::{
-    board::{evaluation::is_game_theoretic_score, Board},
-    definitions::{depth::Depth, MEGABYTE},
-    searchinfo::SearchInfo,
-    threadlocal::ThreadData,
-    timemgmt::{SearchLimit, TimeManager},
-    transpositiontable::TT,
+    board::Board,
+    datagen::{gameformat::Game, marlinformat::PackedRecord},
+    tablebases::probe::{self, WDL},
 };
 
-fn batch_convert<const USE_NNUE: bool>(
-    depth: i32,
-    fens: &[String],
-    evals: &mut Vec<Option<i32>>,
-    filter_quiescent: bool,
-    counter: &AtomicU64,
-    printing_thread: bool,
-    start_time: std::time::Instant,
-) {
-    let mut pos = Board::default();
-    let mut tt = TT::new();
-    tt.resize(16 * MEGABYTE);
-    let mut t = ThreadData::new(0, &pos);
-    let mut local_ticker = 0;
-    if printing_thread {
-        let c = counter.load(atomic::Ordering::SeqCst);
-        #[allow(clippy::cast_precision_loss)]
-        let ppersec = c as f64 / start_time.elapsed().as_secs_f64();
-        print!("{c: >8} FENs converted. ({ppersec:.2}/s)\r");
-        std::io::stdout().flush().unwrap();
-    }
-    for fen in fens {
-        counter.fetch_add(1, atomic::Ordering::SeqCst);
-        local_ticker += 1;
-        if printing_thread && local_ticker % 25 == 0 {
-            let c = counter.load(atomic::Ordering::SeqCst);
-            #[allow(clippy::cast_precision_loss)]
-            let ppersec = c as f64 / start_time.elapsed().as_secs_f64();
-            print!("{c: >8} FENs converted. ({ppersec:.2}/s)                                        \r");
-            std::io::stdout().flush().unwrap();
-        }
-        pos.set_from_fen(fen).unwrap();
-        if filter_quiescent && pos.in_check::<{ Board::US }>() {
-            evals.push(None);
-            continue;
-        }
-        // no NNUE for generating training data.
-        t.nnue.refresh_acc(&pos);
-        tt.clear();
-        let stopped = AtomicBool::new(false);
-        let time_manager =
-            TimeManager { limit: SearchLimit::Depth(Depth::new(depth)), ..TimeManager::default() };
-        let mut info =
-            SearchInfo { time_manager, print_to_stdout: false, ..SearchInfo::new(&stopped) };
-        let (score, bm) =
-            pos.search_position::<USE_NNUE>(&mut info, array::from_mut(&mut t), tt.view());
-        if filter_quiescent && (pos.is_tactical(bm) || is_game_theoretic_score(score)) {
-            evals.push(None);
-            continue;
-        }
-        evals.push(Some(score));
-    }
-    if printing_thread {
-        let c = counter.load(atomic::Ordering::SeqCst);
-        #[allow(clippy::cast_precision_loss)]
-        let ppersec = c as f64 / start_time.elapsed().as_secs_f64();
-        print!("{c: >8} FENs converted. ({ppersec:.2}/s)                                            \r");
-        std::io::stdout().flush().unwrap();
+use self::formats::{DataRecord, Marlinformat};
+
+pub use self::{
+    evaluate::{evaluate_fens, EvalMode},
+    external::{dedup_files, shuffle_files, DedupKey},
+    filter::{filter, Predicate},
+    formats::{format_for_path, DataFormat, InvalidDataPolicy, Marlinflow, OurTexel, Records},
+    stats::data_stats,
+};
+
+/// Convert a marlinflow-format text data file (`<FEN> | <EVAL> | <WDL>`) into marlinformat binary records.
+pub fn text_to_binary<P1: AsRef<Path>, P2: AsRef<Path>>(
+    input_file: P1,
+    output_file: P2,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    convert_format(input_file.as_ref(), output_file.as_ref(), &Marlinflow, &Marlinformat, policy)
+}
+
+/// Copy every record of `input_file` into `output_file`, converting it from one format into another.
+fn convert_format(
+    input_file: &Path,
+    output_file: &Path,
+    input_format: &dyn DataFormat,
+    output_format: &dyn DataFormat,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    let mut records = Records::open(input_format, input_file, policy)?;
+    let mut output = BufWriter::new(File::create(output_file)?);
+    let start_time = std::time::Instant::now();
+    let mut n = 0;
+    for record in records.by_ref() {
+        output_format.write(&mut output, &record?)?;
+        n += 1;
     }
+    output.flush()?;
+    records.report_skipped();
+    let elapsed = start_time.elapsed();
+    println!("Converted {n} positions in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
+    Ok(())
+}
+
+/// Convert a file of marlinformat binary records into marlinflow-format text.
+pub fn binary_to_text<P1: AsRef<Path>, P2: AsRef<Path>>(
+    input_file: P1,
+    output_file: P2,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    convert_format(input_file.as_ref(), output_file.as_ref(), &Marlinformat, &Marlinflow, policy)
 }
 
-pub fn evaluate_fens<P1: AsRef<Path>, P2: AsRef<Path>>(
+/// Expand a file of datagen game records into marlinformat binary records,
+/// keeping the positions that were flagged to be kept when the games were generated.
+pub fn expand_games<P1: AsRef<Path>, P2: AsRef<Path>>(
     input_file: P1,
     output_file: P2,
-    format: Format,
-    depth: i32,
-    filter_quiescent: bool,
-    use_nnue: bool,
-) -> io::Result<()> {
-    let reader = BufReader::new(File::open(input_file)?);
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(input_file)?);
     let mut output = BufWriter::new(File::create(output_file)?);
-    let mut data_iterator = match format {
-        Format::OurTexel => from_our_texel_format(reader),
-        Format::Marlinflow => from_marlinflow_format(reader),
-    };
-    let fens_processed = AtomicU64::new(0);
     let start_time = std::time::Instant::now();
-    let mut fens = Vec::with_capacity(100_000);
-    let mut outcomes = Vec::with_capacity(100_000);
-    loop {
-        fens.clear();
-        outcomes.clear();
-        for _ in 0..100_000 {
-            if let Some((fen, outcome)) = data_iterator.next() {
-                fens.push(fen);
-                outcomes.push(outcome);
-            } else {
-                break;
-            }
-        }
-        if fens.is_empty() {
-            break;
-        }
-        let evals = parallel_evaluate(
-            &fens,
-            depth,
-            filter_quiescent,
-            use_nnue,
-            &fens_processed,
-            start_time,
-        );
-        for ((fen, outcome), eval) in fens.iter().zip(&outcomes).zip(&evals) {
-            if let Some(eval) = eval {
-                // data is written to conform with marlinflow's data format.
-                writeln!(output, "{fen} | {eval} | {outcome:.1}")?;
-            }
-        }
+    let mut n_games = 0;
+    let mut n_moves = 0;
+    let mut n_positions = 0;
+    while let Some(game) =
+        Game::deserialise_from(&mut reader).map_err(|e| format!("game {n_games}: {e}"))?
+    {
+        let mut write_result = Ok(());
+        game.splat(
+            |_, _, _, keep| keep,
+            |record| {
+                if write_result.is_ok() {
+                    write_result = output.write_all(&record.to_bytes());
+                    n_positions += 1;
+                }
+            },
+        )
+        .map_err(|e| format!("game {n_games}: {e}"))?;
+        write_result?;
+        n_games += 1;
+        n_moves += game.num_moves();
     }
     output.flush()?;
+    let elapsed = start_time.elapsed();
+    println!(
+        "Expanded {n_games} games ({n_moves} moves) into {n_positions} positions in {}.{:03}s",
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+    );
     Ok(())
 }
 
-/// A container to optimise the comparison of strings.
-struct QuicklySortableString<'a> {
-    /// The string to be sorted.
-    pub string: &'a str,
-    /// The substring that we are deduping based on.
-    pub view: Range<usize>,
-    /// The hash of the substring.
-    pub hash: u64,
+/// How tablebase wins and losses that the fifty-move rule turns into draws are labelled
+/// when rescoring data with tablebases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursedResults {
+    /// Label cursed wins and blessed losses as draws, as the game would be scored.
+    Draws,
+    /// Label cursed wins and blessed losses as the wins and losses they would be without the fifty-move rule.
+    WinsAndLosses,
+}
+
+/// Settings for rescoring data with tablebases.
+#[derive(Debug, Clone, Copy)]
+pub struct TbRescoreOptions {
+    /// The white-relative magnitude of the score given to tablebase wins and losses.
+    pub win_score: i32,
+    /// How cursed wins and blessed losses are labelled.
+    pub cursed_results: CursedResults,
 }
 
-impl<'a> QuicklySortableString<'a> {
-    pub fn new(string: &'a str, view: Range<usize>) -> Self {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = DefaultHasher::new();
-        let interesting_range = &string[view.clone()];
-        interesting_range.hash(&mut hasher);
-        Self { string, view, hash: hasher.finish() }
+/// Counts of what rescoring a dataset with tablebases changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct TbRescoreStats {
+    /// The number of positions read.
+    positions: u64,
+    /// The number of positions that were found in the tablebases.
+    probed: u64,
+    /// The number of probed positions that were cursed wins or blessed losses.
+    cursed: u64,
+    /// The number of positions whose score was changed.
+    scores_changed: u64,
+    /// Relabellings, indexed by the old and new WDL bytes.
+    relabelled: [[u64; 3]; 3],
+}
+
+impl TbRescoreStats {
+    fn add(&mut self, other: &Self) {
+        self.positions += other.positions;
+        self.probed += other.probed;
+        self.cursed += other.cursed;
+        self.scores_changed += other.scores_changed;
+        for (row, other_row) in self.relabelled.iter_mut().zip(&other.relabelled) {
+            for (count, other_count) in row.iter_mut().zip(other_row) {
+                *count += other_count;
+            }
+        }
     }
 
-    /// Compare the substring of the two strings.
-    pub fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.hash.cmp(&other.hash).then_with(|| {
-            let a = &self.string[self.view.clone()];
-            let b = &other.string[other.view.clone()];
-            a.cmp(b)
-        })
+    fn report(&self) {
+        #![allow(clippy::cast_precision_loss)]
+        const NAMES: [&str; 3] = ["black win", "draw", "white win"];
+        let percent = |n: u64| n as f64 / self.positions.max(1) as f64 * 100.0;
+        let changed = (0..3)
+            .flat_map(|from| (0..3).filter(move |&to| to != from).map(move |to| (from, to)))
+            .map(|(from, to)| self.relabelled[from][to])
+            .sum::<u64>();
+        println!("Positions: {}", self.positions);
+        println!(" |> found in tablebases: {} ({:.1}%)", self.probed, percent(self.probed));
+        println!(" |> cursed wins or blessed losses: {}", self.cursed);
+        println!(
+            " |> scores changed: {} ({:.1}%)",
+            self.scores_changed,
+            percent(self.scores_changed)
+        );
+        println!(" |> WDL labels changed: {changed} ({:.1}%)", percent(changed));
+        for (from, row) in self.relabelled.iter().enumerate() {
+            for (to, &count) in row.iter().enumerate() {
+                if from != to && count > 0 {
+                    println!("     {} -> {}: {count}", NAMES[from], NAMES[to]);
+                }
+            }
+        }
     }
+}
 
-    /// Get a reference to the backing string.
-    pub const fn as_str(&self) -> &str {
-        self.string
+/// Gets the white-relative score and WDL byte that a white-relative tablebase result should be labelled with.
+fn tb_label(wdl: WDL, options: TbRescoreOptions) -> (i32, u8) {
+    let cursed_as_draw = options.cursed_results == CursedResults::Draws;
+    match wdl {
+        WDL::CursedWin | WDL::BlessedLoss if cursed_as_draw => (0, PackedRecord::DRAW),
+        WDL::Win | WDL::CursedWin => (options.win_score, PackedRecord::WHITE_WIN),
+        WDL::Loss | WDL::BlessedLoss => (-options.win_score, PackedRecord::BLACK_WIN),
+        WDL::Draw => (0, PackedRecord::DRAW),
     }
 }
 
-/// Deduplicate a dataset by board.
-/// This deduplicates based on the first two parts of FENs, which are the board and the side to move.
-pub fn dedup<P1: AsRef<Path>, P2: AsRef<Path>>(input_file: P1, output_file: P2) -> io::Result<()> {
-    #![allow(clippy::cast_precision_loss)]
-    let start_time = std::time::Instant::now();
-    let all_data = std::fs::read_to_string(input_file)?; // we need everything in memory anyway
-    let mb = all_data.len() as f64 / 1_000_000.0;
-    let elapsed = start_time.elapsed();
-    println!("Read {mb:.0} bytes in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
-    dedup_inner(output_file, &all_data)
+/// Relabels a single position with its tablebase result, if it is within the tablebases.
+/// Only the WDL tables are probed, which is thread safe. Returns `false` if the position is a win
+/// or loss with a non-zero fifty-move counter, which might really be cursed or blessed, so that it
+/// has to be relabelled by [`tb_rescore_position_with_dtz`] instead.
+fn tb_rescore_position(
+    board: &Board,
+    eval: &mut i32,
+    wdl: &mut u8,
+    options: TbRescoreOptions,
+    stats: &mut TbRescoreStats,
+) -> bool {
+    if board.n_men() > probe::get_max_pieces_count() {
+        return true;
+    }
+    let Some(tb_wdl) = probe::get_wdl_white_ignoring_clock(board) else {
+        return true;
+    };
+    if board.fifty_move_counter() != 0 && matches!(tb_wdl, WDL::Win | WDL::Loss) {
+        return false;
+    }
+    tb_relabel(tb_wdl, eval, wdl, options, stats);
+    true
 }
 
-/// Merge two datasets, deduplicating by boards, according to the same criterion as `nnue::convert::dedup`.
-pub fn merge<P1, P2, P3>(path_1: P1, path_2: P2, output: P3) -> Result<(), Box<dyn Error>>
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-    P3: AsRef<Path>,
-{
-    #![allow(clippy::cast_precision_loss)]
-    use std::io::Read;
-    let start_time = std::time::Instant::now();
-    let mut f1 = File::open(path_1)?;
-    let mut f2 = File::open(path_2)?;
-    let mut buffer = Vec::new();
-    f1.read_to_end(&mut buffer)?;
-    f2.read_to_end(&mut buffer)?;
-    let all_data = String::from_utf8(buffer)?;
-    let mb = all_data.len() as f64 / 1_000_000.0;
-    let elapsed = start_time.elapsed();
-    println!("Read {mb:.0} megabytes in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
-    Ok(dedup_inner(output, &all_data)?)
+/// Relabels a position with its tablebase result, taking the fifty-move counter into account.
+/// This probes the DTZ tables, which isn't thread safe, so it must only be called from one thread at a time.
+fn tb_rescore_position_with_dtz(
+    board: &Board,
+    eval: &mut i32,
+    wdl: &mut u8,
+    options: TbRescoreOptions,
+    stats: &mut TbRescoreStats,
+) {
+    if let Some(tb_wdl) = probe::get_wdl_white(board) {
+        tb_relabel(tb_wdl, eval, wdl, options, stats);
+    }
+}
+
+/// Replaces the labels of a position with those for its white-relative tablebase result.
+fn tb_relabel(
+    tb_wdl: WDL,
+    eval: &mut i32,
+    wdl: &mut u8,
+    options: TbRescoreOptions,
+    stats: &mut TbRescoreStats,
+) {
+    let (new_eval, new_wdl) = tb_label(tb_wdl, options);
+    stats.probed += 1;
+    stats.cursed += u64::from(matches!(tb_wdl, WDL::CursedWin | WDL::BlessedLoss));
+    stats.scores_changed += u64::from(*eval != new_eval);
+    stats.relabelled[usize::from(*wdl)][usize::from(new_wdl)] += 1;
+    *eval = new_eval;
+    *wdl = new_wdl;
 }
 
-fn dedup_inner<P2: AsRef<Path>>(output_file: P2, all_data: &str) -> Result<(), io::Error> {
+/// Rescore a dataset with Syzygy tablebases, replacing the score and WDL label of every position
+/// within the tablebases with the tablebase result, and report how many labels were changed.
+/// Files with a `.bin` extension are read and written as marlinformat binary records,
+/// and anything else as marlinflow-format text.
+pub fn tb_rescore<P1: AsRef<Path>, P2: AsRef<Path>>(
+    input_file: P1,
+    output_file: P2,
+    tablebases_path: &Path,
+    options: TbRescoreOptions,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    const CHUNK_SIZE: usize = 100_000;
+    probe::init(&tablebases_path.to_string_lossy());
+    if probe::get_max_pieces_count() == 0 {
+        return Err(format!(
+            "no tablebases loaded from {} (tablebase probing requires the syzygy feature)",
+            tablebases_path.display()
+        )
+        .into());
+    }
+    let format = format_for_path(input_file.as_ref(), None)?;
+    let mut records = Records::open(format, input_file.as_ref(), policy)?;
     let mut output = BufWriter::new(File::create(output_file)?);
     let start_time = std::time::Instant::now();
-    let mut data = all_data
-        .lines()
-        .map(|line| {
-            let split_index = line.bytes().position(|b| b == b' ').unwrap(); // the space between the board part and the "w/b" part.
-            QuicklySortableString::new(line, 0..(split_index + 2))
-        })
-        .collect::<Vec<_>>();
-    let n = data.len();
-    let elapsed = start_time.elapsed();
-    println!("Hashed {n} lines in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
-    let start_time = std::time::Instant::now();
-    data.sort_unstable_by(QuicklySortableString::cmp);
-    data.dedup_by(|a, b| a.cmp(b) == std::cmp::Ordering::Equal);
-    let n = data.len();
+    let mut stats = TbRescoreStats::default();
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        chunk.clear();
+        for record in records.by_ref().take(CHUNK_SIZE) {
+            let record = record?;
+            let board =
+                Board::from_fen(&record.fen).expect("FENs are validated when they are read");
+            chunk.push((board, record.eval.unwrap_or(0), (record.wdl * 2.0).round() as u8, true));
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        stats.positions += chunk.len() as u64;
+        let chunk_size = chunk.len() / num_cpus::get() + 1;
+        std::thread::scope(|s| {
+            let handles = chunk
+                .chunks_mut(chunk_size)
+                .map(|records| {
+                    s.spawn(move || {
+                        let mut stats = TbRescoreStats::default();
+                        for (board, eval, wdl, done) in records {
+                            *done = tb_rescore_position(board, eval, wdl, options, &mut stats);
+                        }
+                        stats
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                stats.add(&handle.join().unwrap());
+            }
+        });
+        // the positions that need DTZ probes are done here, on one thread.
+        for (board, eval, wdl, _) in chunk.iter_mut().filter(|(.., done)| !*done) {
+            tb_rescore_position_with_dtz(board, eval, wdl, options, &mut stats);
+        }
+        for (board, eval, wdl, _) in &chunk {
+            let record =
+                DataRecord { fen: board.fen(), eval: Some(*eval), wdl: f32::from(*wdl) / 2.0 };
+            format.write(&mut output, &record)?;
+        }
+        print!("{: >8} positions rescored.\r", stats.positions);
+        std::io::stdout().flush()?;
+    }
+    output.flush()?;
+    records.report_skipped();
     let elapsed = start_time.elapsed();
     println!(
-        "Sorted and deduped down to {n} lines in {}.{:03}s",
+        "Rescored {} positions in {}.{:03}s",
+        stats.positions,
         elapsed.as_secs(),
         elapsed.subsec_millis()
     );
-    let start_time = std::time::Instant::now();
-    // write deduplicated data to output file.
-    for line in data {
-        let line = line.as_str();
-        writeln!(output, "{line}")?;
-    }
-    let res = output.flush();
-    let elapsed = start_time.elapsed();
-    println!("Wrote {n} lines in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
-    res
-}
-
-fn parallel_evaluate(
-    fens: &[String],
-    depth: i32,
-    filter_quiescent: bool,
-    use_nnue: bool,
-    fens_processed: &AtomicU64,
-    start_time: std::time::Instant,
-) -> Vec<Option<i32>> {
-    let chunk_size = fens.len() / num_cpus::get() + 1;
-    let chunks = fens.chunks(chunk_size).collect::<Vec<_>>();
-    std::thread::scope(|s| {
-        let mut handles = Vec::new();
-        let mut evals = Vec::with_capacity(fens.len());
-        for (i, chunk) in chunks.into_iter().enumerate() {
-            let printing_thread = i == 0;
-            handles.push(s.spawn(move || {
-                let mut inner_evals = Vec::new();
-                if use_nnue {
-                    batch_convert::<true>(
-                        depth,
-                        chunk,
-                        &mut inner_evals,
-                        filter_quiescent,
-                        fens_processed,
-                        printing_thread,
-                        start_time,
-                    );
-                } else {
-                    batch_convert::<false>(
-                        depth,
-                        chunk,
-                        &mut inner_evals,
-                        filter_quiescent,
-                        fens_processed,
-                        printing_thread,
-                        start_time,
-                    );
-                }
-                inner_evals
-            }));
-        }
-        for handle in handles {
-            evals.extend_from_slice(&handle.join().unwrap());
-        }
-        evals
-    })
-}
-
-type EvaledFenIterator = Box<dyn Iterator<Item = (String, f32)>>;
-
-fn from_our_texel_format(reader: BufReader<File>) -> EvaledFenIterator {
-    // texel format is <FEN>;<WDL>
-    Box::new(reader.lines().filter_map(|line| {
-        line.ok().map(|line| {
-            line.split_once(';').map(|(fen, wdl)| (fen.to_string(), wdl.parse().unwrap())).unwrap()
-        })
-    }))
+    stats.report();
+    Ok(())
 }
 
-fn from_marlinflow_format(reader: BufReader<File>) -> EvaledFenIterator {
-    // marlinflow format is <FEN> | <EVAL> | <WDL>
-    Box::new(reader.lines().filter_map(|line| {
-        line.ok().map(|line| {
-            let (fen, rest) = line.split_once('|').unwrap();
-            let fen = fen.trim().to_string();
-            let outcome = rest.trim().split_once('|').unwrap().1.trim().parse().unwrap();
-            (fen, outcome)
-        })
-    }))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{datagen::marlinformat::PackedRecord, tablebases::probe::WDL};
 
-#[derive(Debug, Clone, Copy)]
-pub enum Format {
-    OurTexel,
-    Marlinflow,
+    #[test]
+    fn tb_labels() {
+        let mut options =
+            TbRescoreOptions { win_score: 2000, cursed_results: CursedResults::Draws };
+        assert_eq!(tb_label(WDL::Win, options), (2000, PackedRecord::WHITE_WIN));
+        assert_eq!(tb_label(WDL::Loss, options), (-2000, PackedRecord::BLACK_WIN));
+        assert_eq!(tb_label(WDL::Draw, options), (0, PackedRecord::DRAW));
+        assert_eq!(tb_label(WDL::CursedWin, options), (0, PackedRecord::DRAW));
+        assert_eq!(tb_label(WDL::BlessedLoss, options), (0, PackedRecord::DRAW));
+        options.cursed_results = CursedResults::WinsAndLosses;
+        assert_eq!(tb_label(WDL::CursedWin, options), (2000, PackedRecord::WHITE_WIN));
+        assert_eq!(tb_label(WDL::BlessedLoss, options), (-2000, PackedRecord::BLACK_WIN));
+        assert_eq!(tb_label(WDL::Draw, options), (0, PackedRecord::DRAW));
+    }
 }
@@ -0,0 +1,444 @@
+//! Scoring of the positions in data files, by search or by evaluation alone.
+//!
+//! Records stream through a pipeline: this thread reads them in chunks, a pool of workers scores
+//! the chunks as they arrive, and a writer thread puts the scored chunks back in their original
+//! order and writes them out. Every worker keeps its board, network state, and hash table for the
+//! whole run, so scoring a position only costs what the chosen mode needs.
+
+use std::{
+    array,
+    collections::BTreeMap,
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{atomic::AtomicBool, mpsc, Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    board::{evaluation::is_game_theoretic_score, Board},
+    definitions::{depth::Depth, INFINITY, MEGABYTE},
+    errors::{DataError, DataLocation},
+    piece::Colour,
+    search::PVariation,
+    searchinfo::SearchInfo,
+    threadlocal::ThreadData,
+    timemgmt::{SearchLimit, TimeManager},
+    transpositiontable::TT,
+};
+
+use super::formats::{
+    DataFormat, DataRecord, InvalidDataPolicy, InvalidRecords, Marlinflow, Records,
+};
+
+/// The number of records that are passed to a worker at once.
+const CHUNK_SIZE: usize = 4096;
+
+/// How the positions of a data file are scored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalMode {
+    /// A search to a fixed depth.
+    Depth(i32),
+    /// A search of a fixed number of nodes.
+    Nodes(u64),
+    /// A quiescence search, which only resolves captures and promotions.
+    Quiescence,
+    /// The static evaluation, with no search at all.
+    Static,
+}
+
+impl EvalMode {
+    /// Gets a mode by the name given on the command line, using `depth` or `nodes` to limit searches.
+    pub fn from_name(name: &str, depth: i32, nodes: u64) -> Result<Self, String> {
+        match name {
+            "depth" => Ok(Self::Depth(depth)),
+            "nodes" => Ok(Self::Nodes(nodes)),
+            "qsearch" => Ok(Self::Quiescence),
+            "static" => Ok(Self::Static),
+            _ => Err(format!(
+                "unknown evaluation mode \"{name}\", expected one of depth, nodes, qsearch, or static"
+            )),
+        }
+    }
+
+    /// The size of the hash table that each worker needs.
+    const fn hash_size(self) -> usize {
+        match self {
+            Self::Depth(_) | Self::Nodes(_) => 16 * MEGABYTE,
+            // the quiescence search only touches a handful of entries, so a small table is quick to clear.
+            Self::Quiescence => MEGABYTE / 256,
+            Self::Static => 0,
+        }
+    }
+}
+
+/// The state that a worker needs to score positions.
+struct Evaluator<'a> {
+    mode: EvalMode,
+    use_nnue: bool,
+    filter_quiescent: bool,
+    stopped: &'a AtomicBool,
+    board: Board,
+    tt: TT,
+    thread: ThreadData,
+    info: SearchInfo<'a>,
+    pv: PVariation,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(
+        mode: EvalMode,
+        use_nnue: bool,
+        filter_quiescent: bool,
+        stopped: &'a AtomicBool,
+    ) -> Self {
+        let board = Board::new();
+        let mut tt = TT::new();
+        tt.resize(mode.hash_size());
+        let thread = ThreadData::new(0, &board);
+        let info = SearchInfo { print_to_stdout: false, ..SearchInfo::new(stopped) };
+        Self {
+            mode,
+            use_nnue,
+            filter_quiescent,
+            stopped,
+            board,
+            tt,
+            thread,
+            info,
+            pv: PVariation::default(),
+        }
+    }
+
+    /// Scores the position on the board from White's point of view, or returns [None] if it is filtered out.
+    fn score(&mut self) -> Option<i32> {
+        if self.filter_quiescent && self.board.in_check::<{ Board::US }>() {
+            return None;
+        }
+        if self.use_nnue {
+            self.thread.nnue.refresh_acc(&self.board);
+        } else {
+            // the classical evaluation is updated incrementally, starting from here.
+            self.board.refresh_psqt(&self.info);
+        }
+        let white_relative = |board: &Board, score: i32| {
+            if board.turn() == Colour::WHITE {
+                score
+            } else {
+                -score
+            }
+        };
+        match self.mode {
+            EvalMode::Static => {
+                let score = if self.use_nnue {
+                    self.board.evaluate::<true>(&self.info, &mut self.thread, 0)
+                } else {
+                    self.board.evaluate::<false>(&self.info, &mut self.thread, 0)
+                };
+                Some(white_relative(&self.board, score))
+            }
+            EvalMode::Quiescence => {
+                self.tt.clear();
+                self.info.nodes = 0;
+                let (board, tt, pv, info, t) = (
+                    &mut self.board,
+                    self.tt.view(),
+                    &mut self.pv,
+                    &mut self.info,
+                    &mut self.thread,
+                );
+                let score = if self.use_nnue {
+                    board.quiescence::<true, true>(tt, pv, info, t, -INFINITY, INFINITY)
+                } else {
+                    board.quiescence::<true, false>(tt, pv, info, t, -INFINITY, INFINITY)
+                };
+                // a position is quiet if the quiescence search can't improve on the static evaluation.
+                if self.filter_quiescent
+                    && (!self.pv.moves().is_empty() || is_game_theoretic_score(score))
+                {
+                    return None;
+                }
+                Some(white_relative(&self.board, score))
+            }
+            EvalMode::Depth(_) | EvalMode::Nodes(_) => {
+                self.tt.clear();
+                let limit = match self.mode {
+                    EvalMode::Depth(depth) => SearchLimit::Depth(Depth::new(depth)),
+                    EvalMode::Nodes(nodes) => SearchLimit::Nodes(nodes),
+                    EvalMode::Quiescence | EvalMode::Static => unreachable!(),
+                };
+                let time_manager = TimeManager { limit, ..TimeManager::default() };
+                let mut info = SearchInfo {
+                    time_manager,
+                    print_to_stdout: false,
+                    ..SearchInfo::new(self.stopped)
+                };
+                let thread = array::from_mut(&mut self.thread);
+                let (score, best_move) = if self.use_nnue {
+                    self.board.search_position::<true>(&mut info, thread, self.tt.view())
+                } else {
+                    self.board.search_position::<false>(&mut info, thread, self.tt.view())
+                };
+                if self.filter_quiescent
+                    && (self.board.is_tactical(best_move) || is_game_theoretic_score(score))
+                {
+                    return None;
+                }
+                // search scores are already from White's point of view.
+                Some(score)
+            }
+        }
+    }
+}
+
+/// A chunk of unparsed records, so that parsing happens on the workers.
+struct RawChunk {
+    /// The place of the chunk in the input.
+    index: usize,
+    /// The bytes of every record, one after the other.
+    bytes: Vec<u8>,
+    /// Where each record came from and where its bytes end, or the error from reading it.
+    records: Vec<Result<(DataLocation, usize), DataError>>,
+}
+
+/// A record and its score, or the error from reading or parsing it.
+type Scored = Result<(DataRecord, Option<i32>), DataError>;
+
+/// An error that can be passed between threads.
+type ThreadError = Box<dyn Error + Send + Sync>;
+
+/// Reads raw records into chunks and passes them to the workers, until the input or the workers run out.
+fn read_chunks(
+    records: &mut Records,
+    chunks: &mpsc::SyncSender<RawChunk>,
+) -> Result<(), DataError> {
+    let mut raw = Vec::new();
+    for index in 0.. {
+        let mut chunk =
+            RawChunk { index, bytes: Vec::new(), records: Vec::with_capacity(CHUNK_SIZE) };
+        while chunk.records.len() < CHUNK_SIZE {
+            match records.next_raw(&mut raw) {
+                None => break,
+                Some(Ok(location)) => {
+                    chunk.bytes.extend_from_slice(&raw);
+                    chunk.records.push(Ok((location, chunk.bytes.len())));
+                }
+                Some(Err(error)) if error.kind.is_fatal() => return Err(error),
+                Some(Err(error)) => chunk.records.push(Err(error)),
+            }
+        }
+        if chunk.records.is_empty() || chunks.send(chunk).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Parses and scores the records of a chunk.
+fn score_chunk(
+    chunk: RawChunk,
+    format: &dyn DataFormat,
+    file: &Path,
+    evaluator: &mut Evaluator,
+) -> Vec<Scored> {
+    let mut start = 0;
+    chunk
+        .records
+        .into_iter()
+        .map(|entry| {
+            let (location, end) = entry?;
+            let raw = &chunk.bytes[start..end];
+            start = end;
+            let record = format.parse(raw, &mut evaluator.board).map_err(|kind| DataError {
+                file: file.to_owned(),
+                location,
+                kind,
+            })?;
+            Ok((record, evaluator.score()))
+        })
+        .collect()
+}
+
+/// Writes scored chunks in input order, returning the number of valid positions and the number written,
+/// along with the invalid records that were skipped.
+fn write_chunks(
+    results: mpsc::Receiver<(usize, Vec<Scored>)>,
+    output: &mut impl Write,
+    mut invalid: InvalidRecords,
+    start_time: Instant,
+) -> Result<(u64, u64, InvalidRecords), ThreadError> {
+    #![allow(clippy::cast_precision_loss)]
+    let mut pending = BTreeMap::new();
+    let mut next_chunk = 0;
+    let mut n_read = 0u64;
+    let mut n_written = 0u64;
+    for (index, scored) in results {
+        pending.insert(index, scored);
+        while let Some(scored) = pending.remove(&next_chunk) {
+            for result in scored {
+                let (record, eval) = match result {
+                    Ok(scored) => scored,
+                    Err(error) => {
+                        invalid.check(error)?;
+                        continue;
+                    }
+                };
+                n_read += 1;
+                if let Some(eval) = eval {
+                    n_written += 1;
+                    // data is written to conform with marlinflow's data format.
+                    Marlinflow.write(output, &DataRecord { eval: Some(eval), ..record })?;
+                }
+            }
+            next_chunk += 1;
+            let per_second = n_read as f64 / start_time.elapsed().as_secs_f64();
+            print!("{n_read: >8} FENs converted. ({per_second:.2}/s)\r");
+            io::stdout().flush()?;
+        }
+    }
+    output.flush()?;
+    Ok((n_read, n_written, invalid))
+}
+
+/// Score every position of a data file, writing the scored positions to `output_file` in marlinflow format.
+/// If `filter_quiescent` is set, positions that are in check, that aren't quiet, or that are
+/// decided are dropped. In [`EvalMode::Static`] only the first of these can be checked.
+pub fn evaluate_fens<P1: AsRef<Path>, P2: AsRef<Path>>(
+    input_file: P1,
+    output_file: P2,
+    format: &dyn DataFormat,
+    mode: EvalMode,
+    filter_quiescent: bool,
+    use_nnue: bool,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    #![allow(clippy::cast_precision_loss)]
+    let input_file = input_file.as_ref();
+    let mut records = Records::open(format, input_file, policy)?;
+    let mut output = BufWriter::new(File::create(output_file)?);
+    let start_time = Instant::now();
+    let stopped = AtomicBool::new(false);
+    let n_workers = num_cpus::get();
+    let (chunk_sender, chunk_receiver) = mpsc::sync_channel::<RawChunk>(n_workers * 2);
+    // the workers own the receiver between them, so that if they all stop, sending to them fails rather than blocking.
+    let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+    let (result_sender, result_receiver) = mpsc::channel();
+    let (read_result, write_result) = std::thread::scope(|s| {
+        for _ in 0..n_workers {
+            let chunk_receiver = Arc::clone(&chunk_receiver);
+            let result_sender = result_sender.clone();
+            let stopped = &stopped;
+            s.spawn(move || {
+                let mut evaluator = Evaluator::new(mode, use_nnue, filter_quiescent, stopped);
+                loop {
+                    let next = chunk_receiver.lock().unwrap().recv();
+                    let Ok(chunk) = next else {
+                        break;
+                    };
+                    let index = chunk.index;
+                    let scored = score_chunk(chunk, format, input_file, &mut evaluator);
+                    if result_sender.send((index, scored)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(chunk_receiver);
+        drop(result_sender);
+        let invalid = InvalidRecords::new(policy);
+        let writer = s.spawn(|| write_chunks(result_receiver, &mut output, invalid, start_time));
+        let read_result = read_chunks(&mut records, &chunk_sender);
+        drop(chunk_sender);
+        (read_result, writer.join().unwrap())
+    });
+    let (n_read, n_written, invalid) = write_result.map_err(|e| e as Box<dyn Error>)?;
+    read_result?;
+    println!();
+    invalid.report(input_file);
+    let elapsed = start_time.elapsed();
+    println!(
+        "Scored {n_read} positions in {}.{:03}s ({:.0}/s), kept {n_written}",
+        elapsed.as_secs(),
+        elapsed.subsec_millis(),
+        n_read as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn evaluation_modes() {
+        crate::magic::initialise();
+        let stopped = AtomicBool::new(false);
+        let quiet = "8/8/4k3/8/8/4K3/4P3/8 b - - 0 50";
+        let hanging_rook = "8/8/4k3/8/3r4/4K3/4P3/8 w - - 0 50";
+        let check = "4k3/4R3/8/8/8/8/8/4K3 b - - 0 1";
+        let score = |evaluator: &mut Evaluator, fen: &str| {
+            evaluator.board.set_from_fen(fen).unwrap();
+            evaluator.score()
+        };
+        for use_nnue in [false, true] {
+            let evaluator = |mode| Evaluator::new(mode, use_nnue, true, &stopped);
+            let mut static_eval = evaluator(EvalMode::Static);
+            let quiet_eval = score(&mut static_eval, quiet).unwrap();
+            assert!(quiet_eval > 0, "{quiet_eval}");
+            assert!(score(&mut static_eval, hanging_rook).unwrap() < 0);
+            assert_eq!(score(&mut static_eval, check), None);
+
+            let mut qsearch = evaluator(EvalMode::Quiescence);
+            assert_eq!(score(&mut qsearch, quiet), Some(quiet_eval));
+            // taking the rook isn't quiet.
+            assert_eq!(score(&mut qsearch, hanging_rook), None);
+            let mut qsearch = Evaluator::new(EvalMode::Quiescence, use_nnue, false, &stopped);
+            assert!(score(&mut qsearch, hanging_rook).unwrap() > 0);
+
+            for mode in [EvalMode::Depth(3), EvalMode::Nodes(2000)] {
+                let mut search = evaluator(mode);
+                assert!(score(&mut search, quiet).unwrap() > 0);
+                assert_eq!(score(&mut search, hanging_rook), None);
+            }
+        }
+    }
+
+    #[test]
+    fn pipeline_keeps_input_order() {
+        crate::magic::initialise();
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("viri_evaluate_{}_in.txt", std::process::id()));
+        let output = dir.join(format!("viri_evaluate_{}_out.txt", std::process::id()));
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "8/8/4k3/8/8/4K3/4P3/8 b - - 0 50",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 3 17",
+        ];
+        // enough records for several chunks, with an invalid one in the middle.
+        let mut lines = (0..10_000).map(|i| format!("{};0.5", fens[i % 3])).collect::<Vec<_>>();
+        lines[5000] = "not a record".into();
+        std::fs::write(&input, lines.join("\n")).unwrap();
+        let run = |policy| {
+            evaluate_fens(
+                &input,
+                &output,
+                &super::super::OurTexel,
+                EvalMode::Static,
+                true,
+                true,
+                policy,
+            )
+        };
+        assert!(run(InvalidDataPolicy::Strict).is_err());
+        run(InvalidDataPolicy::Skip).unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        let written =
+            text.lines().map(|line| line.split(" | ").next().unwrap()).collect::<Vec<_>>();
+        let expected = (0..10_000).filter(|&i| i != 5000).map(|i| fens[i % 3]).collect::<Vec<_>>();
+        assert_eq!(written, expected);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,495 @@
+//! External-memory deduplication, merging, and shuffling of data files.
+//!
+//! Everything here streams through its inputs with a bounded amount of memory,
+//! spilling to temporary files next to the output, so it works on files larger than RAM.
+//! Deduplication is an external merge sort: the inputs are cut into sorted runs that fit in memory,
+//! which are then merged (in several passes, if there are very many of them) while dropping duplicates.
+//! Shuffling scatters records into buckets chosen uniformly at random, and then shuffles each bucket
+//! in memory, which gives every permutation of the input the same probability, just like Fisher-Yates.
+
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BinaryHeap},
+    error::Error,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use rand::{seq::SliceRandom, Rng};
+
+use super::formats::{format_for_path, DataFormat, DataRecord, InvalidDataPolicy, Records};
+
+/// The most runs that are merged at once, to stay clear of limits on open files.
+const MAX_FAN_IN: usize = 128;
+/// The estimated memory used by each record held in memory, on top of its contents.
+const RECORD_OVERHEAD: usize = 64;
+
+/// Which records count as duplicates of each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupKey {
+    /// Records with the same full FEN, including the move counters.
+    Fen,
+    /// Records of the same position, ignoring the move counters.
+    Position,
+}
+
+/// Reads records one at a time from any number of files of the same format.
+struct RecordReader {
+    format: &'static dyn DataFormat,
+    policy: InvalidDataPolicy,
+    /// The files that haven't been opened yet, in reverse order.
+    files: Vec<PathBuf>,
+    records: Option<Records<'static>>,
+}
+
+impl RecordReader {
+    fn new(format: &'static dyn DataFormat, policy: InvalidDataPolicy, paths: &[PathBuf]) -> Self {
+        Self { format, policy, files: paths.iter().rev().cloned().collect(), records: None }
+    }
+
+    /// Opens the next file if the current one is exhausted, returning `false` once every file is.
+    fn next_file(&mut self) -> io::Result<bool> {
+        if self.records.is_some() {
+            return Ok(true);
+        }
+        let Some(path) = self.files.pop() else {
+            return Ok(false);
+        };
+        self.records = Some(Records::open(self.format, &path, self.policy)?);
+        Ok(true)
+    }
+
+    /// Parses the next record, leaving its bytes in `raw`, or returns [None] once every file is exhausted.
+    fn next_parsed(&mut self, raw: &mut Vec<u8>) -> Result<Option<DataRecord>, Box<dyn Error>> {
+        while self.next_file()? {
+            let records = self.records.as_mut().unwrap();
+            if let Some(record) = records.next_with_raw(raw) {
+                return Ok(Some(record?));
+            }
+            records.report_skipped();
+            self.records = None;
+        }
+        Ok(None)
+    }
+
+    /// Reads the bytes of the next record into `raw` without parsing them,
+    /// returning `false` once every file is exhausted.
+    fn next_raw(&mut self, raw: &mut Vec<u8>) -> Result<bool, Box<dyn Error>> {
+        while self.next_file()? {
+            if let Some(location) = self.records.as_mut().unwrap().next_raw(raw) {
+                location?;
+                return Ok(true);
+            }
+            self.records = None;
+        }
+        Ok(false)
+    }
+}
+
+fn write_record(format: &dyn DataFormat, output: &mut impl Write, record: &[u8]) -> io::Result<()> {
+    output.write_all(record)?;
+    if format.record_size().is_none() {
+        output.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Extracts the part of a record that duplicates are detected by.
+fn record_key(record: &DataRecord, key: DedupKey) -> Vec<u8> {
+    let n_fields = match key {
+        DedupKey::Fen => 6,
+        DedupKey::Position => 4,
+    };
+    record.fen.split_whitespace().take(n_fields).collect::<Vec<_>>().join(" ").into_bytes()
+}
+
+/// A record tagged with its deduplication key, ordered by the hash of the key.
+/// Sorting by the hash rather than the key itself is faster, and has the pleasant side effect of
+/// scattering the positions of a single game across the output.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct RunEntry {
+    hash: u64,
+    key: Vec<u8>,
+    record: Vec<u8>,
+}
+
+impl RunEntry {
+    fn new(key: Vec<u8>, record: Vec<u8>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Self { hash: hasher.finish(), key, record }
+    }
+
+    fn same_key(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.key == other.key
+    }
+
+    const fn memory_usage(&self) -> usize {
+        self.key.len() + self.record.len() + RECORD_OVERHEAD
+    }
+
+    fn write_to(&self, output: &mut impl Write) -> io::Result<()> {
+        #![allow(clippy::cast_possible_truncation)]
+        output.write_all(&self.hash.to_le_bytes())?;
+        output.write_all(&(self.key.len() as u32).to_le_bytes())?;
+        output.write_all(&self.key)?;
+        output.write_all(&(self.record.len() as u32).to_le_bytes())?;
+        output.write_all(&self.record)
+    }
+
+    fn read_from(input: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut hash = [0; 8];
+        match input.read_exact(&mut hash) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut read_bytes = || -> io::Result<Vec<u8>> {
+            let mut len = [0; 4];
+            input.read_exact(&mut len)?;
+            let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+            input.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+        let key = read_bytes()?;
+        let record = read_bytes()?;
+        Ok(Some(Self { hash: u64::from_le_bytes(hash), key, record }))
+    }
+}
+
+/// A directory of temporary files next to the output, removed when dropped.
+struct ScratchDir {
+    path: PathBuf,
+    next_file: usize,
+}
+
+impl ScratchDir {
+    fn new(output: &Path) -> io::Result<Self> {
+        let mut name = output.file_name().unwrap_or_default().to_owned();
+        name.push(".tmp");
+        let path = output.with_file_name(name);
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path, next_file: 0 })
+    }
+
+    fn new_file(&mut self) -> PathBuf {
+        self.next_file += 1;
+        self.path.join(format!("{}", self.next_file))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Sorts and deduplicates `entries`, and writes them out as a run.
+fn write_run(entries: &mut Vec<RunEntry>, scratch: &mut ScratchDir) -> io::Result<PathBuf> {
+    entries.sort_unstable();
+    entries.dedup_by(|a, b| a.same_key(b));
+    let path = scratch.new_file();
+    let mut output = BufWriter::new(File::create(&path)?);
+    for entry in entries.drain(..) {
+        entry.write_to(&mut output)?;
+    }
+    output.flush()?;
+    Ok(path)
+}
+
+/// Merges sorted runs, passing each distinct entry to `sink` in order.
+/// Returns the number of distinct entries.
+fn merge_runs(
+    runs: &[PathBuf],
+    mut sink: impl FnMut(&RunEntry) -> io::Result<()>,
+) -> io::Result<u64> {
+    let mut readers = runs
+        .iter()
+        .map(|path| Ok(BufReader::new(File::open(path)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some(entry) = RunEntry::read_from(reader)? {
+            heap.push(Reverse((entry, i)));
+        }
+    }
+    let mut last: Option<RunEntry> = None;
+    let mut n = 0;
+    while let Some(Reverse((entry, i))) = heap.pop() {
+        if let Some(next) = RunEntry::read_from(&mut readers[i])? {
+            heap.push(Reverse((next, i)));
+        }
+        if last.as_ref().is_some_and(|last| last.same_key(&entry)) {
+            continue;
+        }
+        sink(&entry)?;
+        n += 1;
+        last = Some(entry);
+    }
+    Ok(n)
+}
+
+/// Deduplicate any number of data files of the same format into `output_file`,
+/// using at most roughly `memory` bytes of memory.
+/// The format is given by name, or taken from the file extensions if `format` is [None].
+pub fn dedup_files(
+    input_files: &[PathBuf],
+    output_file: &Path,
+    format: Option<&str>,
+    key: DedupKey,
+    memory: usize,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    let format = check_formats(input_files, format)?;
+    let mut scratch = ScratchDir::new(output_file)?;
+
+    // split the inputs into sorted runs.
+    let start_time = std::time::Instant::now();
+    let mut reader = RecordReader::new(format, policy, input_files);
+    let mut entries = Vec::new();
+    let mut used = 0;
+    let mut runs = Vec::new();
+    let mut n_records = 0u64;
+    let mut record = Vec::new();
+    while let Some(parsed) = reader.next_parsed(&mut record)? {
+        let entry = RunEntry::new(record_key(&parsed, key), record.clone());
+        used += entry.memory_usage();
+        entries.push(entry);
+        n_records += 1;
+        if used >= memory {
+            runs.push(write_run(&mut entries, &mut scratch)?);
+            used = 0;
+        }
+    }
+    if !entries.is_empty() || runs.is_empty() {
+        runs.push(write_run(&mut entries, &mut scratch)?);
+    }
+    let elapsed = start_time.elapsed();
+    println!(
+        "Sorted {n_records} records into {} runs in {}.{:03}s",
+        runs.len(),
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+    );
+
+    // merge the runs down until they can all be merged at once.
+    let start_time = std::time::Instant::now();
+    while runs.len() > MAX_FAN_IN {
+        let mut merged = Vec::new();
+        for group in runs.chunks(MAX_FAN_IN) {
+            let path = scratch.new_file();
+            let mut output = BufWriter::new(File::create(&path)?);
+            merge_runs(group, |entry| entry.write_to(&mut output))?;
+            output.flush()?;
+            for run in group {
+                std::fs::remove_file(run)?;
+            }
+            merged.push(path);
+        }
+        runs = merged;
+    }
+    let mut output = BufWriter::new(File::create(output_file)?);
+    let n = merge_runs(&runs, |entry| write_record(format, &mut output, &entry.record))?;
+    output.flush()?;
+    let elapsed = start_time.elapsed();
+    println!(
+        "Merged and deduped down to {n} records in {}.{:03}s",
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+    );
+    Ok(())
+}
+
+/// Shuffle any number of data files of the same format into `output_file`,
+/// using at most roughly `memory` bytes of memory.
+/// The format is given by name, or taken from the file extensions if `format` is [None].
+/// Records are moved around whole without being parsed, so invalid records are shuffled along with the rest.
+pub fn shuffle_files(
+    input_files: &[PathBuf],
+    output_file: &Path,
+    format: Option<&str>,
+    memory: usize,
+    policy: InvalidDataPolicy,
+    rng: &mut impl Rng,
+) -> Result<(), Box<dyn Error>> {
+    let format = check_formats(input_files, format)?;
+    let mut scratch = ScratchDir::new(output_file)?;
+    let start_time = std::time::Instant::now();
+    let mut output = BufWriter::new(File::create(output_file)?);
+    let n = shuffle_into(format, input_files, &mut output, memory, policy, rng, &mut scratch, 0)?;
+    output.flush()?;
+    let elapsed = start_time.elapsed();
+    println!("Shuffled {n} records in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
+    Ok(())
+}
+
+/// Shuffles the records of `input_files` onto the end of `output`, returning the number of records.
+#[allow(clippy::too_many_arguments)]
+fn shuffle_into(
+    format: &'static dyn DataFormat,
+    input_files: &[PathBuf],
+    output: &mut impl Write,
+    memory: usize,
+    policy: InvalidDataPolicy,
+    rng: &mut impl Rng,
+    scratch: &mut ScratchDir,
+    depth: usize,
+) -> Result<u64, Box<dyn Error>> {
+    // how many times a bucket may be split again before giving up and shuffling it in memory,
+    // which only matters if individual records are larger than the memory budget.
+    const MAX_DEPTH: usize = 8;
+    let mut total_size = 0;
+    for path in input_files {
+        total_size += std::fs::metadata(path)?.len();
+    }
+    let mut reader = RecordReader::new(format, policy, input_files);
+    let mut record = Vec::new();
+    let in_memory_size = usize::try_from(total_size).unwrap_or(usize::MAX);
+    if in_memory_size.saturating_mul(2) <= memory || depth >= MAX_DEPTH {
+        let mut records = Vec::new();
+        while reader.next_raw(&mut record)? {
+            records.push(record.clone());
+        }
+        records.shuffle(rng);
+        for record in &records {
+            write_record(format, output, record)?;
+        }
+        return Ok(records.len() as u64);
+    }
+
+    // scatter the records into buckets that should each fit in memory.
+    let n_buckets = (in_memory_size / (memory / 2).max(1) + 1).clamp(2, MAX_FAN_IN);
+    let buckets = (0..n_buckets).map(|_| scratch.new_file()).collect::<Vec<_>>();
+    let mut writers = buckets
+        .iter()
+        .map(|path| Ok(BufWriter::new(File::create(path)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    while reader.next_raw(&mut record)? {
+        write_record(format, &mut writers[rng.gen_range(0..n_buckets)], &record)?;
+    }
+    for mut writer in writers {
+        writer.flush()?;
+    }
+    let mut n = 0;
+    for bucket in buckets {
+        n += shuffle_into(
+            format,
+            std::slice::from_ref(&bucket),
+            output,
+            memory,
+            policy,
+            rng,
+            scratch,
+            depth + 1,
+        )?;
+        std::fs::remove_file(bucket)?;
+    }
+    Ok(n)
+}
+
+/// Checks that every input holds records in the same format, and returns it.
+fn check_formats(
+    input_files: &[PathBuf],
+    name: Option<&str>,
+) -> Result<&'static dyn DataFormat, String> {
+    let Some(first) = input_files.first() else {
+        return Err("no input files given".into());
+    };
+    let format = format_for_path(first, name)?;
+    for path in input_files {
+        if format_for_path(path, name)?.name() != format.name() {
+            return Err(format!(
+                "{} and {} hold records in different formats, and can't be combined",
+                first.display(),
+                path.display()
+            ));
+        }
+    }
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn scratch_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("viri_external_{}_{name}", std::process::id()))
+    }
+
+    fn sample_lines() -> Vec<String> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            "8/8/4k3/8/8/4K3/4P3/8 w - - 0 50",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 3 17",
+        ];
+        // many copies of each position, with both the same and different move counters.
+        let mut lines = Vec::new();
+        for i in 0..500 {
+            for (j, fen) in fens.iter().enumerate() {
+                let fen = if i % 2 == 0 { (*fen).to_string() } else { fen.replace(" 0 1", " 0 2") };
+                lines.push(format!("{fen} | {} | 0.5", i * 4 + j));
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn external_dedup_matches_in_memory() {
+        crate::magic::initialise();
+        let input = scratch_path("dedup_in.txt");
+        let output = scratch_path("dedup_out.txt");
+        std::fs::write(&input, sample_lines().join("\n")).unwrap();
+        // a tiny memory budget forces thousands of runs, and several merge passes.
+        for (key, expected) in [(DedupKey::Fen, 6), (DedupKey::Position, 4)] {
+            dedup_files(
+                &[input.clone(), input.clone()],
+                &output,
+                None,
+                key,
+                256,
+                InvalidDataPolicy::Strict,
+            )
+            .unwrap();
+            let text = std::fs::read_to_string(&output).unwrap();
+            let fens = text
+                .lines()
+                .map(|line| line.split('|').next().unwrap().trim().to_string())
+                .collect::<std::collections::HashSet<_>>();
+            assert_eq!(text.lines().count(), expected);
+            assert_eq!(fens.len(), expected);
+        }
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn external_shuffle_is_a_permutation() {
+        let input = scratch_path("shuffle_in.txt");
+        let output = scratch_path("shuffle_out.txt");
+        let lines = sample_lines();
+        std::fs::write(&input, lines.join("\n")).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        shuffle_files(
+            std::slice::from_ref(&input),
+            &output,
+            None,
+            4096,
+            InvalidDataPolicy::Strict,
+            &mut rng,
+        )
+        .unwrap();
+        let text = std::fs::read_to_string(&output).unwrap();
+        let mut shuffled = text.lines().map(str::to_string).collect::<Vec<_>>();
+        assert_ne!(shuffled, lines);
+        shuffled.sort();
+        let mut sorted = lines;
+        sorted.sort();
+        assert_eq!(shuffled, sorted);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+//! Filtering of training data by composable predicates on the positions and their labels.
+
+use std::{
+    array,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::atomic::AtomicBool,
+};
+
+use crate::{
+    board::Board,
+    definitions::{depth::Depth, MEGABYTE},
+    searchinfo::SearchInfo,
+    threadlocal::ThreadData,
+    timemgmt::{SearchLimit, TimeManager},
+    transpositiontable::TT,
+};
+
+use super::formats::{DataFormat, DataRecord, InvalidDataPolicy, Records};
+
+/// The number of records that are read in and filtered at once.
+const CHUNK_SIZE: usize = 100_000;
+
+/// A test on a single training position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    /// The number of pieces on the board, kings included, is in the range.
+    Men { min: i32, max: i32 },
+    /// The material signature (as in `KRPvKR`) is one of these.
+    Material(Vec<String>),
+    /// The white-relative eval is in the range. Records without an eval never pass.
+    Eval { min: i32, max: i32 },
+    /// The game phase, from 0 (opening) to 256 (endgame), is in the range.
+    Phase { min: i32, max: i32 },
+    /// The game result is one of these, in half-points for White.
+    Result(Vec<u8>),
+    /// The best move found by a search to this depth is neither a capture nor a promotion.
+    Quiet { depth: i32 },
+}
+
+/// A condition that positions must pass (or fail, if negated) to be kept.
+///
+/// Predicates are written as `<NAME>[:<ARGUMENT>]`, optionally prefixed with `!` to negate them:
+/// - `men:<RANGE>`: the number of pieces, kings included.
+/// - `material:<SIGNATURE>[,<SIGNATURE>...]`: the material, as in `KRPvKR`, with White first.
+/// - `eval:<RANGE>`: the white-relative eval.
+/// - `phase:<RANGE>`: the game phase, from 0 (opening) to 256 (endgame).
+/// - `result:<white|draw|black>[,...]`: the game result.
+/// - `quiet[:<DEPTH>]`: the best move from a search (to depth 4 by default) is quiet.
+///
+/// Ranges are inclusive, and written as `<MIN>..<MAX>`, `<MIN>..`, `..<MAX>`, or a single number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Predicate {
+    condition: Condition,
+    negated: bool,
+    spec: String,
+}
+
+/// Parses an inclusive range, with either bound optional.
+fn parse_range(s: &str) -> Result<(i32, i32), String> {
+    let parse = |bound: &str, default| {
+        if bound.is_empty() {
+            Ok(default)
+        } else {
+            bound
+                .parse::<i32>()
+                .map_err(|e| format!("invalid bound \"{bound}\" in range \"{s}\": {e}"))
+        }
+    };
+    if let Some((min, max)) = s.split_once("..") {
+        Ok((parse(min, i32::MIN)?, parse(max, i32::MAX)?))
+    } else {
+        let value = parse(s, 0)?;
+        Ok((value, value))
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s.trim();
+        let (negated, body) = spec.strip_prefix('!').map_or((false, spec), |body| (true, body));
+        let (name, argument) = body.split_once(':').unwrap_or((body, ""));
+        let needs_argument = || {
+            if argument.is_empty() {
+                Err(format!("predicate \"{name}\" needs an argument, as in \"{name}:<ARGUMENT>\""))
+            } else {
+                Ok(argument)
+            }
+        };
+        let condition = match name {
+            "men" => {
+                let (min, max) = parse_range(needs_argument()?)?;
+                Condition::Men { min, max }
+            }
+            "material" => {
+                Condition::Material(needs_argument()?.split(',').map(str::to_string).collect())
+            }
+            "eval" => {
+                let (min, max) = parse_range(needs_argument()?)?;
+                Condition::Eval { min, max }
+            }
+            "phase" => {
+                let (min, max) = parse_range(needs_argument()?)?;
+                Condition::Phase { min, max }
+            }
+            "result" => Condition::Result(
+                needs_argument()?
+                    .split(',')
+                    .map(|result| match result {
+                        "white" => Ok(2),
+                        "draw" => Ok(1),
+                        "black" => Ok(0),
+                        _ => Err(format!(
+                            "invalid result \"{result}\", expected \"white\", \"draw\", or \"black\""
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            "quiet" => Condition::Quiet {
+                depth: if argument.is_empty() {
+                    4
+                } else {
+                    argument.parse().map_err(|e| format!("invalid depth \"{argument}\": {e}"))?
+                },
+            },
+            _ => {
+                return Err(format!(
+                    "unknown predicate \"{name}\", expected one of men, material, eval, phase, result, or quiet"
+                ))
+            }
+        };
+        Ok(Self { condition, negated, spec: spec.to_string() })
+    }
+}
+
+impl Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+/// The state needed to run searches for the `quiet` predicate, made on first use.
+struct Searcher {
+    tt: TT,
+    thread: ThreadData,
+}
+
+impl Searcher {
+    fn new(board: &Board) -> Self {
+        let mut tt = TT::new();
+        tt.resize(16 * MEGABYTE);
+        Self { tt, thread: ThreadData::new(0, board) }
+    }
+
+    fn best_move_is_quiet(&mut self, board: &mut Board, depth: i32) -> bool {
+        self.thread.nnue.refresh_acc(board);
+        self.tt.clear();
+        let stopped = AtomicBool::new(false);
+        let time_manager =
+            TimeManager { limit: SearchLimit::Depth(Depth::new(depth)), ..TimeManager::default() };
+        let mut info =
+            SearchInfo { time_manager, print_to_stdout: false, ..SearchInfo::new(&stopped) };
+        let (_, best_move) = board.search_position::<true>(
+            &mut info,
+            array::from_mut(&mut self.thread),
+            self.tt.view(),
+        );
+        !board.is_tactical(best_move)
+    }
+}
+
+impl Predicate {
+    fn passes(
+        &self,
+        board: &mut Board,
+        record: &DataRecord,
+        searcher: &mut Option<Searcher>,
+    ) -> bool {
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let holds = match &self.condition {
+            Condition::Men { min, max } => (*min..=*max).contains(&i32::from(board.n_men())),
+            Condition::Material(signatures) => {
+                let signature = board.material_signature();
+                signatures.contains(&signature)
+            }
+            Condition::Eval { min, max } => {
+                record.eval.is_some_and(|eval| (*min..=*max).contains(&eval))
+            }
+            Condition::Phase { min, max } => (*min..=*max).contains(&board.phase()),
+            Condition::Result(results) => results.contains(&((record.wdl * 2.0).round() as u8)),
+            Condition::Quiet { depth } => searcher
+                .get_or_insert_with(|| Searcher::new(board))
+                .best_move_is_quiet(board, *depth),
+        };
+        holds != self.negated
+    }
+}
+
+/// Runs the predicates over a chunk of records, returning for each record
+/// the index of the first predicate that it failed, or [None] if it is kept.
+fn filter_chunk(
+    records: &[DataRecord],
+    predicates: &[Predicate],
+) -> Result<Vec<Option<usize>>, String> {
+    let mut board = Board::new();
+    let mut searcher = None;
+    records
+        .iter()
+        .map(|record| {
+            board
+                .set_from_fen(&record.fen)
+                .map_err(|e| format!("invalid FEN \"{}\": {e}", record.fen))?;
+            Ok(predicates
+                .iter()
+                .position(|predicate| !predicate.passes(&mut board, record, &mut searcher)))
+        })
+        .collect()
+}
+
+/// Split a data file into the records that pass every predicate, which are written to `kept_file`,
+/// and those that fail any of them, which are written to `rejected_file`.
+/// Both outputs are written in the same format as the input.
+pub fn filter(
+    input_file: &Path,
+    kept_file: &Path,
+    rejected_file: &Path,
+    format: &dyn DataFormat,
+    predicates: &[Predicate],
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    #![allow(clippy::cast_precision_loss)]
+    let mut records = Records::open(format, input_file, policy)?;
+    let mut kept = BufWriter::new(File::create(kept_file)?);
+    let mut rejected = BufWriter::new(File::create(rejected_file)?);
+    let start_time = std::time::Instant::now();
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    let mut n_kept = 0u64;
+    let mut rejections = vec![0u64; predicates.len()];
+    loop {
+        chunk.clear();
+        for record in records.by_ref().take(CHUNK_SIZE) {
+            chunk.push(record?);
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        let thread_chunk_size = chunk.len() / num_cpus::get() + 1;
+        let outcomes = std::thread::scope(|s| {
+            let handles = chunk
+                .chunks(thread_chunk_size)
+                .map(|records| s.spawn(|| filter_chunk(records, predicates)))
+                .collect::<Vec<_>>();
+            let mut outcomes = Vec::with_capacity(chunk.len());
+            for handle in handles {
+                outcomes.extend(handle.join().unwrap()?);
+            }
+            Ok::<_, String>(outcomes)
+        })?;
+        for (record, outcome) in chunk.iter().zip(outcomes) {
+            if let Some(failed) = outcome {
+                rejections[failed] += 1;
+                format.write(&mut rejected, record)?;
+            } else {
+                n_kept += 1;
+                format.write(&mut kept, record)?;
+            }
+        }
+    }
+    kept.flush()?;
+    rejected.flush()?;
+    records.report_skipped();
+    let elapsed = start_time.elapsed();
+    let total = n_kept + rejections.iter().sum::<u64>();
+    let percentage = |count: u64| count as f64 / total.max(1) as f64 * 100.0;
+    println!("Filtered {total} records in {}.{:03}s", elapsed.as_secs(), elapsed.subsec_millis());
+    println!(" |> kept: {n_kept} ({:.1}%)", percentage(n_kept));
+    if records.skipped() > 0 {
+        println!(" |> skipped as invalid: {}", records.skipped());
+    }
+    for (predicate, count) in predicates.iter().zip(rejections) {
+        println!(" |> rejected by {predicate}: {count} ({:.1}%)", percentage(count));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn predicates() {
+        crate::magic::initialise();
+        let record = |fen: &str, eval, wdl| DataRecord { fen: fen.into(), eval, wdl };
+        let records = [
+            record("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", Some(30), 0.5),
+            record("8/8/4k3/8/8/4K3/4P3/8 b - - 0 50", Some(450), 1.0),
+            record("8/8/4k3/8/3r4/4K3/4P3/8 w - - 0 50", Some(-200), 0.0),
+            record("8/8/4k3/8/3r4/4K3/4P3/8 w - - 0 50", None, 0.0),
+        ];
+        let run = |specs: &[&str]| {
+            let predicates =
+                specs.iter().map(|s| s.parse::<Predicate>().unwrap()).collect::<Vec<_>>();
+            filter_chunk(&records, &predicates).unwrap()
+        };
+        assert_eq!(run(&["men:3..4"]), [Some(0), None, None, None]);
+        assert_eq!(run(&["material:KPvK,KPvKR"]), [Some(0), None, None, None]);
+        assert_eq!(run(&["!material:KPvK"]), [None, Some(0), None, None]);
+        assert_eq!(run(&["eval:-100..100"]), [None, Some(0), Some(0), Some(0)]);
+        assert_eq!(run(&["eval:..-1"]), [Some(0), Some(0), None, Some(0)]);
+        assert_eq!(run(&["phase:..64"]), [None, Some(0), Some(0), Some(0)]);
+        assert_eq!(run(&["result:white,black", "men:4"]), [Some(0), Some(1), None, None]);
+        // the best move for white here is to take the hanging rook.
+        assert_eq!(run(&["quiet:3"]), [None, None, Some(0), Some(0)]);
+        assert!("men".parse::<Predicate>().is_err());
+        assert!("eval:a..b".parse::<Predicate>().is_err());
+        assert!("result:win".parse::<Predicate>().is_err());
+        assert!("colour:white".parse::<Predicate>().is_err());
+    }
+}
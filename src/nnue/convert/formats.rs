@@ -0,0 +1,500 @@
+//! Readers and writers for the formats that training data is stored in.
+//!
+//! Each format is a type implementing [`DataFormat`].
+//! A new format only needs an implementation of the trait and an entry in [`FORMATS`]
+//! to be usable by every command that works on data files.
+//!
+//! Reading never panics: every malformed record is turned into a [`DataError`] saying which
+//! file and line (or record) it came from, and an [`InvalidDataPolicy`] decides whether
+//! such records stop the command or are skipped and summarised at the end.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    board::{packed::PackedBoard, Board},
+    datagen::marlinformat::PackedRecord,
+    errors::{DataError, DataErrorKind, DataLocation},
+};
+
+/// The most skipped records that are listed individually in the summary.
+const MAX_REPORTED_ERRORS: usize = 10;
+
+/// A single training position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRecord {
+    /// The position, as a FEN.
+    pub fen: String,
+    /// The evaluation of the position from White's perspective, if the format stores one.
+    pub eval: Option<i32>,
+    /// The result of the game from White's perspective: 1.0 for a win, 0.5 for a draw, and 0.0 for a loss.
+    pub wdl: f32,
+}
+
+/// A format that training data can be stored in.
+pub trait DataFormat: Sync {
+    /// The name of the format, as given on the command line.
+    fn name(&self) -> &'static str;
+
+    /// The size of every record in bytes, for binary formats, or [None] for formats with one record per line.
+    fn record_size(&self) -> Option<usize>;
+
+    /// Parses a single record, which is a line without its line ending for text formats,
+    /// leaving `board` set to its position.
+    fn parse(&self, raw: &[u8], board: &mut Board) -> Result<DataRecord, DataErrorKind>;
+
+    /// Writes a single record.
+    fn write(&self, output: &mut dyn Write, record: &DataRecord) -> io::Result<()>;
+}
+
+/// Our texel-tuning format, `<FEN>;<WDL>`.
+pub struct OurTexel;
+/// marlinflow's text format, `<FEN> | <EVAL> | <WDL>`.
+pub struct Marlinflow;
+/// marlinformat's 32-byte binary records.
+pub struct Marlinformat;
+
+/// Every supported format.
+pub static FORMATS: [&dyn DataFormat; 3] = [&OurTexel, &Marlinflow, &Marlinformat];
+
+/// Looks up a format by name.
+pub fn format_by_name(name: &str) -> Result<&'static dyn DataFormat, String> {
+    FORMATS.iter().copied().find(|format| format.name() == name).ok_or_else(|| {
+        let names = FORMATS.iter().map(|format| format.name()).collect::<Vec<_>>();
+        format!("Unknown data format \"{name}\", expected one of {}", names.join(", "))
+    })
+}
+
+/// Gets the format of a data file, either by name or from the file extension.
+/// Files with a `.bin` extension hold marlinformat records, and anything else is marlinflow text.
+pub fn format_for_path(path: &Path, name: Option<&str>) -> Result<&'static dyn DataFormat, String> {
+    match name {
+        Some(name) => format_by_name(name),
+        None if path.extension().is_some_and(|ext| ext == "bin") => Ok(&Marlinformat),
+        None => Ok(&Marlinflow),
+    }
+}
+
+/// What to do with records that can't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidDataPolicy {
+    /// Stop at the first invalid record.
+    Strict,
+    /// Skip invalid records, and summarise them at the end.
+    Skip,
+}
+
+fn parse_text(raw: &[u8]) -> Result<&str, DataErrorKind> {
+    std::str::from_utf8(raw).map_err(|e| DataErrorKind::Malformed(e.to_string()))
+}
+
+fn parse_fen(fen: &str, board: &mut Board) -> Result<String, DataErrorKind> {
+    let fen = fen.trim();
+    board.set_from_fen(fen).map_err(DataErrorKind::InvalidFen)?;
+    check_position(board)?;
+    Ok(fen.to_string())
+}
+
+/// Rejects positions that couldn't arise in a game, which would upset the evaluation or the search.
+fn check_position(board: &Board) -> Result<(), DataErrorKind> {
+    board.check_validity().map_err(DataErrorKind::InvalidPosition)?;
+    if board.in_check::<{ Board::THEM }>() {
+        return Err(DataErrorKind::InvalidPosition("the side not to move is in check".into()));
+    }
+    Ok(())
+}
+
+fn parse_wdl(wdl: &str) -> Result<f32, DataErrorKind> {
+    let wdl = wdl.trim();
+    match wdl.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(DataErrorKind::InvalidWdl(wdl.to_string())),
+    }
+}
+
+fn parse_eval(eval: &str) -> Result<i32, DataErrorKind> {
+    let eval = eval.trim();
+    eval.parse().map_err(|_| DataErrorKind::InvalidEval(eval.to_string()))
+}
+
+impl DataFormat for OurTexel {
+    fn name(&self) -> &'static str {
+        "texel"
+    }
+
+    fn record_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn parse(&self, raw: &[u8], board: &mut Board) -> Result<DataRecord, DataErrorKind> {
+        let line = parse_text(raw)?;
+        let (fen, wdl) = line.rsplit_once(';').ok_or_else(|| {
+            DataErrorKind::Malformed(format!("expected \"<FEN>;<WDL>\", got \"{line}\""))
+        })?;
+        Ok(DataRecord { fen: parse_fen(fen, board)?, eval: None, wdl: parse_wdl(wdl)? })
+    }
+
+    fn write(&self, output: &mut dyn Write, record: &DataRecord) -> io::Result<()> {
+        writeln!(output, "{};{:.1}", record.fen, record.wdl)
+    }
+}
+
+impl DataFormat for Marlinflow {
+    fn name(&self) -> &'static str {
+        "marlinflow"
+    }
+
+    fn record_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn parse(&self, raw: &[u8], board: &mut Board) -> Result<DataRecord, DataErrorKind> {
+        let line = parse_text(raw)?;
+        let mut parts = line.split('|');
+        let (Some(fen), Some(eval), Some(wdl), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(DataErrorKind::Malformed(format!(
+                "expected \"<FEN> | <EVAL> | <WDL>\", got \"{line}\""
+            )));
+        };
+        Ok(DataRecord {
+            fen: parse_fen(fen, board)?,
+            eval: Some(parse_eval(eval)?),
+            wdl: parse_wdl(wdl)?,
+        })
+    }
+
+    fn write(&self, output: &mut dyn Write, record: &DataRecord) -> io::Result<()> {
+        let eval = record.eval.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "marlinflow records need an eval")
+        })?;
+        writeln!(output, "{} | {eval} | {:.1}", record.fen, record.wdl)
+    }
+}
+
+impl DataFormat for Marlinformat {
+    fn name(&self) -> &'static str {
+        "marlinformat"
+    }
+
+    fn record_size(&self) -> Option<usize> {
+        Some(PackedRecord::SIZE)
+    }
+
+    fn parse(&self, raw: &[u8], board: &mut Board) -> Result<DataRecord, DataErrorKind> {
+        let bytes = raw.try_into().map_err(|_| {
+            DataErrorKind::Malformed(format!(
+                "expected {} bytes, got {}",
+                PackedRecord::SIZE,
+                raw.len()
+            ))
+        })?;
+        let record = PackedRecord::from_bytes(bytes);
+        if record.wdl() > PackedRecord::WHITE_WIN {
+            return Err(DataErrorKind::InvalidWdl(record.wdl().to_string()));
+        }
+        let (unpacked, eval, _) = record.unpack().map_err(DataErrorKind::InvalidPackedBoard)?;
+        check_position(&unpacked)?;
+        *board = unpacked;
+        Ok(DataRecord { fen: board.fen(), eval: Some(eval), wdl: record.wdl_float() })
+    }
+
+    fn write(&self, output: &mut dyn Write, record: &DataRecord) -> io::Result<()> {
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let board = Board::from_fen(&record.fen)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let eval = record.eval.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "marlinformat records need an eval")
+        })?;
+        let wdl = (record.wdl * 2.0).round() as u8;
+        output.write_all(&PackedRecord::new(PackedBoard::pack(&board), eval, wdl).to_bytes())
+    }
+}
+
+/// Applies an [`InvalidDataPolicy`] to the invalid records of a data file.
+pub struct InvalidRecords {
+    policy: InvalidDataPolicy,
+    skipped: usize,
+    skipped_errors: Vec<DataError>,
+}
+
+impl InvalidRecords {
+    /// Starts tracking invalid records under `policy`.
+    pub const fn new(policy: InvalidDataPolicy) -> Self {
+        Self { policy, skipped: 0, skipped_errors: Vec::new() }
+    }
+
+    /// Passes an error back if it should stop the command, or counts the record as skipped.
+    /// I/O errors and truncated records always stop the command,
+    /// as they mean that the rest of the file can't be trusted.
+    pub fn check(&mut self, error: DataError) -> Result<(), DataError> {
+        if self.policy == InvalidDataPolicy::Strict || error.kind.is_fatal() {
+            return Err(error);
+        }
+        self.skipped += 1;
+        if self.skipped_errors.len() < MAX_REPORTED_ERRORS {
+            self.skipped_errors.push(error);
+        }
+        Ok(())
+    }
+
+    /// Prints a summary of the records of `file` that were skipped, if there were any.
+    pub fn report(&self, file: &Path) {
+        if self.skipped == 0 {
+            return;
+        }
+        eprintln!("Skipped {} invalid records in {}:", self.skipped, file.display());
+        for error in &self.skipped_errors {
+            eprintln!(" |> {}: {}", error.location, error.kind);
+        }
+        if self.skipped > self.skipped_errors.len() {
+            eprintln!(" |> ... and {} more", self.skipped - self.skipped_errors.len());
+        }
+    }
+
+    /// The number of records that were skipped.
+    pub const fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// The errors of the first few records that were skipped.
+    pub fn skipped_errors(&self) -> &[DataError] {
+        &self.skipped_errors
+    }
+}
+
+/// A streaming reader for the records of a data file.
+///
+/// Iterating yields parsed records. Under [`InvalidDataPolicy::Strict`], an invalid record is
+/// yielded as an error, while under [`InvalidDataPolicy::Skip`] it is counted and passed over.
+/// I/O errors and truncated records are always yielded, as they mean that the rest of the file
+/// can't be trusted.
+pub struct Records<'a> {
+    format: &'a dyn DataFormat,
+    reader: Box<dyn BufRead + 'a>,
+    file: PathBuf,
+    /// The number of lines or records read so far.
+    position: usize,
+    board: Board,
+    buffer: Vec<u8>,
+    invalid: InvalidRecords,
+}
+
+impl<'a> Records<'a> {
+    /// Reads records in `format` from `reader`, which holds the contents of `file`.
+    pub fn new(
+        format: &'a dyn DataFormat,
+        reader: Box<dyn BufRead + 'a>,
+        file: &Path,
+        policy: InvalidDataPolicy,
+    ) -> Self {
+        Self {
+            format,
+            reader,
+            file: file.to_owned(),
+            position: 0,
+            board: Board::new(),
+            buffer: Vec::new(),
+            invalid: InvalidRecords::new(policy),
+        }
+    }
+
+    fn error(&self, location: DataLocation, kind: DataErrorKind) -> DataError {
+        DataError { file: self.file.clone(), location, kind }
+    }
+
+    /// Opens a data file for reading.
+    pub fn open(
+        format: &'a dyn DataFormat,
+        file: &Path,
+        policy: InvalidDataPolicy,
+    ) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(file)?);
+        Ok(Self::new(format, Box::new(reader), file, policy))
+    }
+
+    /// Reads the bytes of the next record into `raw` without parsing them,
+    /// returning where the record came from, or [None] at the end of the file.
+    pub fn next_raw(&mut self, raw: &mut Vec<u8>) -> Option<Result<DataLocation, DataError>> {
+        let io_error = |this: &Self, e: io::Error, location| {
+            Some(Err(this.error(location, DataErrorKind::Io(e.to_string()))))
+        };
+        raw.clear();
+        if let Some(size) = self.format.record_size() {
+            let location = DataLocation::Record(self.position);
+            match self.reader.fill_buf() {
+                Ok([]) => return None,
+                Ok(_) => {}
+                Err(e) => return io_error(self, e, location),
+            }
+            self.position += 1;
+            raw.resize(size, 0);
+            return match self.reader.read_exact(raw) {
+                Ok(()) => Some(Ok(location)),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    Some(Err(self.error(location, DataErrorKind::Truncated)))
+                }
+                Err(e) => io_error(self, e, location),
+            };
+        }
+        loop {
+            self.position += 1;
+            let location = DataLocation::Line(self.position);
+            match self.reader.read_until(b'\n', raw) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return io_error(self, e, location),
+            }
+            while raw.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+                raw.pop();
+            }
+            if !raw.iter().all(u8::is_ascii_whitespace) {
+                return Some(Ok(location));
+            }
+            raw.clear();
+        }
+    }
+
+    /// Reads the next record, leaving its unparsed bytes in `raw`,
+    /// so that it can be copied to an output in the same format unchanged.
+    pub fn next_with_raw(&mut self, raw: &mut Vec<u8>) -> Option<Result<DataRecord, DataError>> {
+        loop {
+            let error = match self.next_raw(raw)? {
+                Ok(location) => match self.format.parse(raw, &mut self.board) {
+                    Ok(record) => return Some(Ok(record)),
+                    Err(kind) => self.error(location, kind),
+                },
+                Err(error) => error,
+            };
+            if let Err(error) = self.invalid.check(error) {
+                return Some(Err(error));
+            }
+        }
+    }
+
+    /// Prints a summary of the records that were skipped for being invalid, if there were any.
+    pub fn report_skipped(&self) {
+        self.invalid.report(&self.file);
+    }
+
+    /// The number of records that were skipped for being invalid.
+    pub const fn skipped(&self) -> usize {
+        self.invalid.skipped()
+    }
+
+    /// The errors of the first few records that were skipped for being invalid.
+    pub fn skipped_errors(&self) -> &[DataError] {
+        self.invalid.skipped_errors()
+    }
+
+    /// The position of the record that was read last.
+    pub const fn board(&self) -> &Board {
+        &self.board
+    }
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<DataRecord, DataError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let result = self.next_with_raw(&mut buffer);
+        self.buffer = buffer;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{DataErrorKind, DataLocation};
+    use std::path::Path;
+
+    #[test]
+    fn formats_round_trip() {
+        crate::magic::initialise();
+        let records = vec![
+            DataRecord {
+                fen: "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1".into(),
+                eval: Some(-35),
+                wdl: 0.5,
+            },
+            DataRecord {
+                fen: "8/8/4k3/8/8/4K3/4P3/8 w - - 3 50".into(),
+                eval: Some(400),
+                wdl: 1.0,
+            },
+        ];
+        for format in FORMATS {
+            let mut bytes = Vec::new();
+            for record in &records {
+                format.write(&mut bytes, record).unwrap();
+            }
+            let read = Records::new(
+                format,
+                Box::new(bytes.as_slice()),
+                Path::new("test"),
+                InvalidDataPolicy::Strict,
+            )
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+            let expected = records
+                .iter()
+                .map(|r| DataRecord {
+                    eval: r.eval.filter(|_| format.name() != "texel"),
+                    ..r.clone()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(read, expected, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn invalid_records_are_located() {
+        crate::magic::initialise();
+        let text = "8/8/4k3/8/8/4K3/4P3/8 w - - 0 1 | 10 | 1.0\n\
+                    \n\
+                    8/8/4k3/8/8/4K3/4P3/8 w - - 0 1 | ten | 1.0\n\
+                    8/8/4k3/8/8/4K3/4P3/8 w - - 0 1 | 10\n\
+                    8/8/4k3/8/8/4X3/4P3/8 w - - 0 1 | 10 | 1.0\n\
+                    8/8/4k3/8/8/4K3/4P3/8 w - - 0 1 | 10 | 2.0\n\
+                    8/8/4k3/8/8/4K3/4P3/8 b - - 0 1 | -10 | 0.0\n";
+        let read = |policy| {
+            Records::new(&Marlinflow, Box::new(text.as_bytes()), Path::new("data.txt"), policy)
+        };
+
+        let mut strict = read(InvalidDataPolicy::Strict);
+        assert!(strict.next().unwrap().is_ok());
+        let error = strict.next().unwrap().unwrap_err();
+        assert_eq!(error.location, DataLocation::Line(3));
+        assert_eq!(error.kind, DataErrorKind::InvalidEval("ten".into()));
+        assert_eq!(error.to_string(), "data.txt, line 3: Invalid eval \"ten\"");
+        let errors =
+            strict.by_ref().filter_map(Result::err).map(|e| e.location).collect::<Vec<_>>();
+        assert_eq!(errors, [DataLocation::Line(4), DataLocation::Line(5), DataLocation::Line(6)]);
+
+        let mut skipping = read(InvalidDataPolicy::Skip);
+        let records = skipping.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].eval, Some(-10));
+        assert_eq!(skipping.skipped(), 4);
+
+        // a binary file that ends part of the way through a record.
+        let bytes = [0; 40];
+        let mut binary = Records::new(
+            &Marlinformat,
+            Box::new(&bytes[..]),
+            Path::new("data.bin"),
+            InvalidDataPolicy::Skip,
+        );
+        let error = binary.next().unwrap().unwrap_err();
+        assert_eq!(error.location, DataLocation::Record(1));
+        assert_eq!(error.kind, DataErrorKind::Truncated);
+        assert_eq!(binary.skipped(), 1);
+        assert!(binary.next().is_none());
+    }
+}
@@ -0,0 +1,409 @@
+//! Validation and statistics for training data files.
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Write as _,
+    io::{self, Write},
+    path::Path,
+};
+
+use serde_json::{json, Value};
+
+use crate::{board::Board, datagen::marlinformat::PackedRecord, errors::DataError, piece::Colour};
+
+use super::formats::{DataFormat, InvalidDataPolicy, Records};
+
+/// The size of a bucket of the eval histogram, in centipawns.
+const EVAL_BUCKET_SIZE: i32 = 100;
+/// Evals beyond this (in either direction) go in the outermost buckets of the histogram.
+const EVAL_HISTOGRAM_LIMIT: i32 = 1000;
+/// The number of buckets in the eval histogram, including the two outermost ones.
+const EVAL_BUCKETS: usize = (2 * EVAL_HISTOGRAM_LIMIT / EVAL_BUCKET_SIZE) as usize + 2;
+/// The size of a bucket of the phase histogram. Phase runs from 0 (opening) to 256 (endgame).
+const PHASE_BUCKET_SIZE: i32 = 32;
+/// The smallest eval that is taken as a prediction of the game result.
+const DECISIVE_EVAL: i32 = 200;
+/// The number of material signatures that are listed individually.
+const TOP_SIGNATURES: usize = 20;
+/// The most distinct positions that are remembered to find duplicates, bounding the memory used.
+/// Beyond this, the duplicate count is only a lower bound.
+const MAX_TRACKED_POSITIONS: usize = 1 << 24;
+
+/// Counts and histograms for a data file.
+struct DataStats {
+    records: u64,
+    invalid: u64,
+    errors: Vec<(String, String)>,
+    side_to_move: [u64; 2],
+    in_check: u64,
+    duplicates: u64,
+    /// Hashkeys of the positions seen so far, up to `max_tracked` of them.
+    seen: HashSet<u64>,
+    max_tracked: usize,
+    /// Positions that were neither found in `seen` nor added to it, as it was full.
+    untracked: u64,
+    phases: [u64; 256 / PHASE_BUCKET_SIZE as usize + 1],
+    piece_counts: [u64; 33],
+    signatures: HashMap<String, u64>,
+    evals: u64,
+    eval_sum: f64,
+    eval_square_sum: f64,
+    eval_range: Option<(i32, i32)>,
+    eval_histogram: [u64; EVAL_BUCKETS],
+    /// Results, indexed by the marlinformat WDL byte.
+    results: [u64; 3],
+    /// Decisive evals whose game was won by the favoured side, drawn, or lost by the favoured side.
+    agreement: [u64; 3],
+}
+
+impl DataStats {
+    fn new() -> Self {
+        Self {
+            records: 0,
+            invalid: 0,
+            errors: Vec::new(),
+            side_to_move: [0; 2],
+            in_check: 0,
+            duplicates: 0,
+            seen: HashSet::new(),
+            max_tracked: MAX_TRACKED_POSITIONS,
+            untracked: 0,
+            phases: [0; 256 / PHASE_BUCKET_SIZE as usize + 1],
+            piece_counts: [0; 33],
+            signatures: HashMap::new(),
+            evals: 0,
+            eval_sum: 0.0,
+            eval_square_sum: 0.0,
+            eval_range: None,
+            eval_histogram: [0; EVAL_BUCKETS],
+            results: [0; 3],
+            agreement: [0; 3],
+        }
+    }
+
+    /// Adds the records that `records` skipped for being invalid.
+    fn add_skipped(&mut self, records: &Records) {
+        let skipped = records.skipped() as u64;
+        self.records += skipped;
+        self.invalid += skipped;
+        self.errors.extend(
+            records
+                .skipped_errors()
+                .iter()
+                .map(|error| (error.location.to_string(), error.kind.to_string())),
+        );
+    }
+
+    /// Adds a valid position and its labels to the statistics.
+    /// `wdl` is the result from White's perspective, as a marlinformat WDL byte.
+    fn add(&mut self, board: &Board, eval: Option<i32>, wdl: u8) {
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        self.records += 1;
+        self.side_to_move[board.turn().index()] += 1;
+        self.in_check += u64::from(board.in_check::<{ Board::US }>());
+        let key = board.hashkey();
+        if self.seen.contains(&key) {
+            self.duplicates += 1;
+        } else if self.seen.len() < self.max_tracked {
+            self.seen.insert(key);
+        } else {
+            self.untracked += 1;
+        }
+        self.phases[(board.phase().clamp(0, 256) / PHASE_BUCKET_SIZE) as usize] += 1;
+        self.piece_counts[usize::from(board.n_men())] += 1;
+        *self.signatures.entry(board.material_signature()).or_insert(0) += 1;
+        if let Some(eval) = eval {
+            self.evals += 1;
+            self.eval_sum += f64::from(eval);
+            self.eval_square_sum += f64::from(eval) * f64::from(eval);
+            self.eval_range = Some(
+                self.eval_range.map_or((eval, eval), |(min, max)| (min.min(eval), max.max(eval))),
+            );
+            let bucket = (eval.clamp(-EVAL_HISTOGRAM_LIMIT - 1, EVAL_HISTOGRAM_LIMIT)
+                + EVAL_HISTOGRAM_LIMIT
+                + EVAL_BUCKET_SIZE)
+                .div_euclid(EVAL_BUCKET_SIZE);
+            self.eval_histogram[bucket as usize] += 1;
+        }
+        self.results[usize::from(wdl)] += 1;
+        if let Some(eval) = eval {
+            if eval.abs() >= DECISIVE_EVAL {
+                let favoured =
+                    if eval > 0 { PackedRecord::WHITE_WIN } else { PackedRecord::BLACK_WIN };
+                let index = match wdl {
+                    PackedRecord::DRAW => 1,
+                    wdl if wdl == favoured => 0,
+                    _ => 2,
+                };
+                self.agreement[index] += 1;
+            }
+        }
+    }
+
+    const fn valid(&self) -> u64 {
+        self.records - self.invalid
+    }
+
+    fn eval_mean_and_stddev(&self) -> (f64, f64) {
+        #![allow(clippy::cast_precision_loss)]
+        if self.evals == 0 {
+            return (0.0, 0.0);
+        }
+        let n = self.evals as f64;
+        let mean = self.eval_sum / n;
+        (mean, mean.mul_add(-mean, self.eval_square_sum / n).max(0.0).sqrt())
+    }
+
+    /// The label of an eval histogram bucket.
+    fn eval_bucket_name(bucket: usize) -> String {
+        #![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let low = bucket as i32 * EVAL_BUCKET_SIZE - EVAL_HISTOGRAM_LIMIT - EVAL_BUCKET_SIZE;
+        if bucket == 0 {
+            format!("< {}", -EVAL_HISTOGRAM_LIMIT)
+        } else if bucket == EVAL_BUCKETS - 1 {
+            format!(">= {EVAL_HISTOGRAM_LIMIT}")
+        } else {
+            format!("{low} to {}", low + EVAL_BUCKET_SIZE - 1)
+        }
+    }
+
+    /// The label of a phase histogram bucket.
+    fn phase_bucket_name(bucket: usize) -> String {
+        #![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let low = bucket as i32 * PHASE_BUCKET_SIZE;
+        format!("{low} to {}", (low + PHASE_BUCKET_SIZE - 1).min(256))
+    }
+
+    /// The most common material signatures, most common first.
+    fn top_signatures(&self) -> Vec<(&str, u64)> {
+        let mut signatures =
+            self.signatures.iter().map(|(sig, &count)| (sig.as_str(), count)).collect::<Vec<_>>();
+        signatures.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        signatures.truncate(TOP_SIGNATURES);
+        signatures
+    }
+
+    fn to_json(&self) -> Value {
+        let (mean, stddev) = self.eval_mean_and_stddev();
+        json!({
+            "records": self.records,
+            "valid": self.valid(),
+            "invalid": self.invalid,
+            "errors": self.errors.iter().map(|(location, error)| json!({ "location": location, "error": error })).collect::<Vec<_>>(),
+            "side_to_move": { "white": self.side_to_move[Colour::WHITE.index()], "black": self.side_to_move[Colour::BLACK.index()] },
+            "in_check": self.in_check,
+            "duplicates": self.duplicates,
+            "duplicates_lower_bound": self.untracked > 0,
+            "phase": self.phases.iter().enumerate().map(|(i, &count)| json!({ "bucket": Self::phase_bucket_name(i), "count": count })).collect::<Vec<_>>(),
+            "piece_count": self.piece_counts.iter().enumerate().filter(|(_, &count)| count > 0).map(|(i, &count)| json!({ "pieces": i, "count": count })).collect::<Vec<_>>(),
+            "distinct_material_signatures": self.signatures.len(),
+            "material_signatures": self.top_signatures().into_iter().map(|(sig, count)| json!({ "signature": sig, "count": count })).collect::<Vec<_>>(),
+            "eval": {
+                "count": self.evals,
+                "mean": mean,
+                "stddev": stddev,
+                "min": self.eval_range.map(|(min, _)| min),
+                "max": self.eval_range.map(|(_, max)| max),
+                "histogram": self.eval_histogram.iter().enumerate().map(|(i, &count)| json!({ "bucket": Self::eval_bucket_name(i), "count": count })).collect::<Vec<_>>(),
+            },
+            "results": {
+                "white_win": self.results[usize::from(PackedRecord::WHITE_WIN)],
+                "draw": self.results[usize::from(PackedRecord::DRAW)],
+                "black_win": self.results[usize::from(PackedRecord::BLACK_WIN)],
+            },
+            "eval_result_agreement": {
+                "decisive_eval": DECISIVE_EVAL,
+                "agree": self.agreement[0],
+                "drawn": self.agreement[1],
+                "disagree": self.agreement[2],
+            },
+        })
+    }
+
+    fn to_text(&self) -> String {
+        #![allow(clippy::cast_precision_loss)]
+        let percentage = |count: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                (count as f64 / total as f64 * 1000.0).round() / 10.0
+            }
+        };
+        let valid = self.valid();
+        let mut out = String::new();
+        let w = &mut out;
+        writeln!(w, "Records: {}", self.records).unwrap();
+        writeln!(w, " |> valid: {valid} ({}%)", percentage(valid, self.records)).unwrap();
+        writeln!(w, " |> invalid: {} ({}%)", self.invalid, percentage(self.invalid, self.records))
+            .unwrap();
+        for (location, error) in &self.errors {
+            writeln!(w, "     {location}: {error}").unwrap();
+        }
+        if self.invalid > self.errors.len() as u64 {
+            writeln!(w, "     ... and {} more", self.invalid - self.errors.len() as u64).unwrap();
+        }
+        writeln!(w, "Side to move:").unwrap();
+        for (name, colour) in [("white", Colour::WHITE), ("black", Colour::BLACK)] {
+            let count = self.side_to_move[colour.index()];
+            writeln!(w, " |> {name}: {count} ({}%)", percentage(count, valid)).unwrap();
+        }
+        writeln!(w, "In check: {} ({}%)", self.in_check, percentage(self.in_check, valid)).unwrap();
+        write!(w, "Duplicates: {} ({}%)", self.duplicates, percentage(self.duplicates, valid))
+            .unwrap();
+        if self.untracked > 0 {
+            write!(
+                w,
+                " (lower bound: only the first {} distinct positions were tracked)",
+                self.max_tracked
+            )
+            .unwrap();
+        }
+        writeln!(w).unwrap();
+        writeln!(w, "Game phase (0 = opening, 256 = endgame):").unwrap();
+        for (i, &count) in self.phases.iter().enumerate() {
+            let name = Self::phase_bucket_name(i);
+            writeln!(w, " |> {name: >10}: {count} ({}%)", percentage(count, valid)).unwrap();
+        }
+        writeln!(w, "Piece count:").unwrap();
+        for (i, &count) in self.piece_counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            writeln!(w, " |> {i: >2}: {count} ({}%)", percentage(count, valid)).unwrap();
+        }
+        writeln!(w, "Material signatures ({} distinct):", self.signatures.len()).unwrap();
+        for (sig, count) in self.top_signatures() {
+            writeln!(w, " |> {sig}: {count} ({}%)", percentage(count, valid)).unwrap();
+        }
+        let (mean, stddev) = self.eval_mean_and_stddev();
+        writeln!(w, "Evals: {}", self.evals).unwrap();
+        if let Some((min, max)) = self.eval_range {
+            writeln!(w, " |> mean {mean:.1}, stddev {stddev:.1}, min {min}, max {max}").unwrap();
+            for (i, &count) in self.eval_histogram.iter().enumerate() {
+                let name = Self::eval_bucket_name(i);
+                writeln!(w, " |> {name: >12}: {count} ({}%)", percentage(count, self.evals))
+                    .unwrap();
+            }
+        }
+        let results = self.results.iter().sum();
+        writeln!(w, "Results: {results}").unwrap();
+        for (name, wdl) in [
+            ("white wins", PackedRecord::WHITE_WIN),
+            ("draws", PackedRecord::DRAW),
+            ("black wins", PackedRecord::BLACK_WIN),
+        ] {
+            let count = self.results[usize::from(wdl)];
+            writeln!(w, " |> {name}: {count} ({}%)", percentage(count, results)).unwrap();
+        }
+        let decisive = self.agreement.iter().sum();
+        writeln!(w, "Eval vs result, for evals of at least {DECISIVE_EVAL}: {decisive}").unwrap();
+        for (name, count) in [
+            ("won by the favoured side", self.agreement[0]),
+            ("drawn", self.agreement[1]),
+            ("lost by the favoured side", self.agreement[2]),
+        ] {
+            writeln!(w, " |> {name}: {count} ({}%)", percentage(count, decisive)).unwrap();
+        }
+        out
+    }
+}
+
+/// Gathers statistics about every record of `records`.
+/// Invalid records are handled by the policy `records` was opened with:
+/// skipped records are counted and listed in the statistics, and any other error is returned.
+fn gather_stats(records: &mut Records) -> Result<DataStats, DataError> {
+    #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let mut stats = DataStats::new();
+    let mut raw = Vec::new();
+    while let Some(record) = records.next_with_raw(&mut raw) {
+        let record = record?;
+        stats.add(records.board(), record.eval, (record.wdl * 2.0).round() as u8);
+    }
+    stats.add_skipped(records);
+    Ok(stats)
+}
+
+/// Validate every record of a data file in `format`, and report statistics about the data.
+/// The report is written to `output_file` if one is given, and to stdout otherwise.
+pub fn data_stats(
+    input_file: &Path,
+    output_file: Option<&Path>,
+    format: &dyn DataFormat,
+    policy: InvalidDataPolicy,
+    as_json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut records = Records::open(format, input_file, policy)?;
+    let stats = gather_stats(&mut records)?;
+    records.report_skipped();
+    let report = if as_json {
+        serde_json::to_string_pretty(&stats.to_json())? + "\n"
+    } else {
+        stats.to_text()
+    };
+    match output_file {
+        Some(path) => std::fs::write(path, report)?,
+        None => io::stdout().write_all(report.as_bytes())?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::DataLocation, nnue::convert::formats::Marlinflow};
+
+    #[test]
+    fn stats_of_mixed_data() {
+        crate::magic::initialise();
+        let lines = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 30 | 0.5",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 25 | 1.0",
+            "8/8/4k3/8/8/4K3/4P3/8 b - - 0 50 | 450 | 1.0",
+            "8/8/4k3/8/8/4K3/4P3/8 b - - 0 50 | -450 | 1.0",
+            "8/8/4k3/8/8/4K3/4P3/8 b - - 0 50;0.5",
+            "4k3/8/8/8/8/8/4R3/4K3 b - - 0 1 | 0 | 0.5",
+            "8/8/8/8/8/8/8/8 w - - 0 1 | 0 | 0.5",
+            "4k3/4R3/8/8/8/8/8/4K3 w - - 0 1 | 0 | 0.5",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | xx | 0.5",
+        ]
+        .join("\n");
+        let file = Path::new("data.txt");
+        let open = |policy| Records::new(&Marlinflow, Box::new(lines.as_bytes()), file, policy);
+
+        let error = gather_stats(&mut open(InvalidDataPolicy::Strict)).err().unwrap();
+        assert_eq!(error.location, DataLocation::Line(5));
+
+        let stats = gather_stats(&mut open(InvalidDataPolicy::Skip)).unwrap();
+        assert_eq!(stats.records, 9);
+        assert_eq!(stats.invalid, 4);
+        let invalid_lines = stats.errors.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>();
+        assert_eq!(invalid_lines, ["line 5", "line 7", "line 8", "line 9"]);
+        assert_eq!(stats.duplicates, 2);
+        assert_eq!(stats.in_check, 1);
+        assert_eq!(stats.side_to_move, [2, 3]);
+        assert_eq!(stats.signatures["KPvK"], 2);
+        assert_eq!(stats.evals, 5);
+        assert_eq!(stats.eval_range, Some((-450, 450)));
+        assert_eq!(stats.results, [0, 2, 3]);
+        assert_eq!(stats.agreement, [1, 0, 1]);
+        let json = stats.to_json();
+        assert_eq!(json["valid"], 5);
+        assert_eq!(json["piece_count"][0]["pieces"], 3);
+        assert_eq!(json["piece_count"][0]["count"], 3);
+    }
+
+    #[test]
+    fn duplicates_beyond_the_tracking_limit_are_a_lower_bound() {
+        crate::magic::initialise();
+        let mut stats = DataStats { max_tracked: 1, ..DataStats::new() };
+        let start = Board::from_fen(Board::STARTING_FEN).unwrap();
+        let endgame = Board::from_fen("8/8/4k3/8/8/4K3/4P3/8 b - - 0 50").unwrap();
+        for board in [&start, &start, &endgame, &endgame] {
+            stats.add(board, None, 1);
+        }
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.untracked, 2);
+        assert_eq!(stats.to_json()["duplicates_lower_bound"], true);
+
+        assert!(stats.to_text().contains("Duplicates: 1 (25"));
+        assert!(stats.to_text().contains("(lower bound: only the first 1 distinct positions"));
+    }
+}
@@ -0,0 +1,460 @@
+//! Floating-point networks, as emitted by the trainer.
+//!
+//! These are the source that quantised networks are produced from, and serve as
+//! a reference implementation to check the quantised inference against.
+
+use std::fs;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    board::{movegen::BitLoop, Board},
+    piece::{Colour, Piece, PieceType},
+};
+
+use super::{
+    network::{
+        feature_indices, BucketLayout, NNUEParams, OutputBuckets, INPUT, LAYER_1_SIZE,
+        MAX_LAYER_WIDTH, QA, QAB, QB, QH, SCALE,
+    },
+    quantise::{LayerStats, QuantisationStats},
+};
+
+/// A fully-connected layer after L1, holding the weights for every output bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatLayer {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Weights, one row of `inputs` per output neuron, bucket by bucket.
+    pub weights: Vec<f32>,
+    /// Biases, one per output neuron, bucket by bucket.
+    pub bias: Vec<f32>,
+}
+
+/// An unquantised network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatNNUEParams {
+    pub buckets: BucketLayout,
+    pub output_buckets: OutputBuckets,
+    /// One row of `LAYER_1_SIZE` weights for each input feature, bucket by bucket.
+    pub feature_weights: Vec<f32>,
+    pub feature_bias: Vec<f32>,
+    /// The hidden layers between L1 and the output, if any.
+    pub hidden: Vec<FloatLayer>,
+    /// One row of output weights for each output bucket.
+    pub output_weights: Vec<f32>,
+    pub output_bias: Vec<f32>,
+}
+
+/// Read a JSON array of numbers.
+fn floats(value: &Value, name: &str) -> Vec<f32> {
+    #![allow(clippy::cast_possible_truncation)]
+    let values = value.as_array().unwrap_or_else(|| panic!("{name} must be an array"));
+    values
+        .iter()
+        .map(|v| v.as_f64().unwrap_or_else(|| panic!("{name} must contain numbers")) as f32)
+        .collect()
+}
+
+/// Read a JSON matrix (an array of rows), returning the number of rows and the flattened values.
+fn matrix(value: &Value, name: &str) -> (usize, Vec<f32>) {
+    let rows = value.as_array().unwrap_or_else(|| panic!("{name} must be an array of rows"));
+    let width = rows.first().and_then(Value::as_array).map_or(0, Vec::len);
+    let mut out = Vec::with_capacity(rows.len() * width);
+    for row in rows {
+        let row = floats(row, name);
+        assert_eq!(row.len(), width, "rows of {name} have different lengths");
+        out.extend(row);
+    }
+    (rows.len(), out)
+}
+
+impl FloatNNUEParams {
+    /// The widths of the hidden layers between L1 and the output.
+    pub fn hidden_sizes(&self) -> Vec<usize> {
+        self.hidden.iter().map(|layer| layer.outputs).collect()
+    }
+
+    /// The width of the input to the output layer.
+    fn output_inputs(&self) -> usize {
+        self.hidden.last().map_or(LAYER_1_SIZE * 2, |layer| layer.outputs)
+    }
+
+    /// Load a network from the JSON emitted by the trainer.
+    ///
+    /// The perspective layer is `perspective.weight` / `perspective.bias`, and the output
+    /// layer `out.weight` / `out.bias`, with one row of `out.weight` per output bucket.
+    /// Hidden layers between the two are `l2.weight` / `l2.bias`, `l3.weight` / `l3.bias`
+    /// and so on, with the rows for each output bucket stacked one after another.
+    ///
+    /// A king-bucketed network must have a `king_buckets` entry describing its
+    /// `BucketLayout`, and `perspective.weight` must then have `768 * buckets` inputs.
+    /// Likewise, a network with several output heads needs an `output_buckets` entry
+    /// (see `OutputBuckets::from_json`).
+    pub fn from_json(path: impl AsRef<std::path::Path>) -> Self {
+        let file = fs::read_to_string(path).unwrap();
+        let json: Value = serde_json::from_str(&file).unwrap();
+
+        let buckets =
+            json.get("king_buckets").map_or(BucketLayout::UNBUCKETED, BucketLayout::from_json);
+        let output_buckets =
+            json.get("output_buckets").map_or(OutputBuckets::SINGLE, OutputBuckets::from_json);
+
+        // the trainer stores the perspective weights as one row of inputs per neuron.
+        let (neurons, transposed) = matrix(&json["perspective.weight"], "perspective.weight");
+        assert_eq!(neurons, LAYER_1_SIZE, "perspective.weight must have {LAYER_1_SIZE} rows");
+        let n_inputs = transposed.len() / LAYER_1_SIZE;
+        assert_eq!(
+            n_inputs,
+            INPUT * buckets.count(),
+            "perspective.weight has {n_inputs} inputs, but the bucket layout has {} buckets",
+            buckets.count()
+        );
+        let mut feature_weights = vec![0.0; transposed.len()];
+        for (neuron, row) in transposed.chunks(n_inputs).enumerate() {
+            for (feature, &w) in row.iter().enumerate() {
+                feature_weights[feature * LAYER_1_SIZE + neuron] = w;
+            }
+        }
+        let feature_bias = floats(&json["perspective.bias"], "perspective.bias");
+        assert_eq!(feature_bias.len(), LAYER_1_SIZE, "perspective.bias has the wrong length");
+
+        let mut hidden = Vec::new();
+        let mut inputs = LAYER_1_SIZE * 2;
+        for layer in 2.. {
+            let Some(weights) = json.get(format!("l{layer}.weight")) else { break };
+            let name = format!("l{layer}.weight");
+            let (rows, weights) = matrix(weights, &name);
+            assert_eq!(weights.len(), rows * inputs, "{name} should have {inputs} inputs");
+            assert_eq!(
+                rows % output_buckets.count(),
+                0,
+                "{name} has rows missing for some buckets"
+            );
+            let outputs = rows / output_buckets.count();
+            assert!(outputs <= MAX_LAYER_WIDTH, "{name} has more than {MAX_LAYER_WIDTH} neurons");
+            let bias = floats(&json[format!("l{layer}.bias")], &format!("l{layer}.bias"));
+            assert_eq!(bias.len(), rows, "l{layer}.bias has the wrong length");
+            hidden.push(FloatLayer { inputs, outputs, weights, bias });
+            inputs = outputs;
+        }
+
+        let (n_outputs, output_weights) = matrix(&json["out.weight"], "out.weight");
+        assert_eq!(
+            n_outputs,
+            output_buckets.count(),
+            "out.weight has {n_outputs} outputs, but there are {} output buckets",
+            output_buckets.count()
+        );
+        assert_eq!(
+            output_weights.len(),
+            n_outputs * inputs,
+            "out.weight should have {inputs} inputs"
+        );
+        let output_bias = floats(&json["out.bias"], "out.bias");
+        assert_eq!(output_bias.len(), n_outputs, "out.bias has the wrong length");
+
+        Self {
+            buckets,
+            output_buckets,
+            feature_weights,
+            feature_bias,
+            hidden,
+            output_weights,
+            output_bias,
+        }
+    }
+
+    /// Serialise the network in the format read by `from_json`.
+    pub fn to_json(&self) -> Value {
+        let list = |values: &[f32]| -> Value { values.iter().map(|&v| f64::from(v)).collect() };
+        let rows =
+            |values: &[f32], width: usize| -> Value { values.chunks(width).map(list).collect() };
+        let n_inputs = self.feature_weights.len() / LAYER_1_SIZE;
+        let mut transposed = vec![0.0; self.feature_weights.len()];
+        for (feature, row) in self.feature_weights.chunks(LAYER_1_SIZE).enumerate() {
+            for (neuron, &w) in row.iter().enumerate() {
+                transposed[neuron * n_inputs + feature] = w;
+            }
+        }
+
+        let mut out = Map::new();
+        out.insert(
+            "king_buckets".into(),
+            json!({ "map": self.buckets.map.to_vec(), "mirrored": self.buckets.mirrored }),
+        );
+        out.insert("output_buckets".into(), json!({ "map": self.output_buckets.map.to_vec() }));
+        out.insert("perspective.weight".into(), rows(&transposed, n_inputs));
+        out.insert("perspective.bias".into(), list(&self.feature_bias));
+        for (i, layer) in self.hidden.iter().enumerate() {
+            out.insert(format!("l{}.weight", i + 2), rows(&layer.weights, layer.inputs));
+            out.insert(format!("l{}.bias", i + 2), list(&layer.bias));
+        }
+        out.insert("out.weight".into(), rows(&self.output_weights, self.output_inputs()));
+        out.insert("out.bias".into(), list(&self.output_bias));
+        Value::Object(out)
+    }
+
+    /// Quantise the network for inference.
+    pub fn quantise(&self) -> Box<NNUEParams> {
+        self.quantise_with_stats().0
+    }
+
+    /// Quantise the network for inference, rounding every parameter to the nearest
+    /// representable value, and report on any that had to be clamped into range.
+    pub fn quantise_with_stats(&self) -> (Box<NNUEParams>, QuantisationStats) {
+        let mut out = NNUEParams::zeroed(self.buckets, self.output_buckets, &self.hidden_sizes());
+
+        let mut feature_weights = LayerStats::new("perspective.weight");
+        feature_weights.quantise(
+            &self.feature_weights,
+            QA,
+            out.feature_weights.iter_mut().flat_map(|row| row.iter_mut()),
+        );
+        let mut feature_bias = LayerStats::new("perspective.bias");
+        feature_bias.quantise(&self.feature_bias, QA, out.feature_bias.iter_mut());
+        let mut layers = vec![feature_weights, feature_bias];
+
+        let output_width = self.output_inputs();
+        let mut output_weights = LayerStats::new("out.weight");
+        let mut output_bias = LayerStats::new("out.bias");
+        if self.hidden.is_empty() {
+            output_weights.quantise(
+                &self.output_weights,
+                QB,
+                out.output_weights.iter_mut().flat_map(|head| head.iter_mut()),
+            );
+            output_bias.quantise(&self.output_bias, QAB, &mut out.output_bias);
+        }
+
+        let mut hidden = self
+            .hidden
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                [
+                    LayerStats::new(format!("l{}.weight", i + 2)),
+                    LayerStats::new(format!("l{}.bias", i + 2)),
+                ]
+            })
+            .collect::<Vec<_>>();
+        for (bucket, stack) in out.layer_stacks.iter_mut().enumerate() {
+            for ((layer, float), [weights, bias]) in
+                stack.hidden.iter_mut().zip(&self.hidden).zip(&mut hidden)
+            {
+                let n_weights = float.outputs * float.inputs;
+                weights.quantise(
+                    &float.weights[bucket * n_weights..][..n_weights],
+                    QH,
+                    &mut layer.weights,
+                );
+                let float_bias = &float.bias[bucket * float.outputs..][..float.outputs];
+                bias.quantise(float_bias, QA * QH, &mut layer.bias);
+            }
+            let weights = &self.output_weights[bucket * output_width..][..output_width];
+            output_weights.quantise(weights, QB, &mut stack.output_weights);
+            output_bias.quantise(&self.output_bias[bucket..=bucket], QAB, [&mut stack.output_bias]);
+        }
+        layers.extend(hidden.into_iter().flatten());
+        layers.extend([output_weights, output_bias]);
+
+        let mut stats = QuantisationStats {
+            layers,
+            accumulator_overflows: 0,
+            hidden_overflows: 0,
+            output_overflows: 0,
+        };
+        stats.check_overflows(&out);
+        (out, stats)
+    }
+
+    /// Evaluate a position from the side to move's point of view, in centipawns.
+    pub fn evaluate(&self, board: &Board) -> f32 {
+        #![allow(clippy::cast_precision_loss)]
+        let accumulator = |perspective: Colour| -> Vec<f32> {
+            let bucket = self.buckets.bucket(perspective, board.king_sq(perspective));
+            let mut acc = self.feature_bias.clone();
+            for colour in [Colour::WHITE, Colour::BLACK] {
+                for piece_type in PieceType::all() {
+                    let piece_bb = board.pieces.piece_bb(Piece::new(colour, piece_type));
+                    for sq in BitLoop::new(piece_bb) {
+                        let (white_idx, black_idx) = feature_indices(sq, piece_type, colour);
+                        let idx = if perspective == Colour::WHITE { white_idx } else { black_idx };
+                        let row = &self.feature_weights[bucket.feature(idx) * LAYER_1_SIZE..]
+                            [..LAYER_1_SIZE];
+                        acc.iter_mut().zip(row).for_each(|(a, w)| *a += w);
+                    }
+                }
+            }
+            acc
+        };
+
+        let stm = board.turn();
+        let mut activations = accumulator(stm);
+        activations.extend(accumulator(stm.flip()));
+        for a in &mut activations {
+            *a = a.clamp(0.0, 1.0).powi(2);
+        }
+
+        let bucket = self.output_buckets.bucket(board.pieces.occupied().count_ones());
+        for layer in &self.hidden {
+            let weights = &layer.weights[bucket * layer.outputs * layer.inputs..];
+            let bias = &layer.bias[bucket * layer.outputs..][..layer.outputs];
+            activations = weights
+                .chunks(layer.inputs)
+                .zip(bias)
+                .map(|(row, b)| {
+                    let z = row.iter().zip(&activations).map(|(w, a)| w * a).sum::<f32>() + b;
+                    z.clamp(0.0, 1.0)
+                })
+                .collect();
+        }
+
+        let width = self.output_inputs();
+        let weights = &self.output_weights[bucket * width..][..width];
+        let output = weights.iter().zip(&activations).map(|(w, a)| w * a).sum::<f32>()
+            + self.output_bias[bucket];
+        output * SCALE as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        board::{movegen::MoveList, Board},
+        nnue::network::{
+            BucketLayout, NNUEParams, NNUEState, OutputBuckets, INPUT, LAYER_1_SIZE, QA, QB, QH,
+        },
+    };
+
+    /// A random network with the given hidden layers, two mirrored king buckets and four output buckets.
+    fn random_net(rng: &mut StdRng, hidden_sizes: &[usize]) -> FloatNNUEParams {
+        #![allow(clippy::cast_precision_loss)]
+        let mut uniform = |n: usize, range: f32| -> Vec<f32> {
+            (0..n).map(|_| rng.gen_range(-range..range)).collect()
+        };
+        let mut map = [0; 64];
+        map[16..].fill(1);
+        let buckets = BucketLayout { map, mirrored: true };
+        let output_buckets = OutputBuckets::material_count(4);
+
+        let feature_weights = uniform(INPUT * 2 * LAYER_1_SIZE, 0.1);
+        let feature_bias = uniform(LAYER_1_SIZE, 0.2);
+        let mut hidden = Vec::new();
+        let mut inputs = LAYER_1_SIZE * 2;
+        for &outputs in hidden_sizes {
+            let weights = uniform(4 * outputs * inputs, 1.5 / inputs as f32);
+            let bias = uniform(4 * outputs, 0.1);
+            hidden.push(FloatLayer { inputs, outputs, weights, bias });
+            inputs = outputs;
+        }
+        let output_weights = uniform(4 * inputs, 1.0);
+        let output_bias = uniform(4, 0.1);
+
+        FloatNNUEParams {
+            buckets,
+            output_buckets,
+            feature_weights,
+            feature_bias,
+            hidden,
+            output_weights,
+            output_bias,
+        }
+    }
+
+    /// Positions reached by random playouts from the start position.
+    fn random_positions(rng: &mut StdRng, count: usize) -> Vec<Board> {
+        let mut positions = Vec::new();
+        while positions.len() < count {
+            let mut board = Board::default();
+            for _ in 0..rng.gen_range(0..80) {
+                let mut ml = MoveList::new();
+                board.generate_moves(&mut ml);
+                let mut moves = ml.iter().copied().collect::<Vec<_>>();
+                moves.shuffle(rng);
+                if !moves.into_iter().any(|m| board.make_move_base(m)) {
+                    break;
+                }
+            }
+            positions.push(board);
+        }
+        positions
+    }
+
+    /// Move every parameter onto the grid of values that quantise exactly, so that
+    /// the only remaining differences come from the integer arithmetic of inference.
+    fn snap_to_grid(net: &mut FloatNNUEParams) {
+        fn snap(values: &mut [f32], k: i32) {
+            #![allow(clippy::cast_precision_loss)]
+            for v in values {
+                *v = (*v * k as f32).round() / k as f32;
+            }
+        }
+        snap(&mut net.feature_weights, QA);
+        snap(&mut net.feature_bias, QA);
+        for layer in &mut net.hidden {
+            snap(&mut layer.weights, QH);
+            snap(&mut layer.bias, QA * QH);
+        }
+        snap(&mut net.output_weights, QB);
+        snap(&mut net.output_bias, QA * QB);
+    }
+
+    /// The mean and maximum absolute difference between quantised and float evaluations.
+    fn quantisation_error(net: &FloatNNUEParams, positions: &[Board]) -> (f32, f32) {
+        #![allow(clippy::cast_precision_loss)]
+        let quantised: &'static NNUEParams = Box::leak(net.quantise());
+        let errors = positions
+            .iter()
+            .map(|board| {
+                let state = NNUEState::with_params(board, quantised);
+                let eval = state.evaluate(board.turn(), board.pieces.occupied().count_ones());
+                (eval as f32 - net.evaluate(board)).abs()
+            })
+            .collect::<Vec<_>>();
+        let mean = errors.iter().sum::<f32>() / errors.len() as f32;
+        (mean, errors.iter().copied().fold(0.0, f32::max))
+    }
+
+    #[test]
+    fn json_and_binary_round_trip() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let net = random_net(&mut rng, &[16, 32]);
+        let path = std::env::temp_dir().join(format!("viri_float_{}.json", std::process::id()));
+        std::fs::write(&path, net.to_json().to_string()).unwrap();
+        let loaded = FloatNNUEParams::from_json(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, net);
+
+        let quantised = net.quantise();
+        assert_eq!(quantised.hidden_sizes(), [16, 32]);
+        let bytes = quantised.to_bytes();
+        let parts: Vec<&[u8]> = bytes.iter().map(Vec::as_slice).collect();
+        let reloaded = NNUEParams::from_bytes(parts.try_into().unwrap());
+        assert_eq!(reloaded.layer_stacks, quantised.layer_stacks);
+        assert_eq!(reloaded.feature_weights, quantised.feature_weights);
+        assert_eq!(reloaded.num_params(), quantised.num_params());
+    }
+
+    #[test]
+    fn quantised_inference_matches_float() {
+        crate::magic::initialise();
+        let mut rng = StdRng::seed_from_u64(43);
+        let positions = random_positions(&mut rng, 200);
+        for hidden_sizes in [&[][..], &[16], &[16, 32]] {
+            let mut net = random_net(&mut rng, hidden_sizes);
+            // off the grid, the error is dominated by the coarse rounding of the output weights.
+            let (mean, max) = quantisation_error(&net, &positions);
+            assert!(mean < 20.0, "mean rounding error {mean} for {hidden_sizes:?}");
+            assert!(max < 80.0, "max rounding error {max} for {hidden_sizes:?}");
+
+            snap_to_grid(&mut net);
+            let (mean, max) = quantisation_error(&net, &positions);
+            assert!(mean < 1.0, "mean quantisation error {mean} for {hidden_sizes:?}");
+            assert!(max < 3.0, "max quantisation error {max} for {hidden_sizes:?}");
+        }
+    }
+}
@@ -0,0 +1,466 @@
+//! Inspection of networks, and comparison of one network against another.
+//!
+//! Networks can be given as the JSON emitted by the trainer, as a directory of the binary
+//! files written by `--jsontobin`, or as `embedded` for the network built into the engine.
+
+use std::{error::Error, fmt, path::Path, time::Instant};
+
+use crate::{board::Board, piece::Colour};
+
+use super::{
+    convert::{DataFormat, InvalidDataPolicy, Records},
+    network::{self, NNUEParams, NNUEState, CR_MAX, INPUT, LAYER_1_SIZE, NNUE, QA},
+    quantise::{l1_sum_bounds, EvalComparison},
+};
+
+/// The number of largest disagreements that are listed individually.
+const TOP_DISAGREEMENTS: usize = 10;
+/// The bands of the per-phase breakdown, by the highest `Board::phase` in each.
+const PHASES: [(&str, i32); 3] = [("opening", 85), ("middlegame", 170), ("endgame", i32::MAX)];
+
+/// Load a network for inspection.
+pub fn load_net(path: &Path) -> Result<&'static NNUEParams, Box<dyn Error>> {
+    if path.as_os_str() == "embedded" {
+        return Ok(&NNUE);
+    }
+    let net =
+        if path.is_dir() { network::read_net_files(path)? } else { NNUEParams::from_json(path) };
+    Ok(Box::leak(net))
+}
+
+/// Evaluate a position from scratch, from the side to move's point of view.
+fn evaluate(nnue: &mut NNUEState, board: &Board) -> i32 {
+    nnue.refresh_acc(board);
+    nnue.evaluate(board.turn(), board.pieces.occupied().count_ones())
+}
+
+/// Compare the evaluations of two networks over the positions in a data file.
+pub fn net_diff(
+    a_path: &Path,
+    b_path: &Path,
+    positions_path: &Path,
+    format: &dyn DataFormat,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    let (a, b) = (load_net(a_path)?, load_net(b_path)?);
+
+    let start_time = Instant::now();
+    let mut records = Records::open(format, positions_path, policy)?;
+    let mut board = Board::new();
+    let mut a_nnue = NNUEState::with_params(&board, a);
+    let mut b_nnue = NNUEState::with_params(&board, b);
+    let mut overall = EvalComparison::new("A", "B");
+    let mut phases = PHASES.map(|_| EvalComparison::new("A", "B"));
+    let mut evals = Vec::new();
+    let mut disagreements = Disagreements::default();
+    for record in records.by_ref() {
+        let record = record?;
+        board.set_from_fen(&record.fen)?;
+        let a_eval = evaluate(&mut a_nnue, &board);
+        let b_eval = evaluate(&mut b_nnue, &board);
+        let phase = board.phase();
+        let band = PHASES.iter().position(|&(_, max)| phase <= max).unwrap();
+        overall.push(f64::from(a_eval), f64::from(b_eval), &record.fen);
+        phases[band].push(f64::from(a_eval), f64::from(b_eval), &record.fen);
+        disagreements.push(a_eval, b_eval, &record.fen);
+        evals.push((a_eval, b_eval));
+    }
+    records.report_skipped();
+
+    let elapsed = start_time.elapsed();
+    println!(
+        "Compared {} positions in {}.{:03}s",
+        overall.n,
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+    );
+    println!(" |> A: {}", a_path.display());
+    println!(" |> B: {}", b_path.display());
+    print!("{overall}");
+    println!(" |> rank correlation: {:.6}", rank_correlation(&evals));
+    println!("Largest disagreements:");
+    print!("{disagreements}");
+    println!("By phase:");
+    let mut low = i32::MIN;
+    for ((name, max), comparison) in PHASES.iter().zip(&phases) {
+        let range = match (low, *max) {
+            (i32::MIN, max) => format!("<= {max}"),
+            (low, i32::MAX) => format!(">= {low}"),
+            (low, max) => format!("{low} to {max}"),
+        };
+        println!(
+            " |> {name:<10} (phase {range:>9}): {:>8} positions, mean absolute difference {:.2}cp, mean difference {:+.2}cp, correlation {:.6}",
+            comparison.n,
+            comparison.mean_absolute_difference(),
+            comparison.mean_difference(),
+            comparison.correlation(),
+        );
+        low = max.saturating_add(1);
+    }
+    Ok(())
+}
+
+/// The positions that two networks disagree about the most, most first.
+/// Positions that appear more than once in the data are only listed once.
+#[derive(Default)]
+struct Disagreements {
+    positions: Vec<(i32, i32, String)>,
+}
+
+impl Disagreements {
+    fn push(&mut self, a: i32, b: i32, fen: &str) {
+        let difference = |&(a, b, _): &(i32, i32, String)| (b - a).abs();
+        if self.positions.len() == TOP_DISAGREEMENTS
+            && self.positions.last().is_some_and(|last| difference(last) >= (b - a).abs())
+            || self.positions.iter().any(|p| p.2 == fen)
+        {
+            return;
+        }
+        self.positions.push((a, b, fen.to_string()));
+        self.positions.sort_by_key(|p| std::cmp::Reverse(difference(p)));
+        self.positions.truncate(TOP_DISAGREEMENTS);
+    }
+}
+
+impl fmt::Display for Disagreements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (a, b, fen) in &self.positions {
+            writeln!(f, " |> A {a:>+6} B {b:>+6} ({:>5}cp): {fen}", (b - a).abs())?;
+        }
+        Ok(())
+    }
+}
+
+/// The ranks of some values, with tied values sharing the mean of their ranks.
+fn ranks(values: impl Iterator<Item = i32>) -> Vec<f64> {
+    #![allow(clippy::cast_precision_loss)]
+    let mut order = values.zip(0..).collect::<Vec<(i32, usize)>>();
+    order.sort_unstable();
+    let mut ranks = vec![0.0; order.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let end = start + order[start..].iter().take_while(|(v, _)| *v == order[start].0).count();
+        let rank = (start + end - 1) as f64 / 2.0;
+        for &(_, i) in &order[start..end] {
+            ranks[i] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// The Spearman rank correlation coefficient between two sets of evaluations.
+fn rank_correlation(evals: &[(i32, i32)]) -> f64 {
+    #![allow(clippy::cast_precision_loss)]
+    let a = ranks(evals.iter().map(|e| e.0));
+    let b = ranks(evals.iter().map(|e| e.1));
+    // both sets of ranks have the same mean.
+    let mean = (evals.len() as f64 - 1.0) / 2.0;
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (a, b) in a.iter().zip(&b) {
+        covariance += (a - mean) * (b - mean);
+        variance_a += (a - mean) * (a - mean);
+        variance_b += (b - mean) * (b - mean);
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+/// Summary statistics of a set of quantised parameters.
+struct WeightStats {
+    name: String,
+    count: usize,
+    min: i32,
+    max: i32,
+    sum: f64,
+    square_sum: f64,
+    zeros: usize,
+}
+
+impl WeightStats {
+    fn new(name: impl Into<String>, values: impl IntoIterator<Item = i32>) -> Self {
+        let mut stats = Self {
+            name: name.into(),
+            count: 0,
+            min: i32::MAX,
+            max: i32::MIN,
+            sum: 0.0,
+            square_sum: 0.0,
+            zeros: 0,
+        };
+        for v in values {
+            stats.count += 1;
+            stats.min = stats.min.min(v);
+            stats.max = stats.max.max(v);
+            stats.sum += f64::from(v);
+            stats.square_sum += f64::from(v) * f64::from(v);
+            stats.zeros += usize::from(v == 0);
+        }
+        stats
+    }
+}
+
+impl fmt::Display for WeightStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #![allow(clippy::cast_precision_loss)]
+        let n = self.count.max(1) as f64;
+        let mean = self.sum / n;
+        let stddev = mean.mul_add(-mean, self.square_sum / n).max(0.0).sqrt();
+        write!(
+            f,
+            " |> {:<18} {:>9} params, min {:>7}, max {:>7}, mean {:>8.2}, stddev {:>8.2}, {:.2}% zero",
+            self.name,
+            self.count,
+            self.min,
+            self.max,
+            mean,
+            stddev,
+            self.zeros as f64 / n * 100.0,
+        )
+    }
+}
+
+/// The weight statistics of every layer of a network.
+fn weight_stats(net: &NNUEParams) -> Vec<WeightStats> {
+    let mut stats = vec![
+        WeightStats::new(
+            "perspective.weight",
+            net.feature_weights.iter().flat_map(|row| row.iter().copied().map(i32::from)),
+        ),
+        WeightStats::new("perspective.bias", net.feature_bias.iter().copied().map(i32::from)),
+    ];
+    if net.layer_stacks.is_empty() {
+        stats.push(WeightStats::new(
+            "out.weight",
+            net.output_weights.iter().flat_map(|head| head.iter().copied().map(i32::from)),
+        ));
+        stats.push(WeightStats::new("out.bias", net.output_bias.iter().copied().map(i32::from)));
+    } else {
+        for i in 0..net.hidden_sizes().len() {
+            let layers = || net.layer_stacks.iter().map(move |stack| &stack.hidden[i]);
+            stats.push(WeightStats::new(
+                format!("l{}.weight", i + 2),
+                layers().flat_map(|l| l.weights.iter().copied().map(i32::from)),
+            ));
+            stats.push(WeightStats::new(
+                format!("l{}.bias", i + 2),
+                layers().flat_map(|l| l.bias.iter().copied()),
+            ));
+        }
+        stats.push(WeightStats::new(
+            "out.weight",
+            net.layer_stacks.iter().flat_map(|s| s.output_weights.iter().copied().map(i32::from)),
+        ));
+        stats.push(WeightStats::new("out.bias", net.layer_stacks.iter().map(|s| s.output_bias)));
+    }
+    stats
+}
+
+/// The L1 neurons that can't be positive in any king bucket, even with a full board
+/// of the pieces that excite them most, and so never pass anything on.
+fn inactive_neurons(net: &NNUEParams) -> usize {
+    let bounds = l1_sum_bounds(net);
+    (0..LAYER_1_SIZE).filter(|&neuron| bounds.iter().all(|bucket| bucket[neuron].1 <= 0)).count()
+}
+
+/// The L1 neurons whose activations are ignored by the next layer, from either perspective.
+fn unused_neurons(net: &NNUEParams) -> usize {
+    (0..LAYER_1_SIZE)
+        .filter(|&neuron| {
+            let inputs = [neuron, LAYER_1_SIZE + neuron];
+            net.output_weights.iter().all(|head| inputs.iter().all(|&i| head[i] == 0))
+                && net.layer_stacks.iter().all(|stack| {
+                    let layer = &stack.hidden[0];
+                    layer
+                        .weights
+                        .chunks_exact(layer.inputs)
+                        .all(|row| inputs.iter().all(|&i| row[i] == 0))
+                })
+        })
+        .count()
+}
+
+/// How often each neuron of a layer is switched off, or saturated.
+struct ActivationStats {
+    samples: u64,
+    off: Vec<u64>,
+    saturated: Vec<u64>,
+}
+
+impl ActivationStats {
+    fn new(neurons: usize) -> Self {
+        Self { samples: 0, off: vec![0; neurons], saturated: vec![0; neurons] }
+    }
+
+    fn push(&mut self, activations: &[i16], max: i16) {
+        self.samples += 1;
+        for ((&a, off), saturated) in activations.iter().zip(&mut self.off).zip(&mut self.saturated)
+        {
+            *off += u64::from(a <= 0);
+            *saturated += u64::from(a >= max);
+        }
+    }
+
+    /// Merge the statistics of the same layer in another output bucket.
+    fn merge(&mut self, other: &Self) {
+        self.samples += other.samples;
+        self.off.extend(&other.off);
+        self.saturated.extend(&other.saturated);
+    }
+
+    /// Report the statistics of a layer, given the samples seen by each neuron.
+    fn write(&self, f: &mut fmt::Formatter<'_>, name: &str, samples: &[u64]) -> fmt::Result {
+        #![allow(clippy::cast_precision_loss)]
+        let seen = samples.iter().sum::<u64>().max(1) as f64;
+        let neurons = self.off.iter().zip(&self.saturated).zip(samples);
+        let never_active = neurons.clone().filter(|((&off, _), &n)| n > 0 && off == n).count();
+        let always_saturated = neurons.filter(|((_, &sat), &n)| n > 0 && sat == n).count();
+        writeln!(
+            f,
+            " |> {name:<4} {:>6.2}% off, {:>6.2}% saturated; {never_active} of {} neurons never active, {always_saturated} always saturated",
+            self.off.iter().sum::<u64>() as f64 / seen * 100.0,
+            self.saturated.iter().sum::<u64>() as f64 / seen * 100.0,
+            self.off.len(),
+        )
+    }
+}
+
+/// Activation statistics of a network over a set of positions.
+struct Saturation {
+    positions: u64,
+    l1: ActivationStats,
+    /// The statistics of each hidden layer, for each output bucket.
+    hidden: Vec<Vec<ActivationStats>>,
+}
+
+impl Saturation {
+    fn new(net: &NNUEParams) -> Self {
+        let hidden = net
+            .layer_stacks
+            .iter()
+            .map(|stack| stack.hidden.iter().map(|l| ActivationStats::new(l.outputs())).collect())
+            .collect();
+        Self { positions: 0, l1: ActivationStats::new(LAYER_1_SIZE), hidden }
+    }
+
+    fn push(&mut self, net: &NNUEParams, nnue: &NNUEState, board: &Board) {
+        self.positions += 1;
+        let acc = &nnue.accumulators[nnue.current_acc];
+        self.l1.push(&*acc.white, CR_MAX);
+        self.l1.push(&*acc.black, CR_MAX);
+
+        let (us, them) = if board.turn() == Colour::WHITE {
+            (&acc.white, &acc.black)
+        } else {
+            (&acc.black, &acc.white)
+        };
+        let bucket = net.output_buckets.bucket(board.pieces.occupied().count_ones());
+        if let Some(stack) = net.layer_stacks.get(bucket) {
+            let max = i16::try_from(QA).unwrap();
+            for (stats, activations) in
+                self.hidden[bucket].iter_mut().zip(stack.hidden_activations(us, them))
+            {
+                stats.push(&activations, max);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Saturation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Activations over {} positions:", self.positions)?;
+        self.l1.write(f, "l1", &vec![self.l1.samples; LAYER_1_SIZE])?;
+        let layers = self.hidden.first().map_or(0, Vec::len);
+        for i in 0..layers {
+            // neurons in different output buckets are distinct, and see different positions.
+            let mut merged = ActivationStats::new(0);
+            let mut samples = Vec::new();
+            for stack in &self.hidden {
+                merged.merge(&stack[i]);
+                samples.extend(std::iter::repeat_n(stack[i].samples, stack[i].off.len()));
+            }
+            merged.write(f, &format!("l{}", i + 2), &samples)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print the architecture and parameter statistics of a network, and optionally,
+/// how its neurons behave over the positions in a data file.
+pub fn net_info(
+    net_path: &Path,
+    positions: Option<(&Path, &dyn DataFormat)>,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    let net = load_net(net_path)?;
+
+    println!("Network: {}", net_path.display());
+    println!(
+        " |> king buckets: {}{}",
+        net.buckets.count(),
+        if net.buckets.mirrored { " (mirrored)" } else { "" }
+    );
+    println!(" |> output buckets: {}", net.output_buckets.count());
+    let layers = std::iter::once(format!("{}", INPUT * net.buckets.count()))
+        .chain(std::iter::once(format!("{LAYER_1_SIZE}x2")))
+        .chain(net.hidden_sizes().iter().map(usize::to_string))
+        .chain(std::iter::once("1".to_string()))
+        .collect::<Vec<_>>();
+    println!(" |> layers: {}", layers.join(" -> "));
+    println!(" |> parameters: {}", net.num_params());
+
+    println!("Weights:");
+    for stats in weight_stats(net) {
+        println!("{stats}");
+    }
+
+    println!("Neurons:");
+    println!(" |> l1 neurons that can never activate: {} of {LAYER_1_SIZE}", inactive_neurons(net));
+    println!(" |> l1 neurons unused by the next layer: {} of {LAYER_1_SIZE}", unused_neurons(net));
+
+    let Some((positions_path, format)) = positions else {
+        return Ok(());
+    };
+    let mut records = Records::open(format, positions_path, policy)?;
+    let mut board = Board::new();
+    let mut nnue = NNUEState::with_params(&board, net);
+    let mut saturation = Saturation::new(net);
+    for record in records.by_ref() {
+        let record = record?;
+        board.set_from_fen(&record.fen)?;
+        nnue.refresh_acc(&board);
+        saturation.push(net, &nnue, &board);
+    }
+    records.report_skipped();
+    print!("{saturation}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nnue::network::{BucketLayout, NNUEParams, OutputBuckets, LAYER_1_SIZE};
+
+    #[test]
+    fn ranks_share_ties() {
+        assert_eq!(ranks([30, 10, 20, 10].into_iter()), [3.0, 0.5, 2.0, 0.5]);
+        assert!((rank_correlation(&[(1, 10), (2, 20), (3, 1000)]) - 1.0).abs() < 1e-12);
+        assert!((rank_correlation(&[(1, 3), (2, 2), (3, 1)]) + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn finds_dead_neurons() {
+        let mut net = NNUEParams::zeroed(BucketLayout::UNBUCKETED, OutputBuckets::SINGLE, &[]);
+        assert_eq!(inactive_neurons(&net), LAYER_1_SIZE);
+        assert_eq!(unused_neurons(&net), LAYER_1_SIZE);
+
+        // neuron 0 can only activate with many of the pieces that excite it.
+        net.feature_bias[0] = -100;
+        for row in &mut net.feature_weights[..4] {
+            row[0] = 30;
+        }
+        net.feature_bias[1] = 5;
+        net.output_weights[0][LAYER_1_SIZE + 1] = 1;
+        assert_eq!(inactive_neurons(&net), LAYER_1_SIZE - 2);
+        assert_eq!(unused_neurons(&net), LAYER_1_SIZE - 1);
+    }
+}
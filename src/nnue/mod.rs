@@ -1,3 +1,8 @@
 mod accumulator;
 pub mod convert;
+pub mod float;
+pub mod inspect;
 pub mod network;
+pub mod quantise;
+mod simd;
+pub mod train;
@@ -1,7 +1,7 @@
 use std::{
-    array::from_mut,
     fs, mem,
     ops::{Deref, DerefMut},
+    sync::LazyLock,
 };
 
 use serde_json::Value;
@@ -9,32 +9,50 @@ use serde_json::Value;
 use crate::{
     board::{movegen::BitLoop, Board},
     definitions::{Square, MAX_DEPTH},
-    image::{self, Image},
+    image::{self, BoardGrid},
     piece::{Colour, Piece, PieceType},
 };
 
-use super::accumulator::Accumulator;
+use super::{
+    accumulator::{Accumulator, Feature, RefreshEntry, UpdateBuffer},
+    float::FloatNNUEParams,
+    simd::{self, add_to_all, screlu_flatten, sub_from_all, subtract_and_add_to_all},
+};
 
-/// The size of the input layer of the network.
-const INPUT: usize = 768;
+/// The size of the input layer of the network, for a single king bucket.
+pub const INPUT: usize = 768;
 /// The minimum value for the clipped relu activation.
 const CR_MIN: i16 = 0;
 /// The maximum value for the clipped relu activation.
-const CR_MAX: i16 = 255;
+pub const CR_MAX: i16 = 255;
 /// The amount to scale the output of the network by.
 /// This is to allow for the sigmoid activation to differentiate positions with
 /// a small difference in evaluation.
-const SCALE: i32 = 400;
+pub const SCALE: i32 = 400;
 /// The size of one-half of the hidden layer of the network.
 pub const LAYER_1_SIZE: usize = 512;
 
-const QA: i32 = 255;
-const QB: i32 = 64;
-const QAB: i32 = QA * QB;
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+pub const QAB: i32 = QA * QB;
+/// The quantisation of the weights of hidden layers after L1.
+pub const QH: i32 = 64;
+/// The largest number of inputs or neurons that a layer after L1 can have.
+pub const MAX_LAYER_WIDTH: usize = LAYER_1_SIZE * 2;
 
 /// The size of the stack used to store the activations of the hidden layer.
 const ACC_STACK_SIZE: usize = MAX_DEPTH.ply_to_horizon() + 1;
 
+/// The files that make up a network in binary form, in the order that `to_bytes` emits them.
+const NET_FILES: [&str; 6] = [
+    "buckets",
+    "feature_weights",
+    "feature_bias",
+    "output_weights",
+    "output_bias",
+    "hidden_layers",
+];
+
 pub trait Activation {
     const ACTIVATE: bool;
     type Reverse: Activation;
@@ -66,160 +84,516 @@ impl<T, const SIZE: usize> DerefMut for Align<[T; SIZE]> {
     }
 }
 
-// read in bytes from files and transmute them into u16s.
-// SAFETY: alignment to u16 is guaranteed because transmute() is a copy operation.
-pub static NNUE: NNUEParams = NNUEParams {
-    feature_weights: unsafe { mem::transmute(*include_bytes!("../../nnue/feature_weights.bin")) },
-    feature_bias: unsafe { mem::transmute(*include_bytes!("../../nnue/feature_bias.bin")) },
-    output_weights: unsafe { mem::transmute(*include_bytes!("../../nnue/output_weights.bin")) },
-    output_bias: unsafe { mem::transmute(*include_bytes!("../../nnue/output_bias.bin")) },
-};
+// the embedded network, parsed from its binary files on first use.
+pub static NNUE: LazyLock<Box<NNUEParams>> = LazyLock::new(|| {
+    NNUEParams::from_bytes([
+        include_bytes!("../../nnue/buckets.bin"),
+        include_bytes!("../../nnue/feature_weights.bin"),
+        include_bytes!("../../nnue/feature_bias.bin"),
+        include_bytes!("../../nnue/output_weights.bin"),
+        include_bytes!("../../nnue/output_bias.bin"),
+        include_bytes!("../../nnue/hidden_layers.bin"),
+    ])
+});
+
+/// The layout of the king buckets of a network.
+///
+/// Each perspective takes its input weights from one of several buckets, chosen by
+/// the square of that side's king (as seen from that side, so a1 is always the near
+/// corner). If `mirrored` is set, positions where the king is on the e-h files are
+/// flipped horizontally before the features are computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketLayout {
+    /// The bucket for each king square.
+    pub map: [u8; 64],
+    /// Whether to mirror the board so that the king is always on the a-d files.
+    pub mirrored: bool,
+}
+
+impl BucketLayout {
+    /// A single bucket with no mirroring, i.e. plain piece-square inputs.
+    pub const UNBUCKETED: Self = Self { map: [0; 64], mirrored: false };
+
+    /// The number of buckets in the layout.
+    pub fn count(&self) -> usize {
+        usize::from(*self.map.iter().max().unwrap()) + 1
+    }
+
+    /// The bucket used by `perspective` when its king is on `king_sq`.
+    pub fn bucket(&self, perspective: Colour, king_sq: Square) -> KingBucket {
+        let king_sq = if perspective == Colour::WHITE { king_sq } else { king_sq.flip_rank() };
+        KingBucket {
+            offset: usize::from(self.map[king_sq.index()]) * INPUT,
+            mirror: if self.mirrored && king_sq.file() >= 4 { 0b111 } else { 0 },
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert_eq!(bytes.len(), 65, "bucket layout must be 64 squares and a mirroring flag");
+        assert!(bytes[64] <= 1, "invalid mirroring flag {}", bytes[64]);
+        let mut map = [0; 64];
+        map.copy_from_slice(&bytes[..64]);
+        Self { map, mirrored: bytes[64] == 1 }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut out = self.map.to_vec();
+        out.push(self.mirrored.into());
+        out
+    }
+
+    /// Read a layout of the form `{ "map": [64 bucket indices], "mirrored": bool }`.
+    pub fn from_json(value: &Value) -> Self {
+        #![allow(clippy::cast_possible_truncation)]
+        let squares = value["map"].as_array().expect("king_buckets.map must be an array");
+        assert_eq!(squares.len(), 64, "king_buckets.map must have an entry for every square");
+        let mut map = [0; 64];
+        for (bucket, square) in map.iter_mut().zip(squares) {
+            let idx = square.as_u64().expect("king bucket indices must be integers");
+            assert!(idx < 256, "king bucket index {idx} out of range");
+            *bucket = idx as u8;
+        }
+        Self { map, mirrored: value["mirrored"].as_bool().unwrap_or(false) }
+    }
+}
+
+/// The set of input weights that a perspective is using, as selected by its king.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KingBucket {
+    /// The index of the first feature in the bucket.
+    offset: usize,
+    /// XOR mask applied to a feature index to flip its file, or zero when not mirroring.
+    mirror: usize,
+}
+
+impl KingBucket {
+    /// Map a plain piece-square feature index into this bucket.
+    pub const fn feature(self, idx: usize) -> usize {
+        self.offset + (idx ^ self.mirror)
+    }
+
+    /// A dense index for this bucket, distinguishing its mirrored and unmirrored halves.
+    pub const fn index(self) -> usize {
+        self.offset / INPUT * 2 + (self.mirror & 1)
+    }
+}
+
+/// The mapping from the number of pieces on the board to the output bucket used to evaluate it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBuckets {
+    /// The bucket for each piece count, from 0 to 32.
+    pub map: [u8; 33],
+}
+
+impl OutputBuckets {
+    /// A single output head, used for every position.
+    pub const SINGLE: Self = Self { map: [0; 33] };
+
+    /// `count` buckets of equal width over the piece count, as used by the trainer:
+    /// `(pieces - 2) / ceil(32 / count)`.
+    pub fn material_count(count: usize) -> Self {
+        #![allow(clippy::cast_possible_truncation)]
+        assert!((1..=32).contains(&count), "invalid number of output buckets {count}");
+        let divisor = 32usize.div_ceil(count);
+        let mut map = [0; 33];
+        for (pieces, bucket) in map.iter_mut().enumerate() {
+            *bucket = (pieces.saturating_sub(2) / divisor) as u8;
+        }
+        Self { map }
+    }
+
+    /// The number of output buckets.
+    pub fn count(&self) -> usize {
+        usize::from(*self.map.iter().max().unwrap()) + 1
+    }
+
+    /// The output bucket for a position with `piece_count` pieces on the board.
+    pub const fn bucket(&self, piece_count: u32) -> usize {
+        self.map[piece_count as usize] as usize
+    }
+
+    /// Read the mapping, which is absent from the files of nets with a single output.
+    fn from_bytes(bytes: &[u8]) -> Self {
+        if bytes.is_empty() {
+            return Self::SINGLE;
+        }
+        assert_eq!(bytes.len(), 33, "output bucket layout must cover every piece count");
+        let mut map = [0; 33];
+        map.copy_from_slice(bytes);
+        Self { map }
+    }
+
+    /// Read either a number of buckets, for the trainer's material-count formula,
+    /// or an explicit table of the form `{ "map": [33 bucket indices] }`.
+    pub fn from_json(value: &Value) -> Self {
+        #![allow(clippy::cast_possible_truncation)]
+        if let Some(count) = value.as_u64() {
+            return Self::material_count(count as usize);
+        }
+        let counts = value["map"].as_array().expect("output_buckets.map must be an array");
+        assert_eq!(counts.len(), 33, "output_buckets.map must have an entry for 0-32 pieces");
+        let mut map = [0; 33];
+        for (bucket, count) in map.iter_mut().zip(counts) {
+            let idx = count.as_u64().expect("output bucket indices must be integers");
+            assert!(idx < 256, "output bucket index {idx} out of range");
+            *bucket = idx as u8;
+        }
+        Self { map }
+    }
+}
+
+/// A fully-connected layer after L1, with int8 weights and a clipped relu activation.
+///
+/// Inputs and outputs are activations in `0..=QA`, and the weights are quantised by `QH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DenseLayer {
+    pub inputs: usize,
+    /// Weights, one row of `inputs` per output neuron.
+    pub weights: Vec<i8>,
+    /// Biases, one per output neuron, quantised by `QA * QH`.
+    pub bias: Vec<i32>,
+}
+
+impl DenseLayer {
+    fn zeroed(inputs: usize, outputs: usize) -> Self {
+        Self { inputs, weights: vec![0; inputs * outputs], bias: vec![0; outputs] }
+    }
+
+    pub const fn outputs(&self) -> usize {
+        self.bias.len()
+    }
+
+    /// Apply the layer to `input`, writing the activations of its neurons to `output`.
+    fn forward(&self, input: &[i16], output: &mut [i16]) {
+        #![allow(clippy::cast_possible_truncation)]
+        for ((out, row), &bias) in
+            output.iter_mut().zip(self.weights.chunks_exact(self.inputs)).zip(&self.bias)
+        {
+            let sum =
+                row.iter().zip(input).fold(bias, |sum, (&w, &x)| sum + i32::from(w) * i32::from(x));
+            // round to nearest rather than truncate, so as not to bias the activations.
+            *out = ((sum + QH / 2) / QH).clamp(0, QA) as i16;
+        }
+    }
+}
+
+/// The layers after L1 of a deeper network, for a single output bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerStack {
+    pub hidden: Vec<DenseLayer>,
+    /// Output weights, one per neuron of the last hidden layer, quantised by `QB`.
+    pub output_weights: Vec<i16>,
+    /// Output bias, quantised by `QA * QB`.
+    pub output_bias: i32,
+}
+
+impl LayerStack {
+    fn zeroed(hidden_sizes: &[usize]) -> Self {
+        let mut inputs = LAYER_1_SIZE * 2;
+        let mut hidden = Vec::with_capacity(hidden_sizes.len());
+        for &outputs in hidden_sizes {
+            assert!(
+                (1..=MAX_LAYER_WIDTH).contains(&outputs),
+                "hidden layers must have between 1 and {MAX_LAYER_WIDTH} neurons"
+            );
+            hidden.push(DenseLayer::zeroed(inputs, outputs));
+            inputs = outputs;
+        }
+        Self { hidden, output_weights: vec![0; inputs], output_bias: 0 }
+    }
+
+    /// Activate the partial activations of L1, scaling them back down to `0..=QA`.
+    fn activate_l1(us: &[i16; LAYER_1_SIZE], them: &[i16; LAYER_1_SIZE], input: &mut [i16]) {
+        #![allow(clippy::cast_possible_truncation)]
+        for (a, &x) in input.iter_mut().zip(us.iter().chain(them)) {
+            *a = ((screlu(x) + QA / 2) / QA) as i16;
+        }
+    }
+
+    /// Run the layers on the partial activations, giving the output scaled by `QA * QB`.
+    pub fn evaluate(&self, us: &[i16; LAYER_1_SIZE], them: &[i16; LAYER_1_SIZE]) -> i32 {
+        let mut input = [0; MAX_LAYER_WIDTH];
+        let mut output = [0; MAX_LAYER_WIDTH];
+        Self::activate_l1(us, them, &mut input);
+        let mut width = LAYER_1_SIZE * 2;
+        for layer in &self.hidden {
+            layer.forward(&input[..width], &mut output[..layer.outputs()]);
+            width = layer.outputs();
+            mem::swap(&mut input, &mut output);
+        }
+        self.output_weights
+            .iter()
+            .zip(&input[..width])
+            .fold(self.output_bias, |sum, (&w, &x)| sum + i32::from(w) * i32::from(x))
+    }
 
+    /// The activations of each hidden layer, for inspecting the network.
+    pub fn hidden_activations(
+        &self,
+        us: &[i16; LAYER_1_SIZE],
+        them: &[i16; LAYER_1_SIZE],
+    ) -> Vec<Vec<i16>> {
+        let mut input = vec![0; LAYER_1_SIZE * 2];
+        Self::activate_l1(us, them, &mut input);
+        let mut activations = Vec::with_capacity(self.hidden.len());
+        for layer in &self.hidden {
+            let mut output = vec![0; layer.outputs()];
+            layer.forward(&input, &mut output);
+            activations.push(output.clone());
+            input = output;
+        }
+        activations
+    }
+}
+
+#[derive(Debug)]
 pub struct NNUEParams {
-    pub feature_weights: Align<[i16; INPUT * LAYER_1_SIZE]>,
+    pub buckets: BucketLayout,
+    pub output_buckets: OutputBuckets,
+    /// One row of hidden-layer weights for each input feature, bucket by bucket.
+    pub feature_weights: Vec<Align<[i16; LAYER_1_SIZE]>>,
     pub feature_bias: Align<[i16; LAYER_1_SIZE]>,
-    pub output_weights: Align<[i16; LAYER_1_SIZE * 2]>,
-    pub output_bias: i16,
+    /// The weights of each output head, for nets without hidden layers after L1.
+    pub output_weights: Vec<Align<[i16; LAYER_1_SIZE * 2]>>,
+    /// The bias of each output head, for nets without hidden layers after L1.
+    pub output_bias: Vec<i16>,
+    /// The layers after L1 for each output bucket, for nets that have them.
+    pub layer_stacks: Vec<LayerStack>,
 }
 
 impl NNUEParams {
-    pub const fn num_params() -> usize {
-        INPUT * LAYER_1_SIZE + LAYER_1_SIZE + LAYER_1_SIZE * 2 + 1 // don't duplicate the feature weights
+    pub fn zeroed(
+        buckets: BucketLayout,
+        output_buckets: OutputBuckets,
+        hidden_sizes: &[usize],
+    ) -> Box<Self> {
+        let (n_heads, n_stacks) = if hidden_sizes.is_empty() {
+            (output_buckets.count(), 0)
+        } else {
+            (0, output_buckets.count())
+        };
+        Box::new(Self {
+            buckets,
+            output_buckets,
+            feature_weights: vec![Align([0; LAYER_1_SIZE]); INPUT * buckets.count()],
+            feature_bias: Align([0; LAYER_1_SIZE]),
+            output_weights: vec![Align([0; LAYER_1_SIZE * 2]); n_heads],
+            output_bias: vec![0; n_heads],
+            layer_stacks: vec![LayerStack::zeroed(hidden_sizes); n_stacks],
+        })
     }
 
-    pub fn visualise_neuron(&self, neuron: usize, path: &std::path::Path) {
-        #![allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
-        // remap pieces to keep opposite colours together
-        static PIECE_REMAPPING: [usize; 12] = [0, 2, 4, 6, 8, 10, 1, 3, 5, 7, 9, 11];
-        assert!(neuron < LAYER_1_SIZE);
-        let starting_idx = neuron * INPUT;
-        let slice = &self.feature_weights[starting_idx..starting_idx + INPUT];
-
-        let mut image = Image::zeroed(8 * 6 + 5, 8 * 2 + 1); // + for inter-piece spacing
-
-        for (piece, chunk) in slice.chunks(64).enumerate() {
-            let piece = PIECE_REMAPPING[piece];
-            let piece_colour = piece % 2;
-            let piece_type = piece / 2;
-            let (max, min) = if piece_type == 0 {
-                let chunk = &chunk[8..56]; // first and last rank are always 0 for pawns
-                (*chunk.iter().max().unwrap(), *chunk.iter().min().unwrap())
-            } else {
-                (*chunk.iter().max().unwrap(), *chunk.iter().min().unwrap())
-            };
-            let weight_to_colour = |weight: i16| -> u32 {
-                let intensity = f32::from(weight - min) / f32::from(max - min);
-                let idx = (intensity * 255.0).round() as u8;
-                image::inferno_colour_map(idx)
-            };
-            for (square, &weight) in chunk.iter().enumerate() {
-                let row = square / 8;
-                let col = square % 8;
-                let colour = if (row == 0 || row == 7) && piece_type == 0 {
-                    0 // pawns on first and last rank are always 0
-                } else {
-                    weight_to_colour(weight)
-                };
-                image.set(col + piece_type * 8 + piece_type, row + piece_colour * 9, colour);
+    /// The widths of the hidden layers between L1 and the output.
+    pub fn hidden_sizes(&self) -> Vec<usize> {
+        self.layer_stacks
+            .first()
+            .map_or_else(Vec::new, |stack| stack.hidden.iter().map(DenseLayer::outputs).collect())
+    }
+
+    pub fn num_params(&self) -> usize {
+        let stack_params = self
+            .layer_stacks
+            .iter()
+            .map(|stack| {
+                let hidden = stack.hidden.iter().map(|l| l.weights.len() + l.bias.len());
+                hidden.sum::<usize>() + stack.output_weights.len() + 1
+            })
+            .sum::<usize>();
+        self.feature_weights.len() * LAYER_1_SIZE
+            + LAYER_1_SIZE
+            + self.output_weights.len() * LAYER_1_SIZE * 2
+            + self.output_bias.len()
+            + stack_params
+        // don't duplicate the feature weights
+    }
+
+    /// The bucket that a perspective's king is in.
+    fn king_bucket(&self, board: &Board, perspective: Colour) -> KingBucket {
+        // boards without kings (such as the empty board that threads start with) use the first bucket.
+        let has_king = board.pieces.piece_bb(Piece::new(perspective, PieceType::KING)) != 0;
+        if has_king {
+            self.buckets.bucket(perspective, board.king_sq(perspective))
+        } else {
+            KingBucket::default()
+        }
+    }
+
+    /// Compute one side of the first layer for a board from scratch.
+    fn compute_perspective(
+        &self,
+        board: &Board,
+        perspective: Colour,
+        bucket: KingBucket,
+        acc: &mut [i16; LAYER_1_SIZE],
+    ) {
+        acc.copy_from_slice(&*self.feature_bias);
+
+        for colour in [Colour::WHITE, Colour::BLACK] {
+            for piece_type in PieceType::all() {
+                let piece_bb = board.pieces.piece_bb(Piece::new(colour, piece_type));
+                for sq in BitLoop::new(piece_bb) {
+                    let (white_idx, black_idx) = feature_indices(sq, piece_type, colour);
+                    let idx = if perspective == Colour::WHITE { white_idx } else { black_idx };
+                    add_to_all(acc, &self.feature_weights[bucket.feature(idx)]);
+                }
             }
         }
+    }
 
-        let path = path.join(format!("neuron_{neuron}.tga"));
-        image.save_as_tga(path);
+    /// The input weights of a neuron, as a heatmap for each piece,
+    /// with a row of boards for each side in each king bucket.
+    pub fn neuron_grid(&self, neuron: usize) -> BoardGrid {
+        assert!(neuron < LAYER_1_SIZE);
+        let mut grid = BoardGrid::new(["P", "N", "B", "R", "Q", "K"]);
+        let n_buckets = self.buckets.count();
+        for (bucket, rows) in self.feature_weights.chunks(INPUT).enumerate() {
+            // features are relative to the perspective, so the first six pieces are its own.
+            for (side, pieces) in ["US", "THEM"].iter().zip(rows.chunks(INPUT / 2)) {
+                let boards = pieces
+                    .chunks(64)
+                    .enumerate()
+                    .map(|(piece_type, squares)| {
+                        std::array::from_fn(|sq| {
+                            // pawns on the first and last rank don't exist.
+                            let back_rank = piece_type == 0 && !(8..56).contains(&sq);
+                            (!back_rank).then(|| f32::from(squares[sq][neuron]))
+                        })
+                    })
+                    .collect();
+                let label =
+                    if n_buckets > 1 { format!("{side}{bucket}") } else { (*side).to_string() };
+                grid.add_row(label, boards);
+            }
+        }
+        grid
     }
 
+    /// Load and quantise a network from the JSON emitted by the trainer.
+    /// (see `FloatNNUEParams::from_json` for the format)
     pub fn from_json(path: impl AsRef<std::path::Path>) -> Box<Self> {
-        #![allow(clippy::cast_possible_truncation)]
-        fn weight<const LEN: usize>(
-            weight_relation: &Value,
-            weight_array: &mut [i16; LEN],
-            stride: usize,
-            k: i32,
-            flip: bool,
-        ) {
-            for (i, output) in weight_relation.as_array().unwrap().iter().enumerate() {
-                for (j, weight) in output.as_array().unwrap().iter().enumerate() {
-                    let index = if flip { j * stride + i } else { i * stride + j };
-                    let value = weight.as_f64().unwrap();
-                    weight_array[index] = (value * f64::from(k)) as i16;
-                }
+        FloatNNUEParams::from_json(path).quantise()
+    }
+
+    /// Parse a network from the contents of its binary files, in the order of `NET_FILES`.
+    ///
+    /// `buckets.bin` holds the king bucket layout, followed by the output bucket
+    /// mapping for nets with more than one output head.
+    ///
+    /// `hidden_layers.bin` is empty for nets whose output is computed directly from L1.
+    /// Otherwise, it holds the number of hidden layers and the width of each as u32s,
+    /// followed by, for each output bucket: the int8 weights and i32 biases of each hidden
+    /// layer, the i16 output weights, and the i32 output bias.
+    pub fn from_bytes(parts: [&[u8]; 6]) -> Box<Self> {
+        fn read<'a>(bytes: &[u8], dst: impl Iterator<Item = &'a mut i16>, name: &str) {
+            let mut chunks = bytes.chunks_exact(2);
+            for v in dst {
+                let b = chunks.next().unwrap_or_else(|| panic!("{name}.bin is too short"));
+                *v = i16::from_le_bytes([b[0], b[1]]);
             }
+            assert!(
+                chunks.next().is_none() && chunks.remainder().is_empty(),
+                "{name}.bin is too long"
+            );
         }
 
-        fn bias<const LEN: usize>(bias_relation: &Value, bias_array: &mut [i16; LEN], k: i32) {
-            for (i, bias) in bias_relation.as_array().unwrap().iter().enumerate() {
-                let value = bias.as_f64().unwrap();
-                bias_array[i] = (value * f64::from(k)) as i16;
-            }
+        fn take<const N: usize>(bytes: &mut &[u8]) -> [u8; N] {
+            assert!(bytes.len() >= N, "hidden_layers.bin is too short");
+            let (head, tail) = bytes.split_at(N);
+            *bytes = tail;
+            head.try_into().unwrap()
         }
 
-        #[allow(clippy::large_stack_arrays)]
-        // we only run this function in release, so it shouldn't blow the stack.
-        let mut out = Box::new(Self {
-            feature_weights: Align([0; INPUT * LAYER_1_SIZE]),
-            feature_bias: Align([0; LAYER_1_SIZE]),
-            output_weights: Align([0; LAYER_1_SIZE * 2]),
-            output_bias: 0,
-        });
-
-        let file = fs::read_to_string(path).unwrap();
-        let json: Value = serde_json::from_str(&file).unwrap();
-
-        let mut things_found = 0;
-        for (key, value) in json.as_object().unwrap() {
-            match key.as_str() {
-                "perspective.weight" => {
-                    // weight(value, &mut out.feature_weights, INPUT, QA, false);
-                    weight(value, &mut out.feature_weights, LAYER_1_SIZE, QA, true);
-                    things_found += 1;
-                }
-                "perspective.bias" => {
-                    bias(value, &mut out.feature_bias, QA);
-                    things_found += 1;
-                }
-                "out.weight" => {
-                    weight(value, &mut out.output_weights, LAYER_1_SIZE * 2, QB, false);
-                    things_found += 1;
+        let [buckets, feature_weights, feature_bias, output_weights, output_bias, mut hidden_layers] =
+            parts;
+        let (king_buckets, output_buckets) = buckets.split_at(buckets.len().min(65));
+        let n_hidden =
+            if hidden_layers.is_empty() { 0 } else { u32::from_le_bytes(take(&mut hidden_layers)) };
+        let hidden_sizes = (0..n_hidden)
+            .map(|_| usize::try_from(u32::from_le_bytes(take(&mut hidden_layers))).unwrap())
+            .collect::<Vec<_>>();
+        let mut out = Self::zeroed(
+            BucketLayout::from_bytes(king_buckets),
+            OutputBuckets::from_bytes(output_buckets),
+            &hidden_sizes,
+        );
+        assert_eq!(
+            feature_weights.len(),
+            out.feature_weights.len() * LAYER_1_SIZE * 2,
+            "feature_weights.bin doesn't match the {}-bucket layout",
+            out.buckets.count()
+        );
+        for (row, bytes) in
+            out.feature_weights.iter_mut().zip(feature_weights.chunks(LAYER_1_SIZE * 2))
+        {
+            read(bytes, row.iter_mut(), "feature_weights");
+        }
+        read(feature_bias, out.feature_bias.iter_mut(), "feature_bias");
+        read(
+            output_weights,
+            out.output_weights.iter_mut().flat_map(|head| head.iter_mut()),
+            "output_weights",
+        );
+        read(output_bias, out.output_bias.iter_mut(), "output_bias");
+        for stack in &mut out.layer_stacks {
+            for layer in &mut stack.hidden {
+                for w in &mut layer.weights {
+                    *w = i8::from_le_bytes(take(&mut hidden_layers));
                 }
-                "out.bias" => {
-                    bias(value, from_mut(&mut out.output_bias), QAB);
-                    things_found += 1;
+                for b in &mut layer.bias {
+                    *b = i32::from_le_bytes(take(&mut hidden_layers));
                 }
-                _ => {}
             }
+            for w in &mut stack.output_weights {
+                *w = i16::from_le_bytes(take(&mut hidden_layers));
+            }
+            stack.output_bias = i32::from_le_bytes(take(&mut hidden_layers));
         }
-        assert_eq!(things_found, 4, "Not all parameters found!");
+        assert!(hidden_layers.is_empty(), "hidden_layers.bin is too long");
 
         out
     }
 
+    /// Serialise the network into the contents of its binary files, in the order of `NET_FILES`.
     pub fn to_bytes(&self) -> Vec<Vec<u8>> {
-        let mut out = Vec::new();
-
-        // let (head, feature_weights, tail) = unsafe { self.feature_weights.align_to::<u8>() };
-        // assert!(head.is_empty() && tail.is_empty());
-        let (head, feature_weights, tail) = unsafe { self.feature_weights.align_to::<u8>() };
-        assert!(head.is_empty() && tail.is_empty());
-        let (head, feature_bias, tail) = unsafe { self.feature_bias.align_to::<u8>() };
-        assert!(head.is_empty() && tail.is_empty());
-        let (head, output_weights, tail) = unsafe { self.output_weights.align_to::<u8>() };
-        assert!(head.is_empty() && tail.is_empty());
-        let ob = [self.output_bias];
-        let (head, output_bias, tail) = unsafe { ob.align_to::<u8>() };
-        assert!(head.is_empty() && tail.is_empty());
-
-        // out.push(feature_weights.to_vec());
-        out.push(feature_weights.to_vec());
-        out.push(feature_bias.to_vec());
-        out.push(output_weights.to_vec());
-        out.push(output_bias.to_vec());
+        fn write<'a>(values: impl IntoIterator<Item = &'a i16>) -> Vec<u8> {
+            values.into_iter().flat_map(|v| v.to_le_bytes()).collect()
+        }
 
-        out
+        let mut buckets = self.buckets.to_bytes();
+        if self.output_buckets != OutputBuckets::SINGLE {
+            buckets.extend_from_slice(&self.output_buckets.map);
+        }
+
+        let mut hidden_layers = Vec::new();
+        let hidden_sizes = self.hidden_sizes();
+        if !self.layer_stacks.is_empty() {
+            hidden_layers.extend(u32::try_from(hidden_sizes.len()).unwrap().to_le_bytes());
+            for &size in &hidden_sizes {
+                hidden_layers.extend(u32::try_from(size).unwrap().to_le_bytes());
+            }
+        }
+        for stack in &self.layer_stacks {
+            for layer in &stack.hidden {
+                hidden_layers.extend(layer.weights.iter().flat_map(|w| w.to_le_bytes()));
+                hidden_layers.extend(layer.bias.iter().flat_map(|b| b.to_le_bytes()));
+            }
+            hidden_layers.extend(write(&stack.output_weights));
+            hidden_layers.extend(stack.output_bias.to_le_bytes());
+        }
+
+        vec![
+            buckets,
+            write(self.feature_weights.iter().flat_map(|row| row.iter())),
+            write(self.feature_bias.iter()),
+            write(self.output_weights.iter().flat_map(|head| head.iter())),
+            write(self.output_bias.iter()),
+            hidden_layers,
+        ]
     }
 }
 
 /// State of the partial activations of the NNUE network.
-#[allow(clippy::upper_case_acronyms)]
+#[allow(clippy::upper_case_acronyms, clippy::large_stack_frames)]
 #[derive(Debug, Clone)]
 pub struct NNUEState {
     /// Active features from white's perspective.
@@ -231,11 +605,22 @@ pub struct NNUEState {
 
     /// Accumulators for the first layer.
     pub accumulators: [Accumulator<LAYER_1_SIZE>; ACC_STACK_SIZE],
+    /// The king bucket of each perspective, for each accumulator.
+    pub buckets: [[KingBucket; 2]; ACC_STACK_SIZE],
+    /// The features changed by the move that led to each accumulator.
+    updates: [UpdateBuffer; ACC_STACK_SIZE],
+    /// Whether each perspective of each accumulator has been brought up to date.
+    computed: [[bool; 2]; ACC_STACK_SIZE],
+    /// The last accumulator computed for each king bucket, for each perspective.
+    refresh_table: Vec<[RefreshEntry<LAYER_1_SIZE>; 2]>,
     /// Index of the current accumulator.
     pub current_acc: usize,
+    /// The network being evaluated.
+    params: &'static NNUEParams,
 }
 
-const fn feature_indices(sq: Square, piece_type: PieceType, colour: Colour) -> (usize, usize) {
+/// The (unbucketed) feature index of a piece on a square, from white's and black's point of view.
+pub const fn feature_indices(sq: Square, piece_type: PieceType, colour: Colour) -> (usize, usize) {
     const COLOUR_STRIDE: usize = 64 * 6;
     const PIECE_STRIDE: usize = 64;
 
@@ -253,6 +638,11 @@ const fn feature_indices(sq: Square, piece_type: PieceType, colour: Colour) -> (
 impl NNUEState {
     /// Create a new `NNUEState`.
     pub fn new(board: &Board) -> Box<Self> {
+        Self::with_params(board, &NNUE)
+    }
+
+    /// Create a new `NNUEState` that evaluates the given network.
+    pub fn with_params(board: &Board, params: &'static NNUEParams) -> Box<Self> {
         #![allow(clippy::cast_ptr_alignment)]
         // NNUEState is INPUT * 2 * 2 + LAYER_1_SIZE * ACC_STACK_SIZE * 2 * 2 + 8 bytes
         // at time of writing, this adds up to 396,296 bytes.
@@ -260,19 +650,32 @@ impl NNUEState {
         // Unfortunately, in debug mode `Box::new(Self::new())` will allocate on the stack
         // and then memcpy it to the heap, so we have to do this manually.
 
-        // SAFETY: NNUEState has four fields:
+        // SAFETY: NNUEState has nine fields:
         // {white,black}_pov, which are just arrays of ints, for whom the all-zeroes bitpattern is valid.
         // current_acc, which is just an int, so the all-zeroes bitpattern is valid.
         // accumulators, which is an array of Accumulator<SIZE>.
         //     Accumulator is a struct containing a pair of arrays of ints, so this field is safe for zeroing too.
-        // As all fields can be safely initialised to all zeroes, the following code is sound.
+        // buckets, which is an array of KingBuckets, which are pairs of ints, so this is also safe.
+        // updates, which is an array of UpdateBuffers, which contain only ints, so this is also safe.
+        // computed, which is an array of bools, for whom the all-zeroes bitpattern is `false`.
+        // refresh_table, which is a Vec, and so must not be null. We write it before creating the box.
+        // params, which is a reference, and so must not be null. We write it before creating the box.
+        // As all other fields can be safely initialised to all zeroes, the following code is sound.
         let mut net: Box<Self> = unsafe {
             let layout = std::alloc::Layout::new::<Self>();
-            let ptr = std::alloc::alloc_zeroed(layout);
+            let ptr = std::alloc::alloc_zeroed(layout).cast::<Self>();
             if ptr.is_null() {
                 std::alloc::handle_alloc_error(layout);
             }
-            Box::from_raw(ptr.cast())
+            std::ptr::addr_of_mut!((*ptr).params).write(params);
+            std::ptr::addr_of_mut!((*ptr).refresh_table).write(vec![
+                [
+                    RefreshEntry::new(&params.feature_bias),
+                    RefreshEntry::new(&params.feature_bias),
+                ];
+                params.buckets.count() * 2
+            ]);
+            Box::from_raw(ptr)
         };
 
         net.refresh_acc(board);
@@ -280,14 +683,18 @@ impl NNUEState {
         net
     }
 
-    /// Copy the current accumulator to the next accumulator, and increment the current accumulator.
-    pub fn push_acc(&mut self) {
-        self.accumulators[self.current_acc + 1] = self.accumulators[self.current_acc];
+    /// Move to the next accumulator, which starts out empty and uncomputed.
+    /// The updates for the move being made are recorded against it, and are only applied
+    /// when the position is evaluated.
+    pub const fn push_acc(&mut self) {
+        self.buckets[self.current_acc + 1] = self.buckets[self.current_acc];
+        self.updates[self.current_acc + 1].clear();
+        self.computed[self.current_acc + 1] = [false; 2];
         self.current_acc += 1;
     }
 
     /// Decrement the current accumulator.
-    pub fn pop_acc(&mut self) {
+    pub const fn pop_acc(&mut self) {
         self.current_acc -= 1;
     }
 
@@ -295,26 +702,95 @@ impl NNUEState {
     pub fn refresh_acc(&mut self, board: &Board) {
         self.current_acc = 0;
 
-        #[cfg(debug_assertions)]
-        self.white_pov.fill(0);
-        #[cfg(debug_assertions)]
-        self.black_pov.fill(0);
+        self.refresh_perspective(board, Colour::WHITE);
+        self.refresh_perspective(board, Colour::BLACK);
+        self.updates[0].clear();
 
-        self.accumulators[self.current_acc].init(&NNUE.feature_bias);
+        #[cfg(debug_assertions)]
+        {
+            self.white_pov.fill(0);
+            self.black_pov.fill(0);
+            for colour in [Colour::WHITE, Colour::BLACK] {
+                for piece_type in PieceType::all() {
+                    let piece_bb = board.pieces.piece_bb(Piece::new(colour, piece_type));
+                    for sq in BitLoop::new(piece_bb) {
+                        self.update_pov_manual::<Activate>(piece_type, colour, sq);
+                    }
+                }
+            }
+        }
+    }
 
+    /// Rebuild one side of the current accumulator from a board,
+    /// selecting the bucket from the position of that side's king.
+    ///
+    /// Rather than starting from the bias, this starts from the last accumulator computed
+    /// for the same bucket, and only adds and removes the pieces that differ.
+    fn refresh_perspective(&mut self, board: &Board, perspective: Colour) {
+        let bucket = self.params.king_bucket(board, perspective);
+        self.buckets[self.current_acc][perspective.index()] = bucket;
+        self.computed[self.current_acc][perspective.index()] = true;
+
+        // collect the changed features first, so that additions and removals can be paired up.
+        let (mut adds, mut n_adds) = ([0; 32], 0);
+        let (mut subs, mut n_subs) = ([0; 32], 0);
+        let entry = &mut self.refresh_table[bucket.index()][perspective.index()];
         for colour in [Colour::WHITE, Colour::BLACK] {
             for piece_type in PieceType::all() {
-                let piece = Piece::new(colour, piece_type);
-                let piece_bb = board.pieces.piece_bb(piece);
-
-                for sq in BitLoop::new(piece_bb) {
-                    self.efficiently_update_manual::<Activate>(piece_type, colour, sq);
+                let piece_bb = board.pieces.piece_bb(Piece::new(colour, piece_type));
+                let cached_bb = &mut entry.bitboards[colour.index()][piece_type.index() - 1];
+                let feature = |sq| {
+                    let (white_idx, black_idx) = feature_indices(sq, piece_type, colour);
+                    let idx = if perspective == Colour::WHITE { white_idx } else { black_idx };
+                    bucket.feature(idx)
+                };
+                for sq in BitLoop::new(piece_bb & !*cached_bb) {
+                    adds[n_adds] = feature(sq);
+                    n_adds += 1;
+                }
+                for sq in BitLoop::new(*cached_bb & !piece_bb) {
+                    subs[n_subs] = feature(sq);
+                    n_subs += 1;
                 }
+                *cached_bb = piece_bb;
             }
         }
+
+        let weights = &self.params.feature_weights;
+        let (adds, subs) = (&adds[..n_adds], &subs[..n_subs]);
+        for (&add, &sub) in adds.iter().zip(subs) {
+            subtract_and_add_to_all(&mut entry.accumulator, &weights[sub], &weights[add]);
+        }
+        for &add in adds.iter().skip(subs.len()) {
+            add_to_all(&mut entry.accumulator, &weights[add]);
+        }
+        for &sub in subs.iter().skip(adds.len()) {
+            sub_from_all(&mut entry.accumulator, &weights[sub]);
+        }
+
+        self.accumulators[self.current_acc]
+            .perspective_mut(perspective)
+            .copy_from_slice(&*entry.accumulator);
     }
 
-    /// Update the state from a move.
+    /// Check that the current accumulator matches one computed from scratch.
+    pub fn check_against_refresh(&self, board: &Board) -> bool {
+        let mut expected = Align([0; LAYER_1_SIZE]);
+        let acc = &self.accumulators[self.current_acc];
+        [(Colour::WHITE, &acc.white), (Colour::BLACK, &acc.black)].into_iter().all(
+            |(perspective, actual)| {
+                let bucket = self.params.king_bucket(board, perspective);
+                self.params.compute_perspective(board, perspective, bucket, &mut expected);
+                bucket == self.buckets[self.current_acc][perspective.index()]
+                    && *expected == **actual
+            },
+        )
+    }
+
+    /// Record a piece moving from one square to another.
+    ///
+    /// A king move that changes the king's bucket invalidates that side's accumulator,
+    /// which will be refreshed from the board when the position is next evaluated.
     pub fn efficiently_update_from_move(
         &mut self,
         piece_type: PieceType,
@@ -325,20 +801,13 @@ impl NNUEState {
         let (white_from, black_from) = feature_indices(from, piece_type, colour);
         let (white_to, black_to) = feature_indices(to, piece_type, colour);
 
-        let acc = &mut self.accumulators[self.current_acc];
+        if piece_type == PieceType::KING {
+            self.buckets[self.current_acc][colour.index()] = self.params.buckets.bucket(colour, to);
+        }
 
-        subtract_and_add_to_all(
-            &mut acc.white,
-            &NNUE.feature_weights,
-            white_from * LAYER_1_SIZE,
-            white_to * LAYER_1_SIZE,
-        );
-        subtract_and_add_to_all(
-            &mut acc.black,
-            &NNUE.feature_weights,
-            black_from * LAYER_1_SIZE,
-            black_to * LAYER_1_SIZE,
-        );
+        let updates = &mut self.updates[self.current_acc];
+        updates.sub(Feature { white: white_from, black: black_from });
+        updates.add(Feature { white: white_to, black: black_to });
 
         #[cfg(debug_assertions)]
         {
@@ -351,7 +820,7 @@ impl NNUEState {
         }
     }
 
-    /// Update by activating or deactivating a piece.
+    /// Record a piece being added or removed.
     pub fn efficiently_update_manual<A: Activation>(
         &mut self,
         piece_type: PieceType,
@@ -359,14 +828,11 @@ impl NNUEState {
         sq: Square,
     ) {
         let (white_idx, black_idx) = feature_indices(sq, piece_type, colour);
-        let acc = &mut self.accumulators[self.current_acc];
-
+        let feature = Feature { white: white_idx, black: black_idx };
         if A::ACTIVATE {
-            add_to_all(&mut acc.white, &NNUE.feature_weights, white_idx * LAYER_1_SIZE);
-            add_to_all(&mut acc.black, &NNUE.feature_weights, black_idx * LAYER_1_SIZE);
+            self.updates[self.current_acc].add(feature);
         } else {
-            sub_from_all(&mut acc.white, &NNUE.feature_weights, white_idx * LAYER_1_SIZE);
-            sub_from_all(&mut acc.black, &NNUE.feature_weights, black_idx * LAYER_1_SIZE);
+            self.updates[self.current_acc].sub(feature);
         }
 
         #[cfg(debug_assertions)]
@@ -377,6 +843,62 @@ impl NNUEState {
         }
     }
 
+    /// Bring the current accumulator up to date with `board`, which must be the current position.
+    ///
+    /// Each side walks back to its last computed accumulator and replays the recorded updates
+    /// from there, unless its king changed bucket along the way, in which case it is refreshed.
+    pub fn materialise(&mut self, board: &Board) {
+        for perspective in [Colour::WHITE, Colour::BLACK] {
+            let side = perspective.index();
+            if self.computed[self.current_acc][side] {
+                continue;
+            }
+            // the root accumulator is always computed, so this walk terminates.
+            let mut last = self.current_acc;
+            let mut refresh = false;
+            while !self.computed[last][side] {
+                if self.buckets[last][side] != self.buckets[last - 1][side] {
+                    refresh = true;
+                    break;
+                }
+                last -= 1;
+            }
+            if refresh {
+                self.refresh_perspective(board, perspective);
+                continue;
+            }
+            for idx in last + 1..=self.current_acc {
+                self.apply_updates(idx, perspective);
+            }
+        }
+    }
+
+    /// Compute one side of the accumulator at `idx` from the one before it.
+    fn apply_updates(&mut self, idx: usize, perspective: Colour) {
+        let (prev, next) = self.accumulators.split_at_mut(idx);
+        let src = prev[idx - 1].perspective_mut(perspective);
+        let acc = next[0].perspective_mut(perspective);
+        acc.copy_from_slice(src);
+
+        let bucket = self.buckets[idx][perspective.index()];
+        let updates = &self.updates[idx];
+        let row = |feature: &Feature| {
+            &self.params.feature_weights[bucket.feature(feature.index(perspective))]
+        };
+        let (adds, subs) = (updates.adds(), updates.subs());
+        for (add, sub) in adds.iter().zip(subs) {
+            subtract_and_add_to_all(acc, row(sub), row(add));
+        }
+        for add in adds.iter().skip(subs.len()) {
+            add_to_all(acc, row(add));
+        }
+        for sub in subs.iter().skip(adds.len()) {
+            sub_from_all(acc, row(sub));
+        }
+
+        self.computed[idx][perspective.index()] = true;
+    }
+
     /// Update only the feature planes that are affected by the given move.
     /// This is just for debugging purposes.
     #[cfg(debug_assertions)]
@@ -435,16 +957,31 @@ impl NNUEState {
         }
     }
 
-    /// Evaluate the final layer on the partial activations.
-    pub fn evaluate(&self, stm: Colour) -> i32 {
+    /// Evaluate the final layer on the partial activations,
+    /// using the output bucket for a position with `piece_count` pieces.
+    ///
+    /// The current accumulator must have been brought up to date with [`Self::materialise`].
+    pub fn evaluate(&self, stm: Colour, piece_count: u32) -> i32 {
+        debug_assert!(
+            self.computed[self.current_acc] == [true; 2],
+            "evaluating a stale accumulator"
+        );
         let acc = &self.accumulators[self.current_acc];
 
         let (us, them) =
             if stm == Colour::WHITE { (&acc.white, &acc.black) } else { (&acc.black, &acc.white) };
 
-        let output = screlu_flatten(us, them, &NNUE.output_weights);
+        let bucket = self.params.output_buckets.bucket(piece_count);
+        // nets with hidden layers after L1 have a stack of them for each bucket.
+        let output = self.params.layer_stacks.get(bucket).map_or_else(
+            || {
+                screlu_flatten(us, them, &self.params.output_weights[bucket])
+                    + i32::from(self.params.output_bias[bucket])
+            },
+            |stack| stack.evaluate(us, them),
+        );
 
-        (output + i32::from(NNUE.output_bias)) * SCALE / QAB
+        output * SCALE / QAB
     }
 
     /// Get the active features for the current position, from white's perspective.
@@ -497,44 +1034,6 @@ impl NNUEState {
     }
 }
 
-/// Move a feature from one square to another.
-fn subtract_and_add_to_all<const SIZE: usize, const WEIGHTS: usize>(
-    input: &mut [i16; SIZE],
-    delta: &[i16; WEIGHTS],
-    offset_sub: usize,
-    offset_add: usize,
-) {
-    let s_block = &delta[offset_sub..offset_sub + SIZE];
-    let a_block = &delta[offset_add..offset_add + SIZE];
-    for ((i, ds), da) in input.iter_mut().zip(s_block).zip(a_block) {
-        *i = *i - *ds + *da;
-    }
-}
-
-/// Add a feature to a square.
-fn add_to_all<const SIZE: usize, const WEIGHTS: usize>(
-    input: &mut [i16; SIZE],
-    delta: &[i16; WEIGHTS],
-    offset_add: usize,
-) {
-    let a_block = &delta[offset_add..offset_add + SIZE];
-    for (i, d) in input.iter_mut().zip(a_block) {
-        *i += *d;
-    }
-}
-
-/// Subtract a feature from a square.
-fn sub_from_all<const SIZE: usize, const WEIGHTS: usize>(
-    input: &mut [i16; SIZE],
-    delta: &[i16; WEIGHTS],
-    offset_sub: usize,
-) {
-    let s_block = &delta[offset_sub..offset_sub + SIZE];
-    for (i, d) in input.iter_mut().zip(s_block) {
-        *i -= *d;
-    }
-}
-
 #[allow(dead_code)]
 fn crelu(x: i16) -> i32 {
     i32::from(x.clamp(CR_MIN, CR_MAX))
@@ -558,69 +1057,338 @@ pub fn crelu_flatten(
     sum
 }
 
-fn screlu(x: i16) -> i32 {
+pub fn screlu(x: i16) -> i32 {
     let x = x.clamp(CR_MIN, CR_MAX);
     let x = i32::from(x);
     x * x
 }
 
-/// Execute squared + clipped relu on the partial activations,
-/// and accumulate the result into a sum.
-pub fn screlu_flatten(
-    us: &[i16; LAYER_1_SIZE],
-    them: &[i16; LAYER_1_SIZE],
-    weights: &[i16; LAYER_1_SIZE * 2],
-) -> i32 {
-    let mut sum: i32 = 0;
-    for (&i, &w) in us.iter().zip(&weights[..LAYER_1_SIZE]) {
-        sum += screlu(i) * i32::from(w);
-    }
-    for (&i, &w) in them.iter().zip(&weights[LAYER_1_SIZE..]) {
-        sum += screlu(i) * i32::from(w);
-    }
-    sum / QA
-}
-
 /// Load a network from a JSON file, and emit binary files.
 pub fn convert_json_to_binary(
     json_path: impl AsRef<std::path::Path>,
     output_path: impl AsRef<std::path::Path>,
 ) {
-    let nnue = NNUEParams::from_json(json_path);
-    let bytes = nnue.to_bytes();
+    let (nnue, stats) = FloatNNUEParams::from_json(json_path).quantise_with_stats();
+    println!("{stats}");
+    if stats.has_problems() {
+        eprintln!("warning: quantisation clipped parameters or left sums that could overflow");
+    }
     fs::create_dir(&output_path).unwrap();
-    for (fname, byte_vector) in
-        ["feature_weights", "feature_bias", "output_weights", "output_bias"].into_iter().zip(&bytes)
-    {
-        let mut f =
-            fs::File::create(output_path.as_ref().join(fname).with_extension("bin")).unwrap();
-        std::io::Write::write_all(&mut f, byte_vector).unwrap();
+    write_net_files(&nnue, output_path.as_ref()).unwrap();
+}
+
+/// Write a network as the binary files that are embedded in the engine.
+pub fn write_net_files(nnue: &NNUEParams, output_path: &std::path::Path) -> std::io::Result<()> {
+    for (fname, byte_vector) in NET_FILES.into_iter().zip(&nnue.to_bytes()) {
+        fs::write(output_path.join(fname).with_extension("bin"), byte_vector)?;
     }
+    Ok(())
+}
+
+/// Read a network from the binary files written by [`write_net_files`].
+pub fn read_net_files(path: &std::path::Path) -> std::io::Result<Box<NNUEParams>> {
+    let files = NET_FILES
+        .into_iter()
+        .map(|fname| {
+            let file = path.join(fname).with_extension("bin");
+            fs::read(&file)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", file.display())))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let parts: Vec<&[u8]> = files.iter().map(Vec::as_slice).collect();
+    Ok(NNUEParams::from_bytes(parts.try_into().unwrap()))
 }
 
 /// Benchmark the inference portion of the NNUE evaluation.
 /// (everything after the feature extraction)
-pub fn inference_benchmark(state: &NNUEState) {
+pub fn inference_benchmark(state: &NNUEState, board: &Board) {
+    let piece_count = board.pieces.occupied().count_ones();
     let start = std::time::Instant::now();
     for _ in 0..1_000_000 {
-        std::hint::black_box(state.evaluate(Colour::WHITE));
+        std::hint::black_box(state.evaluate(Colour::WHITE, piece_count));
     }
     let elapsed = start.elapsed();
     let nanos = elapsed.as_nanos();
     let ns_per_eval = nanos / 1_000_000;
-    println!("{ns_per_eval} ns per evaluation");
+    println!("{ns_per_eval} ns per evaluation ({} kernels)", simd::Kernel::active().name());
 }
 
-pub fn visualise_nnue() {
+/// Benchmark accumulator refreshes through the refresh table against refreshes from scratch,
+/// both for successive positions in a game (as when converting data or starting a search)
+/// and for unrelated positions (the worst case for the table).
+pub fn refresh_benchmark() {
+    const ROUNDS: usize = 100;
+    const GAME_LENGTH: usize = 16;
+    let unrelated = crate::bench::BENCH_POSITIONS
+        .iter()
+        .map(|fen| Board::from_fen(fen).unwrap())
+        .collect::<Vec<_>>();
+    // play out a few moves from each bench position to get runs of related positions.
+    let mut games = Vec::new();
+    for board in &unrelated {
+        let mut board = board.clone();
+        for _ in 0..GAME_LENGTH {
+            games.push(board.clone());
+            let Some(&m) = board.legal_moves().first() else { break };
+            board.make_move_base(m);
+        }
+    }
+
+    let time_refreshes = |boards: &[Board], refresh: &mut dyn FnMut(&Board)| {
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            boards.iter().for_each(&mut *refresh);
+        }
+        start.elapsed().as_nanos() / (ROUNDS * boards.len()) as u128
+    };
+    let mut state = NNUEState::new(&unrelated[0]);
+    let mut cached = |board: &Board| {
+        state.refresh_acc(board);
+        std::hint::black_box(&state.accumulators[0]);
+    };
+    let (game_cached, unrelated_cached) =
+        (time_refreshes(&games, &mut cached), time_refreshes(&unrelated, &mut cached));
+    let mut acc = Align([0; LAYER_1_SIZE]);
+    let mut scratch = |board: &Board| {
+        for perspective in [Colour::WHITE, Colour::BLACK] {
+            let bucket = NNUE.king_bucket(board, perspective);
+            NNUE.compute_perspective(board, perspective, bucket, &mut acc);
+            std::hint::black_box(&acc);
+        }
+    };
+    let (game_scratch, unrelated_scratch) =
+        (time_refreshes(&games, &mut scratch), time_refreshes(&unrelated, &mut scratch));
+
+    println!("{game_cached} ns per refresh in games ({game_scratch} ns from scratch)");
+    println!("{unrelated_cached} ns per refresh of unrelated positions ({unrelated_scratch} ns from scratch)");
+}
+
+pub fn visualise_nnue(path: &std::path::Path, atlas: bool) {
+    const SCALE: usize = 2;
+    const ATLAS_COLUMNS: usize = 16;
     // create folder for the images
-    let path = std::path::PathBuf::from("nnue-visualisations");
-    std::fs::create_dir_all(&path).unwrap();
-    for neuron in 0..crate::nnue::network::LAYER_1_SIZE {
-        crate::nnue::network::NNUE.visualise_neuron(neuron, &path);
+    std::fs::create_dir_all(path).unwrap();
+    let neurons = (0..LAYER_1_SIZE).map(|neuron| NNUE.neuron_grid(neuron).render(SCALE));
+    if atlas {
+        let tiles =
+            neurons.enumerate().map(|(i, image)| (i.to_string(), image)).collect::<Vec<_>>();
+        image::atlas(&tiles, ATLAS_COLUMNS, SCALE).save(path.join("atlas.png"));
+    } else {
+        for (neuron, image) in neurons.enumerate() {
+            image.save(path.join(format!("neuron_{neuron}.png")));
+        }
     }
 }
 
 mod tests {
+    /// A small random network with four king buckets, mirroring, and eight output buckets.
+    #[cfg(test)]
+    fn bucketed_params() -> &'static super::NNUEParams {
+        use rand::{Rng, SeedableRng};
+        let mut map = [0; 64];
+        for (sq, bucket) in map.iter_mut().enumerate() {
+            // split by rank, then by file within the mirrored half.
+            *bucket = u8::from(sq >= 16) * 2 + u8::from(sq % 8 == 2 || sq % 8 == 5);
+        }
+        let mut params = super::NNUEParams::zeroed(
+            super::BucketLayout { map, mirrored: true },
+            super::OutputBuckets::material_count(8),
+            &[],
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(41);
+        for row in &mut params.feature_weights {
+            row.iter_mut().for_each(|w| *w = rng.gen_range(-64..64));
+        }
+        params.feature_bias.iter_mut().for_each(|w| *w = rng.gen_range(-64..64));
+        for head in &mut params.output_weights {
+            head.iter_mut().for_each(|w| *w = rng.gen_range(-64..64));
+        }
+        params.output_bias.iter_mut().for_each(|w| *w = rng.gen_range(-4096..4096));
+        Box::leak(params)
+    }
+
+    #[test]
+    fn bucketed_net_round_trips() {
+        let params = bucketed_params();
+        assert_eq!(params.buckets.count(), 4);
+        let bytes = params.to_bytes();
+        let parts: Vec<&[u8]> = bytes.iter().map(Vec::as_slice).collect();
+        let loaded = super::NNUEParams::from_bytes(parts.try_into().unwrap());
+        assert_eq!(loaded.buckets, params.buckets);
+        assert_eq!(loaded.output_buckets, params.output_buckets);
+        assert_eq!(loaded.output_bias, params.output_bias);
+        assert_eq!(loaded.feature_weights, params.feature_weights);
+        assert_eq!(loaded.output_weights, params.output_weights);
+    }
+
+    #[test]
+    fn output_bucket_selection() {
+        use super::{NNUEState, OutputBuckets};
+        let buckets = OutputBuckets::material_count(8);
+        assert_eq!(buckets.count(), 8);
+        assert_eq!(buckets.bucket(2), 0);
+        assert_eq!(buckets.bucket(5), 0);
+        assert_eq!(buckets.bucket(6), 1);
+        assert_eq!(buckets.bucket(32), 7);
+        assert_eq!(OutputBuckets::material_count(1), OutputBuckets::SINGLE);
+
+        crate::magic::initialise();
+        let params = bucketed_params();
+        let board = crate::board::Board::default();
+        let state = NNUEState::with_params(&board, params);
+        let acc = &state.accumulators[0];
+        for pieces in [2, 10, 17, 32] {
+            let bucket = buckets.bucket(pieces);
+            let output =
+                super::screlu_flatten(&acc.white, &acc.black, &params.output_weights[bucket]);
+            let expected =
+                (output + i32::from(params.output_bias[bucket])) * super::SCALE / super::QAB;
+            assert_eq!(state.evaluate(crate::piece::Colour::WHITE, pieces), expected);
+        }
+    }
+
+    #[test]
+    fn king_bucket_changes_match_refresh() {
+        crate::magic::initialise();
+        let params = bucketed_params();
+        for fen in [
+            "r3k2r/pppq1ppp/2n2n2/8/8/2N2N2/PPPQ1PPP/R3K2R w KQkq - 0 1",
+            "r3k2r/pppq1ppp/2n2n2/8/8/2N2N2/PPPQ1PPP/R3K2R b KQkq - 0 1",
+            "8/8/3k4/2pP4/8/2K5/8/8 w - c6 0 1",
+            "8/5k2/8/8/8/8/3K4/8 b - - 0 1",
+        ] {
+            let mut board = crate::board::Board::from_fen(fen).unwrap();
+            let mut t = crate::threadlocal::ThreadData::new(0, &board);
+            t.nnue = super::NNUEState::with_params(&board, params);
+            let initial = t.nnue.accumulators[0];
+            let mut ml = crate::board::movegen::MoveList::new();
+            board.generate_moves(&mut ml);
+            for &m in ml.iter() {
+                if !board.make_move_nnue(m, &mut t) {
+                    continue;
+                }
+                t.nnue.materialise(&board);
+                let fresh = super::NNUEState::with_params(&board, params);
+                let acc = &t.nnue.accumulators[t.nnue.current_acc];
+                assert_eq!(acc.white, fresh.accumulators[0].white, "{fen} {m}");
+                assert_eq!(acc.black, fresh.accumulators[0].black, "{fen} {m}");
+                assert_eq!(t.nnue.buckets[t.nnue.current_acc], fresh.buckets[0], "{fen} {m}");
+                board.unmake_move_nnue(&mut t);
+                assert_eq!(t.nnue.accumulators[t.nnue.current_acc].white, initial.white);
+                assert_eq!(t.nnue.accumulators[t.nnue.current_acc].black, initial.black);
+            }
+        }
+    }
+
+    #[test]
+    fn refresh_table_matches_scratch() {
+        crate::magic::initialise();
+        let params = bucketed_params();
+        let mut board = crate::board::Board::default();
+        let mut state = super::NNUEState::with_params(&board, params);
+        // revisit each position so that later refreshes start from earlier, unrelated ones.
+        for fen in crate::bench::BENCH_POSITIONS.iter().chain(&crate::bench::BENCH_POSITIONS) {
+            board.set_from_fen(fen).unwrap();
+            state.refresh_acc(&board);
+            assert!(state.check_against_refresh(&board), "{fen}");
+        }
+    }
+
+    /// Walk the move tree, materialising `eager` after every move (as the old eager update
+    /// scheme did) and `lazy` only at leaves and at a scattering of interior nodes.
+    #[cfg(test)]
+    fn walk_lazy_and_eager(
+        board: &mut crate::board::Board,
+        lazy: &mut crate::threadlocal::ThreadData,
+        eager: &mut crate::threadlocal::ThreadData,
+        depth: usize,
+        visited: &mut usize,
+    ) {
+        *visited += 1;
+        if depth == 0 || visited.is_multiple_of(5) {
+            lazy.nnue.materialise(board);
+            let pieces = board.pieces.occupied().count_ones();
+            let (l, e) = (lazy.nnue.current_acc, eager.nnue.current_acc);
+            assert_eq!(
+                lazy.nnue.accumulators[l].white, eager.nnue.accumulators[e].white,
+                "{board}"
+            );
+            assert_eq!(
+                lazy.nnue.accumulators[l].black, eager.nnue.accumulators[e].black,
+                "{board}"
+            );
+            assert_eq!(
+                lazy.nnue.evaluate(board.turn(), pieces),
+                eager.nnue.evaluate(board.turn(), pieces)
+            );
+        }
+        if depth == 0 {
+            return;
+        }
+        let mut ml = crate::board::movegen::MoveList::new();
+        board.generate_moves(&mut ml);
+        for &m in ml.iter() {
+            let mut eager_board = board.clone();
+            if !board.make_move_nnue(m, lazy) {
+                continue;
+            }
+            assert!(eager_board.make_move_nnue(m, eager));
+            eager.nnue.materialise(&eager_board);
+            walk_lazy_and_eager(board, lazy, eager, depth - 1, visited);
+            board.unmake_move_nnue(lazy);
+            eager_board.unmake_move_nnue(eager);
+        }
+    }
+
+    #[test]
+    fn lazy_updates_match_eager() {
+        crate::magic::initialise();
+        let params = bucketed_params();
+        for (fen, depth) in [
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2),
+            ("rnbqk2r/1pp1p1P1/p4np1/2Pp3p/8/3B1N2/PP1P1PPP/RNBQK2R w KQkq - 1 9", 2),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4),
+            ("8/5k2/8/8/8/8/3K4/8 b - - 0 1", 5),
+        ] {
+            let mut board = crate::board::Board::from_fen(fen).unwrap();
+            let mut lazy = crate::threadlocal::ThreadData::new(0, &board);
+            let mut eager = crate::threadlocal::ThreadData::new(0, &board);
+            lazy.nnue = super::NNUEState::with_params(&board, params);
+            eager.nnue = super::NNUEState::with_params(&board, params);
+            walk_lazy_and_eager(&mut board, &mut lazy, &mut eager, depth, &mut 0);
+        }
+    }
+
+    #[test]
+    fn search_evaluations_match_refresh() {
+        use crate::{
+            definitions::{depth::Depth, MEGABYTE},
+            searchinfo::SearchInfo,
+            timemgmt::{SearchLimit, TimeManager},
+            transpositiontable::TT,
+        };
+        crate::magic::initialise();
+        let params = bucketed_params();
+        for fen in [
+            "r3k2r/pppq1ppp/2n2n2/8/8/2N2N2/PPPQ1PPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let mut board = crate::board::Board::from_fen(fen).unwrap();
+            let stopped = std::sync::atomic::AtomicBool::new(false);
+            let time_manager =
+                TimeManager { limit: SearchLimit::Depth(Depth::new(5)), ..TimeManager::default() };
+            let mut info = SearchInfo { time_manager, ..SearchInfo::new(&stopped) };
+            let mut tt = TT::new();
+            tt.resize(MEGABYTE);
+            let mut t = crate::threadlocal::ThreadData::new(0, &board);
+            t.nnue = super::NNUEState::with_params(&board, params);
+            // in debug builds, every evaluation in the search checks the lazily-updated
+            // accumulator against one computed from scratch.
+            board.search_position::<true>(&mut info, std::array::from_mut(&mut t), tt.view());
+            assert!(info.nodes > 0);
+        }
+    }
+
     #[test]
     fn pov_preserved() {
         crate::magic::initialise();
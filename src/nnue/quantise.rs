@@ -0,0 +1,386 @@
+//! Quantisation of floating-point networks, and reports on how much it costs them.
+//!
+//! Every parameter is scaled, rounded to the nearest integer, and clamped to the range of
+//! the integer type it is stored in. Clamped parameters are counted layer by layer, and the
+//! quantised network is checked for neurons whose sums could overflow during inference,
+//! so that a poor choice of `QA`, `QB` or `SCALE` (or a net with outsized weights) shows up
+//! before the net is used.
+
+use std::{error::Error, fmt, path::Path, time::Instant};
+
+use crate::board::Board;
+
+use super::{
+    convert::{DataFormat, InvalidDataPolicy, Records},
+    float::FloatNNUEParams,
+    network::{NNUEParams, NNUEState, INPUT, LAYER_1_SIZE, QA, QH},
+};
+
+/// The most pieces that can be on the board, and so the most features active at once.
+pub(super) const MAX_ACTIVE_FEATURES: usize = 32;
+
+/// The smallest and largest values that each L1 neuron's accumulator could reach in each
+/// king bucket: its bias, plus the (at most 32) most negative or most positive weights.
+pub(super) fn l1_sum_bounds(net: &NNUEParams) -> Vec<[(i32, i32); LAYER_1_SIZE]> {
+    let mut column = Vec::with_capacity(INPUT);
+    net.feature_weights
+        .chunks(INPUT)
+        .map(|rows| {
+            std::array::from_fn(|neuron| {
+                column.clear();
+                column.extend(rows.iter().map(|row| i32::from(row[neuron])));
+                column.sort_unstable();
+                let bias = i32::from(net.feature_bias[neuron]);
+                let lowest = column.iter().take(MAX_ACTIVE_FEATURES).filter(|&&w| w < 0);
+                let highest = column.iter().rev().take(MAX_ACTIVE_FEATURES).filter(|&&w| w > 0);
+                (bias + lowest.sum::<i32>(), bias + highest.sum::<i32>())
+            })
+        })
+        .collect()
+}
+
+/// The largest magnitude that `bias` plus the sum of `weights`, each times an input of
+/// magnitude at most `max_input`, can reach.
+fn worst_sum(weights: impl IntoIterator<Item = i64>, bias: i64, max_input: i64) -> i64 {
+    weights.into_iter().map(|w| w.abs() * max_input).sum::<i64>() + bias.abs()
+}
+
+/// An integer type that parameters are quantised into.
+pub trait Quantised: Copy {
+    const MIN: f64;
+    const MAX: f64;
+
+    /// Convert a value that is already rounded and within range.
+    fn from_clamped(value: f64) -> Self;
+}
+
+macro_rules! impl_quantised {
+    ($($t:ty),*) => {$(
+        impl Quantised for $t {
+            const MIN: f64 = <$t>::MIN as f64;
+            const MAX: f64 = <$t>::MAX as f64;
+
+            fn from_clamped(value: f64) -> Self {
+                #![allow(clippy::cast_possible_truncation)]
+                value as Self
+            }
+        }
+    )*};
+}
+
+impl_quantised!(i8, i16, i32);
+
+/// What happened to one layer's parameters when they were quantised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerStats {
+    pub name: String,
+    /// The number of parameters.
+    pub count: usize,
+    /// The number of parameters that were out of range, and clamped.
+    pub clipped: usize,
+    /// The largest magnitude of any parameter, before quantisation.
+    pub largest: f32,
+    /// The largest magnitude that the parameters can have without being clamped.
+    pub limit: f32,
+}
+
+impl LayerStats {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), count: 0, clipped: 0, largest: 0.0, limit: f32::INFINITY }
+    }
+
+    /// Quantise `values` into `out`, scaling them by `k`, and record what happened.
+    pub fn quantise<'a, T: Quantised + 'a>(
+        &mut self,
+        values: &[f32],
+        k: i32,
+        out: impl IntoIterator<Item = &'a mut T>,
+    ) {
+        #![allow(clippy::cast_possible_truncation)]
+        let k = f64::from(k);
+        self.limit = self.limit.min((T::MAX / k) as f32);
+        for (q, &v) in out.into_iter().zip(values) {
+            let scaled = (f64::from(v) * k).round();
+            self.count += 1;
+            self.largest = self.largest.max(v.abs());
+            if !(T::MIN..=T::MAX).contains(&scaled) {
+                self.clipped += 1;
+            }
+            *q = T::from_clamped(scaled.clamp(T::MIN, T::MAX));
+        }
+    }
+}
+
+/// What happened to a network when it was quantised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantisationStats {
+    pub layers: Vec<LayerStats>,
+    /// The number of (king bucket, neuron) pairs whose accumulator could overflow an `i16`
+    /// with a full board of the largest weights.
+    pub accumulator_overflows: usize,
+    /// The number of neurons of the hidden layers after L1 whose sum could overflow an `i32`
+    /// with every input saturated.
+    pub hidden_overflows: usize,
+    /// The number of output buckets whose output sum could overflow an `i32`
+    /// with every activation saturated.
+    pub output_overflows: usize,
+}
+
+impl QuantisationStats {
+    /// Check a quantised network for sums that could overflow during inference.
+    pub fn check_overflows(&mut self, net: &NNUEParams) {
+        let i16_range = i32::from(i16::MIN)..=i32::from(i16::MAX);
+        self.accumulator_overflows = l1_sum_bounds(net)
+            .iter()
+            .flatten()
+            .filter(|(lowest, highest)| !i16_range.contains(lowest) || !i16_range.contains(highest))
+            .count();
+
+        let fits = |worst: i64| worst <= i64::from(i32::MAX);
+        let heads = net.output_weights.iter().zip(&net.output_bias).filter(|(weights, &bias)| {
+            // the single output layer takes squared activations, so its inputs reach `QA * QA`.
+            let weights = weights.iter().map(|&w| i64::from(w));
+            !fits(worst_sum(weights, i64::from(bias), i64::from(QA * QA)))
+        });
+        let stacks = net.layer_stacks.iter().filter(|stack| {
+            let weights = stack.output_weights.iter().map(|&w| i64::from(w));
+            !fits(worst_sum(weights, i64::from(stack.output_bias), i64::from(QA)))
+        });
+        self.output_overflows = heads.count() + stacks.count();
+
+        self.hidden_overflows = net
+            .layer_stacks
+            .iter()
+            .flat_map(|stack| &stack.hidden)
+            .flat_map(|layer| layer.weights.chunks_exact(layer.inputs).zip(&layer.bias))
+            .filter(|(row, &bias)| {
+                let weights = row.iter().map(|&w| i64::from(w));
+                // the sum is rounded before it is scaled down, which adds `QH / 2`.
+                !fits(worst_sum(weights, i64::from(bias), i64::from(QA)) + i64::from(QH / 2))
+            })
+            .count();
+    }
+
+    /// Whether quantisation clamped anything, or left anything that could overflow.
+    pub fn has_problems(&self) -> bool {
+        self.accumulator_overflows > 0
+            || self.hidden_overflows > 0
+            || self.output_overflows > 0
+            || self.layers.iter().any(|layer| layer.clipped > 0)
+    }
+}
+
+impl fmt::Display for QuantisationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #![allow(clippy::cast_precision_loss)]
+        writeln!(f, "Quantisation:")?;
+        for layer in &self.layers {
+            writeln!(
+                f,
+                " |> {:<22} {:>9} params, {} clipped ({:.3}%), largest |w| {:.3} (limit {:.3})",
+                layer.name,
+                layer.count,
+                layer.clipped,
+                layer.clipped as f64 / layer.count.max(1) as f64 * 100.0,
+                layer.largest,
+                layer.limit,
+            )?;
+        }
+        writeln!(
+            f,
+            " |> accumulators that could overflow: {} of {} neurons",
+            self.accumulator_overflows,
+            self.layers.first().map_or(0, |l1| l1.count / INPUT),
+        )?;
+        writeln!(f, " |> hidden-layer neurons that could overflow: {}", self.hidden_overflows)?;
+        write!(f, " |> output buckets that could overflow: {}", self.output_overflows)
+    }
+}
+
+/// Quantise a network and compare its evaluations against the float network's
+/// over the positions in a data file, to see what quantisation costs it.
+pub fn accuracy_report(
+    net_path: &Path,
+    positions_path: &Path,
+    format: &dyn DataFormat,
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>> {
+    #![allow(clippy::cast_precision_loss)]
+    let net = FloatNNUEParams::from_json(net_path);
+    let (quantised, stats) = net.quantise_with_stats();
+    println!("{stats}");
+    let quantised: &'static NNUEParams = Box::leak(quantised);
+
+    let start_time = Instant::now();
+    let mut records = Records::open(format, positions_path, policy)?;
+    let mut board = Board::new();
+    let mut nnue = NNUEState::with_params(&board, quantised);
+    let mut comparison = EvalComparison::new("float", "quantised");
+    for record in records.by_ref() {
+        let record = record?;
+        board.set_from_fen(&record.fen)?;
+        nnue.refresh_acc(&board);
+        let quantised_eval = nnue.evaluate(board.turn(), board.pieces.occupied().count_ones());
+        comparison.push(f64::from(net.evaluate(&board)), f64::from(quantised_eval), &record.fen);
+    }
+    records.report_skipped();
+
+    let elapsed = start_time.elapsed();
+    println!(
+        "Compared {} positions in {}.{:03}s",
+        comparison.n,
+        elapsed.as_secs(),
+        elapsed.subsec_millis()
+    );
+    print!("{comparison}");
+    Ok(())
+}
+
+/// Running statistics of the differences between two sets of evaluations.
+pub struct EvalComparison {
+    /// The names of the two sets of evaluations.
+    names: (&'static str, &'static str),
+    pub n: usize,
+    sum_a: f64,
+    sum_b: f64,
+    sum_aa: f64,
+    sum_bb: f64,
+    sum_ab: f64,
+    sum_error: f64,
+    sum_abs_error: f64,
+    /// The largest absolute difference, and the FEN it occurred in.
+    worst: Option<(f64, String)>,
+}
+
+impl EvalComparison {
+    pub const fn new(a: &'static str, b: &'static str) -> Self {
+        Self {
+            names: (a, b),
+            n: 0,
+            sum_a: 0.0,
+            sum_b: 0.0,
+            sum_aa: 0.0,
+            sum_bb: 0.0,
+            sum_ab: 0.0,
+            sum_error: 0.0,
+            sum_abs_error: 0.0,
+            worst: None,
+        }
+    }
+
+    pub fn push(&mut self, a: f64, b: f64, fen: &str) {
+        self.n += 1;
+        self.sum_a += a;
+        self.sum_b += b;
+        self.sum_aa += a * a;
+        self.sum_bb += b * b;
+        self.sum_ab += a * b;
+        self.sum_error += b - a;
+        self.sum_abs_error += (b - a).abs();
+        if self.worst.as_ref().is_none_or(|(worst, _)| (b - a).abs() > *worst) {
+            self.worst = Some(((b - a).abs(), fen.to_string()));
+        }
+    }
+
+    /// The mean of `b - a`.
+    pub fn mean_difference(&self) -> f64 {
+        #![allow(clippy::cast_precision_loss)]
+        self.sum_error / self.n.max(1) as f64
+    }
+
+    /// The mean of `|b - a|`.
+    pub fn mean_absolute_difference(&self) -> f64 {
+        #![allow(clippy::cast_precision_loss)]
+        self.sum_abs_error / self.n.max(1) as f64
+    }
+
+    /// The Pearson correlation coefficient between the two sets of evaluations.
+    pub fn correlation(&self) -> f64 {
+        #![allow(clippy::cast_precision_loss)]
+        let n = self.n as f64;
+        let covariance = self.sum_ab.mul_add(n, -self.sum_a * self.sum_b);
+        let variance_a = self.sum_aa.mul_add(n, -self.sum_a * self.sum_a);
+        let variance_b = self.sum_bb.mul_add(n, -self.sum_b * self.sum_b);
+        covariance / (variance_a * variance_b).sqrt()
+    }
+}
+
+impl fmt::Display for EvalComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (a, b) = self.names;
+        writeln!(f, " |> mean absolute difference: {:.2}cp", self.mean_absolute_difference())?;
+        writeln!(f, " |> mean difference ({b} - {a}): {:+.2}cp", self.mean_difference())?;
+        if let Some((worst, fen)) = &self.worst {
+            writeln!(f, " |> largest absolute difference: {worst:.2}cp, in {fen}")?;
+        }
+        writeln!(f, " |> correlation: {:.6}", self.correlation())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nnue::network::{BucketLayout, NNUEParams, OutputBuckets};
+
+    #[test]
+    fn rounds_and_clips() {
+        let mut stats = LayerStats::new("test");
+        let mut out = [0i8; 5];
+        stats.quantise(&[0.26, -0.26, 0.74, 2.5, -3.0], 100, &mut out);
+        assert_eq!(out, [26, -26, 74, 127, -128]);
+        assert_eq!((stats.count, stats.clipped), (5, 2));
+        assert!((stats.largest - 3.0).abs() < f32::EPSILON);
+        assert!((stats.limit - 1.27).abs() < 1e-6);
+    }
+
+    #[test]
+    fn detects_possible_overflows() {
+        let mut net = NNUEParams::zeroed(BucketLayout::UNBUCKETED, OutputBuckets::SINGLE, &[]);
+        let mut stats = QuantisationStats {
+            layers: Vec::new(),
+            accumulator_overflows: 0,
+            hidden_overflows: 0,
+            output_overflows: 0,
+        };
+        stats.check_overflows(&net);
+        assert!(!stats.has_problems());
+
+        // 32 features of 1100 can reach 35,200, past i16::MAX.
+        for row in &mut net.feature_weights[..32] {
+            row[3] = 1100;
+        }
+        net.output_weights[0].fill(2100);
+        stats.check_overflows(&net);
+        assert_eq!(stats.accumulator_overflows, 1);
+        assert_eq!(stats.hidden_overflows, 0);
+        assert_eq!(stats.output_overflows, 1);
+    }
+
+    #[test]
+    fn detects_possible_overflows_in_layer_stacks() {
+        let mut net = NNUEParams::zeroed(BucketLayout::UNBUCKETED, OutputBuckets::SINGLE, &[16]);
+        let mut stats = QuantisationStats {
+            layers: Vec::new(),
+            accumulator_overflows: 0,
+            hidden_overflows: 0,
+            output_overflows: 0,
+        };
+        stats.check_overflows(&net);
+        assert!(!stats.has_problems());
+
+        let stack = &mut net.layer_stacks[0];
+        stack.hidden[0].bias[2] = i32::MAX - 10;
+        stack.hidden[0].bias[5] = i32::MIN + QH;
+        stack.hidden[0].weights[5 * LAYER_1_SIZE * 2] = -1;
+        stack.output_weights.fill(i16::MAX);
+        stack.output_bias = i32::MAX - 16 * i32::from(i16::MAX) * QA;
+        stats.check_overflows(&net);
+        assert_eq!(stats.hidden_overflows, 2);
+        assert_eq!(stats.output_overflows, 0);
+
+        net.layer_stacks[0].output_bias += 1;
+        stats.check_overflows(&net);
+        assert_eq!(stats.output_overflows, 1);
+        assert!(stats.has_problems());
+    }
+}
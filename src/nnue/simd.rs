@@ -0,0 +1,440 @@
+//! Explicit SIMD kernels for the accumulator updates and the output layer.
+//!
+//! The kernel is chosen once at runtime from the features of the CPU, with a scalar
+//! fallback for machines (or architectures) without any of the supported extensions.
+//! All kernels produce bit-identical results to the scalar code.
+
+use std::sync::LazyLock;
+
+use super::network::{CR_MAX, LAYER_1_SIZE, QA};
+
+/// A family of kernels, by the instruction set extension that it uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    Scalar,
+    Sse41,
+    Avx2,
+    Avx512,
+}
+
+static KERNEL: LazyLock<Kernel> = LazyLock::new(Kernel::detect);
+
+impl Kernel {
+    /// The fastest kernel that the CPU supports.
+    fn detect() -> Self {
+        Self::available().into_iter().last().unwrap()
+    }
+
+    /// All the kernels that the CPU supports, from slowest to fastest.
+    pub fn available() -> Vec<Self> {
+        let mut kernels = vec![Self::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse4.1") {
+                kernels.push(Self::Sse41);
+            }
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(Self::Avx2);
+            }
+            if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") {
+                kernels.push(Self::Avx512);
+            }
+        }
+        kernels
+    }
+
+    /// The kernel in use.
+    pub fn active() -> Self {
+        *KERNEL
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Scalar => "scalar",
+            Self::Sse41 => "SSE4.1",
+            Self::Avx2 => "AVX2",
+            Self::Avx512 => "AVX-512",
+        }
+    }
+}
+
+/// Move a feature from one square to another.
+pub fn subtract_and_add_to_all<const SIZE: usize>(
+    input: &mut [i16; SIZE],
+    s_block: &[i16; SIZE],
+    a_block: &[i16; SIZE],
+) {
+    const { assert!(SIZE.is_multiple_of(32)) };
+    subtract_and_add_with(Kernel::active(), input, s_block, a_block);
+}
+
+/// Add a feature to a square.
+pub fn add_to_all<const SIZE: usize>(input: &mut [i16; SIZE], a_block: &[i16; SIZE]) {
+    const { assert!(SIZE.is_multiple_of(32)) };
+    add_with(Kernel::active(), input, a_block);
+}
+
+/// Subtract a feature from a square.
+pub fn sub_from_all<const SIZE: usize>(input: &mut [i16; SIZE], s_block: &[i16; SIZE]) {
+    const { assert!(SIZE.is_multiple_of(32)) };
+    sub_with(Kernel::active(), input, s_block);
+}
+
+/// Execute squared + clipped relu on the partial activations,
+/// and accumulate the result into a sum.
+pub fn screlu_flatten(
+    us: &[i16; LAYER_1_SIZE],
+    them: &[i16; LAYER_1_SIZE],
+    weights: &[i16; LAYER_1_SIZE * 2],
+) -> i32 {
+    screlu_flatten_with(Kernel::active(), us, them, weights)
+}
+
+// The SIMD kernels work on whole registers, so the sizes that they are
+// used with must be multiples of the widest one (32 lanes of i16).
+const _: () = assert!(LAYER_1_SIZE.is_multiple_of(32));
+
+// The dispatchers below take any slices, for the sake of the tests:
+// the public functions above are responsible for checking their sizes.
+
+fn subtract_and_add_with(kernel: Kernel, input: &mut [i16], s_block: &[i16], a_block: &[i16]) {
+    // SAFETY: the kernel is only used if the CPU supports it,
+    // and the slices are all the same length, a multiple of 32.
+    unsafe {
+        match kernel {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse41 => sse41::subtract_and_add(input, s_block, a_block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => avx2::subtract_and_add(input, s_block, a_block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => avx512::subtract_and_add(input, s_block, a_block),
+            _ => scalar::subtract_and_add(input, s_block, a_block),
+        }
+    }
+}
+
+fn add_with(kernel: Kernel, input: &mut [i16], a_block: &[i16]) {
+    // SAFETY: as above.
+    unsafe {
+        match kernel {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse41 => sse41::add(input, a_block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => avx2::add(input, a_block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => avx512::add(input, a_block),
+            _ => scalar::add(input, a_block),
+        }
+    }
+}
+
+fn sub_with(kernel: Kernel, input: &mut [i16], s_block: &[i16]) {
+    // SAFETY: as above.
+    unsafe {
+        match kernel {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse41 => sse41::sub(input, s_block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => avx2::sub(input, s_block),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => avx512::sub(input, s_block),
+            _ => scalar::sub(input, s_block),
+        }
+    }
+}
+
+fn screlu_flatten_with(
+    kernel: Kernel,
+    us: &[i16; LAYER_1_SIZE],
+    them: &[i16; LAYER_1_SIZE],
+    weights: &[i16; LAYER_1_SIZE * 2],
+) -> i32 {
+    let (us_weights, them_weights) = weights.split_at(LAYER_1_SIZE);
+    // SAFETY: as above.
+    let sum = unsafe {
+        match kernel {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse41 => sse41::screlu_dot(us, us_weights)
+                .wrapping_add(sse41::screlu_dot(them, them_weights)),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => {
+                avx2::screlu_dot(us, us_weights).wrapping_add(avx2::screlu_dot(them, them_weights))
+            }
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => avx512::screlu_dot(us, us_weights)
+                .wrapping_add(avx512::screlu_dot(them, them_weights)),
+            _ => scalar::screlu_dot(us, us_weights)
+                .wrapping_add(scalar::screlu_dot(them, them_weights)),
+        }
+    };
+    sum / QA
+}
+
+mod scalar {
+    use super::super::network::screlu;
+
+    pub fn subtract_and_add(input: &mut [i16], s_block: &[i16], a_block: &[i16]) {
+        for ((i, ds), da) in input.iter_mut().zip(s_block).zip(a_block) {
+            *i = *i - *ds + *da;
+        }
+    }
+
+    pub fn add(input: &mut [i16], a_block: &[i16]) {
+        for (i, d) in input.iter_mut().zip(a_block) {
+            *i += *d;
+        }
+    }
+
+    pub fn sub(input: &mut [i16], s_block: &[i16]) {
+        for (i, d) in input.iter_mut().zip(s_block) {
+            *i -= *d;
+        }
+    }
+
+    pub fn screlu_dot(input: &[i16], weights: &[i16]) -> i32 {
+        let mut sum: i32 = 0;
+        for (&i, &w) in input.iter().zip(weights) {
+            sum = sum.wrapping_add(screlu(i) * i32::from(w));
+        }
+        sum
+    }
+}
+
+// Each SIMD kernel squares the clipped activations and multiplies them by the weights
+// in 32-bit lanes, which is exact for any i16 weight, so the only difference from the
+// scalar code is the order of the (wrapping) additions.
+
+#[cfg(target_arch = "x86_64")]
+mod sse41 {
+    use std::arch::x86_64::{
+        __m128i, _mm_add_epi16, _mm_add_epi32, _mm_cvtepi16_epi32, _mm_cvtsi128_si32,
+        _mm_loadl_epi64, _mm_loadu_si128, _mm_max_epi16, _mm_min_epi16, _mm_mullo_epi32,
+        _mm_set1_epi16, _mm_setzero_si128, _mm_shuffle_epi32, _mm_storeu_si128, _mm_sub_epi16,
+    };
+
+    use super::CR_MAX;
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "sse4.1")]
+    pub unsafe fn subtract_and_add(input: &mut [i16], s_block: &[i16], a_block: &[i16]) {
+        for ((i, s), a) in input
+            .chunks_exact_mut(LANES)
+            .zip(s_block.chunks_exact(LANES))
+            .zip(a_block.chunks_exact(LANES))
+        {
+            let v = _mm_loadu_si128(i.as_ptr().cast());
+            let s = _mm_loadu_si128(s.as_ptr().cast());
+            let a = _mm_loadu_si128(a.as_ptr().cast());
+            _mm_storeu_si128(i.as_mut_ptr().cast(), _mm_add_epi16(_mm_sub_epi16(v, s), a));
+        }
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    pub unsafe fn add(input: &mut [i16], a_block: &[i16]) {
+        for (i, a) in input.chunks_exact_mut(LANES).zip(a_block.chunks_exact(LANES)) {
+            let v = _mm_loadu_si128(i.as_ptr().cast());
+            let a = _mm_loadu_si128(a.as_ptr().cast());
+            _mm_storeu_si128(i.as_mut_ptr().cast(), _mm_add_epi16(v, a));
+        }
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    pub unsafe fn sub(input: &mut [i16], s_block: &[i16]) {
+        for (i, s) in input.chunks_exact_mut(LANES).zip(s_block.chunks_exact(LANES)) {
+            let v = _mm_loadu_si128(i.as_ptr().cast());
+            let s = _mm_loadu_si128(s.as_ptr().cast());
+            _mm_storeu_si128(i.as_mut_ptr().cast(), _mm_sub_epi16(v, s));
+        }
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    pub unsafe fn screlu_dot(input: &[i16], weights: &[i16]) -> i32 {
+        const HALF: usize = LANES / 2;
+        let min = _mm_setzero_si128();
+        let max = _mm_set1_epi16(CR_MAX);
+        let mut sum = _mm_setzero_si128();
+        for (i, w) in input.chunks_exact(HALF).zip(weights.chunks_exact(HALF)) {
+            let v = _mm_loadl_epi64(i.as_ptr().cast());
+            let v = _mm_cvtepi16_epi32(_mm_min_epi16(_mm_max_epi16(v, min), max));
+            let w = _mm_cvtepi16_epi32(_mm_loadl_epi64(w.as_ptr().cast()));
+            sum = _mm_add_epi32(sum, _mm_mullo_epi32(_mm_mullo_epi32(v, v), w));
+        }
+        hsum(sum)
+    }
+
+    /// Sum the four lanes of a vector.
+    #[target_feature(enable = "sse4.1")]
+    pub unsafe fn hsum(v: __m128i) -> i32 {
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b01_00_11_10>(v));
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b10_11_00_01>(v));
+        _mm_cvtsi128_si32(v)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::{
+        _mm256_add_epi16, _mm256_add_epi32, _mm256_castsi256_si128, _mm256_cvtepi16_epi32,
+        _mm256_extracti128_si256, _mm256_loadu_si256, _mm256_mullo_epi32, _mm256_setzero_si256,
+        _mm256_storeu_si256, _mm256_sub_epi16, _mm_add_epi32, _mm_loadu_si128, _mm_max_epi16,
+        _mm_min_epi16, _mm_set1_epi16, _mm_setzero_si128,
+    };
+
+    use super::CR_MAX;
+
+    const LANES: usize = 16;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn subtract_and_add(input: &mut [i16], s_block: &[i16], a_block: &[i16]) {
+        for ((i, s), a) in input
+            .chunks_exact_mut(LANES)
+            .zip(s_block.chunks_exact(LANES))
+            .zip(a_block.chunks_exact(LANES))
+        {
+            let v = _mm256_loadu_si256(i.as_ptr().cast());
+            let s = _mm256_loadu_si256(s.as_ptr().cast());
+            let a = _mm256_loadu_si256(a.as_ptr().cast());
+            let v = _mm256_add_epi16(_mm256_sub_epi16(v, s), a);
+            _mm256_storeu_si256(i.as_mut_ptr().cast(), v);
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn add(input: &mut [i16], a_block: &[i16]) {
+        for (i, a) in input.chunks_exact_mut(LANES).zip(a_block.chunks_exact(LANES)) {
+            let v = _mm256_loadu_si256(i.as_ptr().cast());
+            let a = _mm256_loadu_si256(a.as_ptr().cast());
+            _mm256_storeu_si256(i.as_mut_ptr().cast(), _mm256_add_epi16(v, a));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sub(input: &mut [i16], s_block: &[i16]) {
+        for (i, s) in input.chunks_exact_mut(LANES).zip(s_block.chunks_exact(LANES)) {
+            let v = _mm256_loadu_si256(i.as_ptr().cast());
+            let s = _mm256_loadu_si256(s.as_ptr().cast());
+            _mm256_storeu_si256(i.as_mut_ptr().cast(), _mm256_sub_epi16(v, s));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn screlu_dot(input: &[i16], weights: &[i16]) -> i32 {
+        const HALF: usize = LANES / 2;
+        let min = _mm_setzero_si128();
+        let max = _mm_set1_epi16(CR_MAX);
+        let mut sum = _mm256_setzero_si256();
+        for (i, w) in input.chunks_exact(HALF).zip(weights.chunks_exact(HALF)) {
+            let v = _mm_loadu_si128(i.as_ptr().cast());
+            let v = _mm256_cvtepi16_epi32(_mm_min_epi16(_mm_max_epi16(v, min), max));
+            let w = _mm256_cvtepi16_epi32(_mm_loadu_si128(w.as_ptr().cast()));
+            sum = _mm256_add_epi32(sum, _mm256_mullo_epi32(_mm256_mullo_epi32(v, v), w));
+        }
+        let sum = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256::<1>(sum));
+        super::sse41::hsum(sum)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use std::arch::x86_64::{
+        _mm256_loadu_si256, _mm256_max_epi16, _mm256_min_epi16, _mm256_set1_epi16,
+        _mm256_setzero_si256, _mm512_add_epi16, _mm512_add_epi32, _mm512_cvtepi16_epi32,
+        _mm512_loadu_si512, _mm512_mullo_epi32, _mm512_reduce_add_epi32, _mm512_setzero_si512,
+        _mm512_storeu_si512, _mm512_sub_epi16,
+    };
+
+    use super::CR_MAX;
+
+    const LANES: usize = 32;
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    pub unsafe fn subtract_and_add(input: &mut [i16], s_block: &[i16], a_block: &[i16]) {
+        for ((i, s), a) in input
+            .chunks_exact_mut(LANES)
+            .zip(s_block.chunks_exact(LANES))
+            .zip(a_block.chunks_exact(LANES))
+        {
+            let v = _mm512_loadu_si512(i.as_ptr().cast());
+            let s = _mm512_loadu_si512(s.as_ptr().cast());
+            let a = _mm512_loadu_si512(a.as_ptr().cast());
+            let v = _mm512_add_epi16(_mm512_sub_epi16(v, s), a);
+            _mm512_storeu_si512(i.as_mut_ptr().cast(), v);
+        }
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    pub unsafe fn add(input: &mut [i16], a_block: &[i16]) {
+        for (i, a) in input.chunks_exact_mut(LANES).zip(a_block.chunks_exact(LANES)) {
+            let v = _mm512_loadu_si512(i.as_ptr().cast());
+            let a = _mm512_loadu_si512(a.as_ptr().cast());
+            _mm512_storeu_si512(i.as_mut_ptr().cast(), _mm512_add_epi16(v, a));
+        }
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    pub unsafe fn sub(input: &mut [i16], s_block: &[i16]) {
+        for (i, s) in input.chunks_exact_mut(LANES).zip(s_block.chunks_exact(LANES)) {
+            let v = _mm512_loadu_si512(i.as_ptr().cast());
+            let s = _mm512_loadu_si512(s.as_ptr().cast());
+            _mm512_storeu_si512(i.as_mut_ptr().cast(), _mm512_sub_epi16(v, s));
+        }
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    pub unsafe fn screlu_dot(input: &[i16], weights: &[i16]) -> i32 {
+        const HALF: usize = LANES / 2;
+        let min = _mm256_setzero_si256();
+        let max = _mm256_set1_epi16(CR_MAX);
+        let mut sum = _mm512_setzero_si512();
+        for (i, w) in input.chunks_exact(HALF).zip(weights.chunks_exact(HALF)) {
+            let v = _mm256_loadu_si256(i.as_ptr().cast());
+            let v = _mm512_cvtepi16_epi32(_mm256_min_epi16(_mm256_max_epi16(v, min), max));
+            let w = _mm512_cvtepi16_epi32(_mm256_loadu_si256(w.as_ptr().cast()));
+            sum = _mm512_add_epi32(sum, _mm512_mullo_epi32(_mm512_mullo_epi32(v, v), w));
+        }
+        _mm512_reduce_add_epi32(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn random_block(rng: &mut StdRng, range: std::ops::Range<i16>) -> [i16; LAYER_1_SIZE] {
+        std::array::from_fn(|_| rng.gen_range(range.clone()))
+    }
+
+    #[test]
+    fn kernels_match_scalar() {
+        let mut rng = StdRng::seed_from_u64(44);
+        let kernels = Kernel::available();
+        for _ in 0..100 {
+            let input = random_block(&mut rng, -2000..2000);
+            let a = random_block(&mut rng, -500..500);
+            let s = random_block(&mut rng, -500..500);
+            let them = random_block(&mut rng, -100..400);
+            let mut weights: [i16; LAYER_1_SIZE * 2] =
+                std::array::from_fn(|_| rng.gen_range(-i16::MAX..i16::MAX));
+            weights[..4].copy_from_slice(&[i16::MIN, i16::MAX, -1, 1]);
+
+            let mut expected = [input; 3];
+            super::subtract_and_add_with(Kernel::Scalar, &mut expected[0], &s, &a);
+            super::add_with(Kernel::Scalar, &mut expected[1], &a);
+            super::sub_with(Kernel::Scalar, &mut expected[2], &s);
+            let expected_sum = super::screlu_flatten_with(Kernel::Scalar, &input, &them, &weights);
+
+            for &kernel in &kernels {
+                let mut output = [input; 3];
+                super::subtract_and_add_with(kernel, &mut output[0], &s, &a);
+                super::add_with(kernel, &mut output[1], &a);
+                super::sub_with(kernel, &mut output[2], &s);
+                assert_eq!(output, expected, "{kernel:?}");
+                let sum = super::screlu_flatten_with(kernel, &input, &them, &weights);
+                assert_eq!(sum, expected_sum, "{kernel:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,600 @@
+//! A trainer for the NNUE.
+//!
+//! This trains the plain perspective network (768 -> 2x512 -> 1, with `SCReLU` on the
+//! accumulators) in floating point on marlinflow-format text data, spreading each batch
+//! across CPU threads and updating the weights with Adam.
+//!
+//! Checkpoints are written in the JSON format read by [`FloatNNUEParams::from_json`], so
+//! they can be converted with `--jsontobin` as well as resumed from, and the final network
+//! is quantised and written out as the binary files that the engine embeds.
+
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde_json::{json, Value};
+
+use crate::{
+    board::{movegen::BitLoop, Board},
+    piece::{Colour, Piece, PieceType},
+};
+
+use super::{
+    convert::{InvalidDataPolicy, Marlinflow, Records},
+    float::FloatNNUEParams,
+    network::{self, feature_indices, BucketLayout, OutputBuckets, INPUT, LAYER_1_SIZE, SCALE},
+};
+
+/// Weights are clipped to this magnitude after every step, as marlinflow does,
+/// so that every weight fits its integer type once quantised: the tightest is the int8
+/// of the hidden layers, where 1.98 × `QH` rounds to 127. This doesn't bound the sums
+/// that the weights feed into, which the quantisation report checks for overflows.
+const WEIGHT_CLIP: f32 = 1.98;
+
+const BETA_1: f32 = 0.9;
+const BETA_2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+
+/// Settings for a training run.
+pub struct TrainConfig {
+    /// Training data, in marlinflow's text format.
+    pub data: PathBuf,
+    /// Directory to write checkpoints and the final network to.
+    pub output: PathBuf,
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// The initial learning rate.
+    pub lr: f32,
+    /// The number of epochs between drops in the learning rate.
+    pub lr_step: usize,
+    /// The factor that the learning rate is multiplied by at each drop.
+    pub lr_gamma: f32,
+    /// The weight given to the game result in the target, with the rest given to the eval.
+    pub wdl: f32,
+    /// The scale, in centipawns, of the sigmoid that maps evals to expected scores.
+    pub scale: f32,
+    /// The fraction of the data held out to compute the validation loss.
+    pub validation: f32,
+    pub threads: usize,
+    /// A checkpoint directory to continue training from.
+    pub resume: Option<PathBuf>,
+    pub seed: u64,
+}
+
+/// Training positions, stored compactly.
+///
+/// The features of each position (as seen from white's side) are a run of `features`,
+/// from `starts[i]` to `starts[i + 1]`.
+struct Dataset {
+    features: Vec<u16>,
+    starts: Vec<usize>,
+    white_to_move: Vec<bool>,
+    /// Targets, as expected scores from white's point of view.
+    targets: Vec<f32>,
+}
+
+impl Dataset {
+    fn new() -> Self {
+        Self {
+            features: Vec::new(),
+            starts: vec![0],
+            white_to_move: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+
+    const fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    fn push(&mut self, board: &Board, target: f32) {
+        for colour in [Colour::WHITE, Colour::BLACK] {
+            for piece_type in PieceType::all() {
+                let piece_bb = board.pieces.piece_bb(Piece::new(colour, piece_type));
+                for sq in BitLoop::new(piece_bb) {
+                    let (white_idx, _) = feature_indices(sq, piece_type, colour);
+                    self.features.push(u16::try_from(white_idx).unwrap());
+                }
+            }
+        }
+        self.starts.push(self.features.len());
+        self.white_to_move.push(board.turn() == Colour::WHITE);
+        self.targets.push(target);
+    }
+
+    /// The features of a position from white's point of view, whether white is to move,
+    /// and the target from the side to move's point of view.
+    fn position(&self, idx: usize) -> (&[u16], bool, f32) {
+        let features = &self.features[self.starts[idx]..self.starts[idx + 1]];
+        let white_to_move = self.white_to_move[idx];
+        let target = if white_to_move { self.targets[idx] } else { 1.0 - self.targets[idx] };
+        (features, white_to_move, target)
+    }
+}
+
+/// A feature (stored from white's point of view) from the side to move's
+/// and the other side's points of view.
+const fn perspectives(feature: u16, white_to_move: bool) -> [usize; 2] {
+    let white = feature as usize;
+    // flipping the colour and the rank takes a feature to black's point of view.
+    let black = ((white + INPUT / 2) % INPUT) ^ 0b111_000;
+    if white_to_move {
+        [white, black]
+    } else {
+        [black, white]
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// A network for this architecture with every parameter set to zero,
+/// used to hold gradients and optimiser state.
+fn zeroed() -> FloatNNUEParams {
+    FloatNNUEParams {
+        buckets: BucketLayout::UNBUCKETED,
+        output_buckets: OutputBuckets::SINGLE,
+        feature_weights: vec![0.0; INPUT * LAYER_1_SIZE],
+        feature_bias: vec![0.0; LAYER_1_SIZE],
+        hidden: Vec::new(),
+        output_weights: vec![0.0; LAYER_1_SIZE * 2],
+        output_bias: vec![0.0],
+    }
+}
+
+/// A randomly initialised network, drawing each layer's weights uniformly from
+/// `±1/sqrt(inputs)`, the usual default for linear layers.
+fn initialise(rng: &mut StdRng) -> FloatNNUEParams {
+    #![allow(clippy::cast_precision_loss)]
+    let mut params = zeroed();
+    let feature_bound = 1.0 / (INPUT as f32).sqrt();
+    let output_bound = 1.0 / ((LAYER_1_SIZE * 2) as f32).sqrt();
+    for w in params.feature_weights.iter_mut().chain(&mut params.feature_bias) {
+        *w = rng.gen_range(-feature_bound..feature_bound);
+    }
+    for w in params.output_weights.iter_mut().chain(&mut params.output_bias) {
+        *w = rng.gen_range(-output_bound..output_bound);
+    }
+    params
+}
+
+/// Every parameter tensor of a network, in a fixed order.
+fn tensors(params: &FloatNNUEParams) -> [&[f32]; 4] {
+    [&params.feature_weights, &params.feature_bias, &params.output_weights, &params.output_bias]
+}
+
+/// Every parameter tensor of a network, in the same order as [`tensors`].
+fn tensors_mut(params: &mut FloatNNUEParams) -> [&mut [f32]; 4] {
+    [
+        &mut params.feature_weights,
+        &mut params.feature_bias,
+        &mut params.output_weights,
+        &mut params.output_bias,
+    ]
+}
+
+/// The accumulators of a position, and the activations computed from them.
+struct Forward {
+    /// Accumulators from the side to move's and the other side's points of view.
+    accumulators: [[f32; LAYER_1_SIZE]; 2],
+    /// `SCReLU` of the accumulators, in the same order.
+    activations: [[f32; LAYER_1_SIZE]; 2],
+}
+
+impl Forward {
+    fn new() -> Box<Self> {
+        Box::new(Self {
+            accumulators: [[0.0; LAYER_1_SIZE]; 2],
+            activations: [[0.0; LAYER_1_SIZE]; 2],
+        })
+    }
+
+    /// Run the network on a position, returning the raw output, in units of `SCALE` centipawns.
+    fn run(&mut self, params: &FloatNNUEParams, features: &[u16], white_to_move: bool) -> f32 {
+        let [us, them] = &mut self.accumulators;
+        us.copy_from_slice(&params.feature_bias);
+        them.copy_from_slice(&params.feature_bias);
+        for &feature in features {
+            let rows = perspectives(feature, white_to_move)
+                .map(|f| &params.feature_weights[f * LAYER_1_SIZE..][..LAYER_1_SIZE]);
+            us.iter_mut().zip(rows[0]).for_each(|(a, w)| *a += w);
+            them.iter_mut().zip(rows[1]).for_each(|(a, w)| *a += w);
+        }
+
+        let mut output = params.output_bias[0];
+        for ((acc, act), weights) in self
+            .accumulators
+            .iter()
+            .zip(&mut self.activations)
+            .zip(params.output_weights.chunks(LAYER_1_SIZE))
+        {
+            for ((&a, h), &w) in acc.iter().zip(act.iter_mut()).zip(weights) {
+                *h = a.clamp(0.0, 1.0).powi(2);
+                output += *h * w;
+            }
+        }
+        output
+    }
+}
+
+/// The loss of the network on a position, accumulating its gradient into `grads` if given.
+fn position_loss(
+    params: &FloatNNUEParams,
+    data: &Dataset,
+    idx: usize,
+    scale: f32,
+    forward: &mut Forward,
+    grads: Option<&mut FloatNNUEParams>,
+) -> f32 {
+    #![allow(clippy::cast_precision_loss)]
+    let (features, white_to_move, target) = data.position(idx);
+    let output = forward.run(params, features, white_to_move);
+    let k = SCALE as f32 / scale;
+    let prediction = sigmoid(output * k);
+    let error = prediction - target;
+
+    let Some(grads) = grads else {
+        return error * error;
+    };
+
+    // d(loss)/d(output), through the square and the sigmoid.
+    let g = 2.0 * error * prediction * (1.0 - prediction) * k;
+    grads.output_bias[0] += g;
+    let mut deltas = [[0.0; LAYER_1_SIZE]; 2];
+    for (side, delta) in deltas.iter_mut().enumerate() {
+        let weights = &params.output_weights[side * LAYER_1_SIZE..][..LAYER_1_SIZE];
+        let output_grads = &mut grads.output_weights[side * LAYER_1_SIZE..][..LAYER_1_SIZE];
+        for i in 0..LAYER_1_SIZE {
+            output_grads[i] += g * forward.activations[side][i];
+            let a = forward.accumulators[side][i];
+            // the derivative of clamp(a, 0, 1)^2 is 2a inside the clamp, and zero outside.
+            if a > 0.0 && a < 1.0 {
+                delta[i] = g * weights[i] * 2.0 * a;
+            }
+        }
+    }
+    for (b, (us, them)) in grads.feature_bias.iter_mut().zip(deltas[0].iter().zip(&deltas[1])) {
+        *b += us + them;
+    }
+    for &feature in features {
+        for (f, delta) in perspectives(feature, white_to_move).into_iter().zip(&deltas) {
+            let row = &mut grads.feature_weights[f * LAYER_1_SIZE..][..LAYER_1_SIZE];
+            row.iter_mut().zip(delta).for_each(|(w, d)| *w += d);
+        }
+    }
+
+    error * error
+}
+
+/// The summed loss over some positions, and the summed gradient in `grads[0]`,
+/// splitting the positions between one thread for each entry of `grads`.
+fn batch_gradients(
+    params: &FloatNNUEParams,
+    data: &Dataset,
+    batch: &[usize],
+    scale: f32,
+    grads: &mut [FloatNNUEParams],
+) -> f32 {
+    let chunk_size = batch.len().div_ceil(grads.len()).max(1);
+    let loss = std::thread::scope(|s| {
+        let handles = batch
+            .chunks(chunk_size)
+            .zip(grads.iter_mut())
+            .map(|(chunk, grads)| {
+                s.spawn(move || {
+                    for tensor in tensors_mut(grads) {
+                        tensor.fill(0.0);
+                    }
+                    let mut forward = Forward::new();
+                    chunk
+                        .iter()
+                        .map(|&idx| {
+                            position_loss(params, data, idx, scale, &mut forward, Some(grads))
+                        })
+                        .sum::<f32>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut loss = 0.0;
+        for handle in handles {
+            loss += handle.join().unwrap();
+        }
+        loss
+    });
+
+    let used = batch.chunks(chunk_size).len();
+    let (total, rest) = grads.split_first_mut().unwrap();
+    for other in &rest[..used - 1] {
+        for (t, o) in tensors_mut(total).into_iter().zip(tensors(other)) {
+            t.iter_mut().zip(o).for_each(|(t, o)| *t += o);
+        }
+    }
+    loss
+}
+
+/// The mean loss over some positions, split across `threads` threads.
+fn mean_loss(
+    params: &FloatNNUEParams,
+    data: &Dataset,
+    indices: &[usize],
+    scale: f32,
+    threads: usize,
+) -> f32 {
+    #![allow(clippy::cast_precision_loss)]
+    if indices.is_empty() {
+        return 0.0;
+    }
+    let chunk_size = indices.len().div_ceil(threads);
+    let loss: f32 = std::thread::scope(|s| {
+        let handles = indices
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut forward = Forward::new();
+                    chunk
+                        .iter()
+                        .map(|&idx| position_loss(params, data, idx, scale, &mut forward, None))
+                        .sum::<f32>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut loss = 0.0;
+        for handle in handles {
+            loss += handle.join().unwrap();
+        }
+        loss
+    });
+    loss / indices.len() as f32
+}
+
+/// The Adam optimiser, holding running averages of the gradient and its square.
+struct Adam {
+    momentum: FloatNNUEParams,
+    velocity: FloatNNUEParams,
+    /// The number of steps taken.
+    steps: i32,
+}
+
+impl Adam {
+    fn new() -> Self {
+        Self { momentum: zeroed(), velocity: zeroed(), steps: 0 }
+    }
+
+    /// Update `params` from the gradient, which should be the mean over the batch.
+    fn step(&mut self, params: &mut FloatNNUEParams, grads: &FloatNNUEParams, lr: f32) {
+        self.steps += 1;
+        let m_correction = 1.0 - BETA_1.powi(self.steps);
+        let v_correction = 1.0 - BETA_2.powi(self.steps);
+        let tensors = tensors_mut(params)
+            .into_iter()
+            .zip(tensors(grads))
+            .zip(tensors_mut(&mut self.momentum).into_iter().zip(tensors_mut(&mut self.velocity)));
+        for ((params, grads), (momentum, velocity)) in tensors {
+            for (((p, &g), m), v) in params.iter_mut().zip(grads).zip(momentum).zip(velocity) {
+                *m = BETA_1.mul_add(*m, (1.0 - BETA_1) * g);
+                *v = BETA_2.mul_add(*v, (1.0 - BETA_2) * g * g);
+                let m_hat = *m / m_correction;
+                let v_hat = *v / v_correction;
+                *p = (*p - lr * m_hat / (v_hat.sqrt() + EPSILON)).clamp(-WEIGHT_CLIP, WEIGHT_CLIP);
+            }
+        }
+    }
+}
+
+/// Read a data file, computing each position's target from its eval and result.
+fn read_data(config: &TrainConfig, policy: InvalidDataPolicy) -> Result<Dataset, Box<dyn Error>> {
+    #![allow(clippy::cast_precision_loss)]
+    let mut records = Records::open(&Marlinflow, &config.data, policy)?;
+    let mut data = Dataset::new();
+    let mut board = Board::new();
+    for record in records.by_ref() {
+        let record = record?;
+        board.set_from_fen(&record.fen)?;
+        let eval = record.eval.unwrap_or_default() as f32;
+        let target =
+            config.wdl.mul_add(record.wdl, (1.0 - config.wdl) * sigmoid(eval / config.scale));
+        data.push(&board, target);
+    }
+    records.report_skipped();
+    Ok(data)
+}
+
+/// Write a JSON value to a file.
+fn write_json(path: &Path, value: &Value) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the network and optimiser state to a checkpoint directory.
+fn save_checkpoint(
+    dir: &Path,
+    params: &FloatNNUEParams,
+    adam: &Adam,
+    epoch: usize,
+    lr: f32,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    write_json(&dir.join("net.json"), &params.to_json())?;
+    write_json(&dir.join("momentum.json"), &adam.momentum.to_json())?;
+    write_json(&dir.join("velocity.json"), &adam.velocity.to_json())?;
+    write_json(
+        &dir.join("progress.json"),
+        &json!({ "epoch": epoch, "steps": adam.steps, "lr": lr }),
+    )
+}
+
+/// Read a checkpoint written by [`save_checkpoint`], returning the network, the optimiser,
+/// the number of epochs completed, and the learning rate.
+fn load_checkpoint(dir: &Path) -> Result<(FloatNNUEParams, Adam, usize, f32), Box<dyn Error>> {
+    #![allow(clippy::cast_possible_truncation)]
+    let params = FloatNNUEParams::from_json(dir.join("net.json"));
+    let adam = Adam {
+        momentum: FloatNNUEParams::from_json(dir.join("momentum.json")),
+        velocity: FloatNNUEParams::from_json(dir.join("velocity.json")),
+        steps: 0,
+    };
+    let expected = zeroed();
+    for net in [&params, &adam.momentum, &adam.velocity] {
+        if net.buckets != expected.buckets
+            || net.output_buckets != expected.output_buckets
+            || !net.hidden.is_empty()
+        {
+            return Err("only plain 768 -> 2x512 -> 1 networks can be trained".into());
+        }
+    }
+    let progress: Value = serde_json::from_str(&fs::read_to_string(dir.join("progress.json"))?)?;
+    let missing = |name: &str| format!("progress.json is missing \"{name}\"");
+    let count = |name: &str| progress[name].as_u64().ok_or_else(|| missing(name));
+    let adam = Adam { steps: i32::try_from(count("steps")?)?, ..adam };
+    let epoch = usize::try_from(count("epoch")?)?;
+    let lr = progress["lr"].as_f64().ok_or_else(|| missing("lr"))?;
+    Ok((params, adam, epoch, lr as f32))
+}
+
+/// Train a network, as described by `config`.
+pub fn train(config: &TrainConfig, policy: InvalidDataPolicy) -> Result<(), Box<dyn Error>> {
+    #![allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    println!("Loading training data...");
+    let start_time = Instant::now();
+    let data = read_data(config, policy)?;
+    println!("Loaded {} positions in {:.1}s", data.len(), start_time.elapsed().as_secs_f32());
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut indices = (0..data.len()).collect::<Vec<_>>();
+    indices.shuffle(&mut rng);
+    let n_validation = (data.len() as f32 * config.validation) as usize;
+    let (validation, training) = indices.split_at_mut(n_validation);
+    if training.is_empty() {
+        return Err("no positions are left to train on".into());
+    }
+    println!("Training on {} positions, validating on {}", training.len(), validation.len());
+
+    let (mut params, mut adam, first_epoch, mut lr) = match &config.resume {
+        Some(dir) => {
+            let (params, adam, epoch, lr) = load_checkpoint(dir)?;
+            println!("Resuming from {} after epoch {epoch}", dir.display());
+            (params, adam, epoch, lr)
+        }
+        None => (initialise(&mut rng), Adam::new(), 0, config.lr),
+    };
+    let mut grads = (0..config.threads.max(1)).map(|_| zeroed()).collect::<Vec<_>>();
+
+    fs::create_dir_all(&config.output)?;
+    for epoch in first_epoch + 1..=config.epochs {
+        let start_time = Instant::now();
+        training.shuffle(&mut rng);
+        let mut total_loss = 0.0;
+        let n_batches = training.len().div_ceil(config.batch_size);
+        for (i, batch) in training.chunks(config.batch_size).enumerate() {
+            total_loss += batch_gradients(&params, &data, batch, config.scale, &mut grads);
+            let norm = 1.0 / batch.len() as f32;
+            tensors_mut(&mut grads[0]).into_iter().flatten().for_each(|g| *g *= norm);
+            adam.step(&mut params, &grads[0], lr);
+            if i % 16 == 0 || i + 1 == n_batches {
+                print!("\repoch {epoch}: batch {}/{n_batches}", i + 1);
+                std::io::stdout().flush()?;
+            }
+        }
+        let train_loss = total_loss / training.len() as f32;
+        let validation_loss = mean_loss(&params, &data, validation, config.scale, grads.len());
+        let elapsed = start_time.elapsed().as_secs_f32();
+        println!(
+            "\repoch {epoch}: train loss {train_loss:.6}, validation loss {validation_loss:.6}, lr {lr:.1e}, {:.0} pos/s",
+            training.len() as f32 / elapsed
+        );
+
+        if config.lr_step > 0 && epoch % config.lr_step == 0 {
+            lr *= config.lr_gamma;
+        }
+        let checkpoint = config.output.join("checkpoints").join(format!("epoch{epoch:0>3}"));
+        save_checkpoint(&checkpoint, &params, &adam, epoch, lr)?;
+    }
+
+    write_json(&config.output.join("net.json"), &params.to_json())?;
+    let (quantised, stats) = params.quantise_with_stats();
+    println!("{stats}");
+    if stats.has_problems() {
+        eprintln!("warning: quantisation clipped parameters or left sums that could overflow");
+    }
+    network::write_net_files(&quantised, &config.output)?;
+    println!("Wrote the final network to {}", config.output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn dataset() -> Dataset {
+        let mut data = Dataset::new();
+        let mut board = crate::board::Board::new();
+        for (fen, target) in [
+            ("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1", 0.55),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 0.6),
+            ("8/8/4k3/8/8/4K3/4P3/8 w - - 3 50", 0.9),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1", 0.2),
+        ] {
+            board.set_from_fen(fen).unwrap();
+            data.push(&board, target);
+        }
+        data
+    }
+
+    fn total_loss(params: &FloatNNUEParams, data: &Dataset) -> f32 {
+        let mut forward = Forward::new();
+        (0..data.len()).map(|i| position_loss(params, data, i, 400.0, &mut forward, None)).sum()
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        crate::magic::initialise();
+        let data = dataset();
+        let params = super::initialise(&mut StdRng::seed_from_u64(47));
+        let mut grads = vec![zeroed(), zeroed()];
+        let batch = (0..data.len()).collect::<Vec<_>>();
+        batch_gradients(&params, &data, &batch, 400.0, &mut grads);
+
+        // probe a few parameters of every tensor, including weights of features that are present.
+        let (features, _, _) = data.position(1);
+        let feature = usize::from(features[3]) * super::LAYER_1_SIZE + 7;
+        for (tensor, idx) in [(0, feature), (0, feature + 100), (1, 11), (2, 5), (2, 600), (3, 0)] {
+            let h = 1e-2;
+            let mut plus = params.clone();
+            super::tensors_mut(&mut plus)[tensor][idx] += h;
+            let mut minus = params.clone();
+            super::tensors_mut(&mut minus)[tensor][idx] -= h;
+            let numerical = (total_loss(&plus, &data) - total_loss(&minus, &data)) / (2.0 * h);
+            let analytic = tensors(&grads[0])[tensor][idx];
+            assert!(
+                (numerical - analytic).abs() <= 0.05f32.mul_add(analytic.abs(), 1e-3),
+                "tensor {tensor}, index {idx}: numerical {numerical}, analytic {analytic}"
+            );
+        }
+    }
+
+    #[test]
+    fn training_reduces_loss() {
+        crate::magic::initialise();
+        let data = dataset();
+        let mut params = super::initialise(&mut StdRng::seed_from_u64(47));
+        let mut grads = vec![zeroed(), zeroed()];
+        let mut adam = Adam::new();
+        let batch = (0..data.len()).collect::<Vec<_>>();
+        let initial = total_loss(&params, &data);
+        for _ in 0..50 {
+            batch_gradients(&params, &data, &batch, 400.0, &mut grads);
+            adam.step(&mut params, &grads[0], 1e-3);
+        }
+        let trained = total_loss(&params, &data);
+        assert!(trained < initial / 4.0, "loss went from {initial} to {trained}");
+    }
+}
@@ -180,6 +180,47 @@ mod tests {
         // assert_eq!(nnue_perft(&mut pos, &mut t, 4), 197_281);
     }
 
+    #[test]
+    fn perft_chess960() {
+        use super::*;
+        crate::magic::initialise();
+        let mut pos = Board::new();
+        for (fen, nodes) in [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12_189],
+            ),
+            ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", [21, 807, 18_002]),
+            ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", [20, 479, 10_471]),
+            ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", [22, 593, 13_440]),
+            (
+                "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+                [28, 1_120, 31_058],
+            ),
+        ] {
+            pos.set_from_fen(fen).unwrap();
+            for (depth, nodes) in (1..).zip(nodes) {
+                assert_eq!(perft(&mut pos, depth), nodes, "{fen} at depth {depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn perft_incremental_chess960() {
+        use super::*;
+        // the king castles kingside without moving, and queenside across the whole back rank.
+        const TEST_FEN: &str = "r3k2r/8/8/8/8/8/8/R5KR w KQkq - 0 1";
+        crate::magic::initialise();
+        let stopped = AtomicBool::new(false);
+        let info = SearchInfo::new(&stopped);
+        let mut pos = Board::from_fen(TEST_FEN).unwrap();
+        let nodes = perft(&mut pos, 3);
+        pos.refresh_psqt(&info);
+        assert_eq!(hce_perft(&mut pos, &info, 3), nodes);
+        let mut t = ThreadData::new(0, &pos);
+        assert_eq!(nnue_perft(&mut pos, &mut t, 3), nodes);
+    }
+
     #[test]
     fn perft_krk() {
         use super::*;
@@ -1,6 +1,6 @@
 pub mod tables;
 
-use crate::{board::evaluation::score::S, definitions::Square, piece::Piece};
+use crate::{board::evaluation::score::S, definitions::Square, image::BoardGrid, piece::Piece};
 
 pub type PieceSquareTable = [[S; 64]; 13];
 
@@ -37,6 +37,29 @@ pub fn render_pst_table(pst: &PieceSquareTable) {
     }
 }
 
+/// The tables of the white pieces as heatmaps, with a row each for the midgame and endgame.
+/// (black's tables are the same, mirrored and negated)
+pub fn pst_grid(pst: &PieceSquareTable) -> BoardGrid {
+    #![allow(clippy::cast_precision_loss)]
+    let mut grid = BoardGrid::new(["P", "N", "B", "R", "Q", "K"]);
+    for (label, phase) in [("MG", 0), ("EG", 1)] {
+        let boards = Piece::all()
+            .take(6)
+            .map(|piece| {
+                let table = &pst[piece.index()];
+                std::array::from_fn(|sq| {
+                    // pawns on the first and last rank don't exist.
+                    let back_rank = piece == Piece::WP && !(8..56).contains(&sq);
+                    let S(mg, eg) = table[sq];
+                    (!back_rank).then_some(if phase == 0 { mg } else { eg } as f32)
+                })
+            })
+            .collect();
+        grid.add_row(label, boards);
+    }
+    grid
+}
+
 mod tests {
     #[test]
     fn psts_are_mirrored_properly() {
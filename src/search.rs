@@ -109,7 +109,7 @@ impl Board {
             TB_HITS.store(1, Ordering::SeqCst);
             self.readout_info(Bound::Exact, &pv, 0, info, tt, 1);
             if info.print_to_stdout {
-                println!("bestmove {best_move}");
+                println!("bestmove {}", self.uci_move(best_move));
             }
             return (score, best_move);
         }
@@ -151,7 +151,7 @@ impl Board {
             self.select_best(thread_headers, info, tt, total_nodes.load(Ordering::SeqCst), d_move);
 
         if info.print_to_stdout {
-            println!("bestmove {bestmove}");
+            println!("bestmove {}", self.uci_move(bestmove));
             #[cfg(feature = "stats")]
             info.print_stats();
             #[cfg(feature = "stats")]
@@ -205,6 +205,14 @@ impl Board {
                 info.stopped.store(true, Ordering::SeqCst);
                 break 'deepening;
             }
+            // with a soft node limit, stop after the first depth completed past the limit:
+            if MAIN_THREAD
+                && d > starting_depth
+                && info.time_manager.is_past_soft_node_limit(info.nodes)
+            {
+                info.stopped.store(true, Ordering::SeqCst);
+                break 'deepening;
+            }
             let depth = Depth::new(d.try_into().unwrap());
             // aspiration loop:
             loop {
@@ -581,13 +589,13 @@ impl Board {
                 let tb_value = match wdl {
                     WDL::Win => tb_win_in(height),
                     WDL::Loss => tb_loss_in(height),
-                    WDL::Draw => 0,
+                    WDL::Draw | WDL::CursedWin | WDL::BlessedLoss => 0,
                 };
 
                 let tb_bound = match wdl {
                     WDL::Win => Bound::Lower,
                     WDL::Loss => Bound::Upper,
-                    WDL::Draw => Bound::Exact,
+                    WDL::Draw | WDL::CursedWin | WDL::BlessedLoss => Bound::Exact,
                 };
 
                 if tb_bound == Bound::Exact
@@ -1362,6 +1370,7 @@ impl Board {
                 "info score {sstr}{bound_string} wdl {wdl} depth {depth} seldepth {} nodes {total_nodes} time {} nps {nps} hashfull {hashfull} tbhits {tbhits} pv {pv}",
                 info.seldepth.ply_to_horizon(),
                 info.time_manager.start_time.elapsed().as_millis(),
+                pv = self.pv_uci(pv),
                 hashfull = tt.hashfull(),
                 tbhits = TB_HITS.load(Ordering::SeqCst),
                 wdl = uci::format_wdl(pv.score, self.ply()),
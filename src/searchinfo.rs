@@ -305,4 +305,31 @@ mod tests {
 
         drop(guard);
     }
+
+    #[test]
+    fn soft_node_limit_finishes_iteration() {
+        let guard = TEST_LOCK.lock().unwrap();
+        magic::initialise();
+        let mut position = Board::default();
+        let stopped = AtomicBool::new(false);
+        let time_manager = TimeManager {
+            limit: SearchLimit::SoftNodes { soft_limit: 2_000, hard_limit: 10_000_000 },
+            ..TimeManager::default()
+        };
+        let mut info =
+            SearchInfo { time_manager, print_to_stdout: false, ..SearchInfo::new(&stopped) };
+        let mut tt = TT::new();
+        tt.resize(MEGABYTE);
+        let mut t = ThreadData::new(0, &position);
+        let (_, mov) =
+            position.search_position::<true>(&mut info, array::from_mut(&mut t), tt.view());
+
+        assert!(!mov.is_null());
+        // the search must run past the soft limit to finish its iteration,
+        // but should stop long before the hard limit.
+        assert!(info.nodes >= 2_000);
+        assert!(info.nodes < 10_000_000);
+
+        drop(guard);
+    }
 }
@@ -18,10 +18,15 @@ use std::ffi::CString;
 use std::ptr;
 
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WDL {
     Win,
     Loss,
     Draw,
+    /// A win that the fifty-move rule turns into a draw.
+    CursedWin,
+    /// A loss that the fifty-move rule turns into a draw.
+    BlessedLoss,
 }
 pub struct WdlDtzResult {
     wdl: WDL,
@@ -54,11 +59,46 @@ pub fn get_max_pieces_count() -> u8 {
 
 /// Gets WDL (Win-Draw-Loss) for the position specified in `board`. Returns [None] if data couldn't be obtained or the feature is disabled.
 pub fn get_wdl(board: &Board) -> Option<WDL> {
+    // guards for invalid positions
+    if board.fifty_move_counter() != 0 {
+        return None;
+    }
+
+    match probe_wdl_tables(board)? {
+        WDL::Win => Some(WDL::Win),
+        WDL::Loss => Some(WDL::Loss),
+        WDL::Draw | WDL::CursedWin | WDL::BlessedLoss => Some(WDL::Draw),
+    }
+}
+
+/// Gets the WDL of the position from the perspective of White, as it would be with the fifty-move counter at zero,
+/// so a [`WDL::CursedWin`] is a cursed win for White and a [`WDL::BlessedLoss`] is a blessed loss for White.
+/// Unlike [`get_wdl_white`], this only probes the WDL tables, so it is thread safe, but if the fifty-move counter
+/// isn't zero then a win or loss might really be cursed or blessed.
+/// Returns [None] if data couldn't be obtained or the feature is disabled.
+pub fn get_wdl_white_ignoring_clock(board: &Board) -> Option<WDL> {
+    let wdl = probe_wdl_tables(board)?;
+
+    if board.turn() == Colour::WHITE {
+        return Some(wdl);
+    }
+    Some(match wdl {
+        WDL::Win => WDL::Loss,
+        WDL::Loss => WDL::Win,
+        WDL::Draw => WDL::Draw,
+        WDL::CursedWin => WDL::BlessedLoss,
+        WDL::BlessedLoss => WDL::CursedWin,
+    })
+}
+
+/// Probes the WDL tables for the side to move in `board`, as if the fifty-move counter were zero.
+/// Returns [None] if data couldn't be obtained or the feature is disabled.
+fn probe_wdl_tables(board: &Board) -> Option<WDL> {
     const WHITE: bool = true;
     const BLACK: bool = false;
 
     // guards for invalid positions
-    if board.castling_rights() != 0 || board.fifty_move_counter() != 0 {
+    if board.castling_rights() != 0 {
         return None;
     }
 
@@ -82,7 +122,9 @@ pub fn get_wdl(board: &Board) -> Option<WDL> {
         match wdl {
             TB_WIN => Some(WDL::Win),
             TB_LOSS => Some(WDL::Loss),
-            TB_DRAW | TB_CURSED_WIN | TB_BLESSED_LOSS => Some(WDL::Draw),
+            TB_DRAW => Some(WDL::Draw),
+            TB_CURSED_WIN => Some(WDL::CursedWin),
+            TB_BLESSED_LOSS => Some(WDL::BlessedLoss),
             _ => None,
         }
     }
@@ -117,6 +159,8 @@ pub fn get_root_wdl_dtz(board: &Board) -> Option<WdlDtzResult> {
         let wdl = match wdl {
             TB_WIN => WDL::Win,
             TB_LOSS => WDL::Loss,
+            TB_CURSED_WIN => WDL::CursedWin,
+            TB_BLESSED_LOSS => WDL::BlessedLoss,
             _ => WDL::Draw,
         };
         let dtz = (result & TB_RESULT_DTZ_MASK) >> TB_RESULT_DTZ_SHIFT;
@@ -165,14 +209,15 @@ pub fn get_tablebase_move(board: &Board) -> Option<(Move, i32)> {
 
     let score = match result.wdl {
         WDL::Win => TB_WIN_SCORE,
-        WDL::Draw => 0,
+        WDL::Draw | WDL::CursedWin | WDL::BlessedLoss => 0,
         WDL::Loss => -TB_WIN_SCORE,
     };
 
     Some((result.best_move, score))
 }
 
-/// Gets the WDL of the position from the perspective of White.
+/// Gets the WDL of the position from the perspective of White, taking the fifty-move counter into account,
+/// so a [`WDL::CursedWin`] is a cursed win for White and a [`WDL::BlessedLoss`] is a blessed loss for White.
 /// Returns [None] if data couldn't be obtained or the feature is disabled.
 pub fn get_wdl_white(board: &Board) -> Option<WDL> {
     let probe_result = get_root_wdl_dtz(board)?;
@@ -183,5 +228,7 @@ pub fn get_wdl_white(board: &Board) -> Option<WDL> {
         WDL::Win => Some(if stm { WDL::Win } else { WDL::Loss }),
         WDL::Draw => Some(WDL::Draw),
         WDL::Loss => Some(if stm { WDL::Loss } else { WDL::Win }),
+        WDL::CursedWin => Some(if stm { WDL::CursedWin } else { WDL::BlessedLoss }),
+        WDL::BlessedLoss => Some(if stm { WDL::BlessedLoss } else { WDL::CursedWin }),
     }
 }
@@ -1,15 +1,11 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    sync::atomic::AtomicBool,
-    time::Instant,
-};
+use std::{error::Error, sync::atomic::AtomicBool, time::Instant};
 
 use rand::prelude::SliceRandom;
 
 use crate::{
     board::{evaluation::parameters::EvalParams, Board},
     definitions::{INFINITY, MEGABYTE},
+    nnue::convert::{InvalidDataPolicy, OurTexel, Records},
     piece::Colour,
     search::PVariation,
     searchinfo::SearchInfo,
@@ -44,7 +40,7 @@ fn total_squared_error(data: &[TrainingExample], params: &EvalParams, k: f64) ->
     data.iter()
         .map(|TrainingExample { fen, outcome }| {
             // set_from_fen does not allocate, so it should be pretty fast.
-            pos.set_from_fen(fen).unwrap();
+            pos.set_from_fen(fen).expect("FENs are validated when they are read");
             // quiescence is likely the source of all computation time.
             // don't use NNUE, this only works for HCE.
             let pov_score = Board::quiescence::<true, false>(
@@ -165,12 +161,14 @@ pub fn tune<P>(
     starting_params: &EvalParams,
     params_to_tune: Option<&[usize]>,
     data_path: P,
-) where
+    policy: InvalidDataPolicy,
+) -> Result<(), Box<dyn Error>>
+where
     P: AsRef<std::path::Path>,
 {
     println!("Parsing tuning data...");
     let start_time = Instant::now();
-    let mut data = read_data(data_path);
+    let mut data = read_data(data_path.as_ref(), policy)?;
     println!("Parsed {} examples in {:.1}s", data.len(), start_time.elapsed().as_secs_f32());
 
     println!("Shuffling data...");
@@ -205,22 +203,21 @@ pub fn tune<P>(
     println!("Best loss: {best_loss:.6}");
     println!("Saving best parameters...");
     EvalParams::save_param_vec(&best_params, "params/localsearchfinal.txt");
+    Ok(())
 }
 
-fn read_data<P>(path: P) -> Vec<TrainingExample>
-where
-    P: AsRef<std::path::Path>,
-{
-    let data = File::open(path).unwrap();
-    BufReader::new(data)
-        .lines()
-        .map(|line| {
-            let line = line.unwrap();
-            // we expect lines in the format "{fen};{outcome}"
-            let (fen, outcome) = line.rsplit_once(';').unwrap();
-            let outcome = outcome.parse::<f64>().unwrap();
-            let fen = fen.to_owned();
-            TrainingExample { fen, outcome }
+fn read_data(
+    path: &std::path::Path,
+    policy: InvalidDataPolicy,
+) -> Result<Vec<TrainingExample>, Box<dyn Error>> {
+    let mut records = Records::open(&OurTexel, path, policy)?;
+    let data = records
+        .by_ref()
+        .map(|record| {
+            let record = record?;
+            Ok(TrainingExample { fen: record.fen, outcome: record.wdl.into() })
         })
-        .collect::<Vec<_>>()
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    records.report_skipped();
+    Ok(data)
 }
@@ -41,6 +41,10 @@ pub enum SearchLimit {
     Time(u64),
     TimeOrCorrectMoves(u64, Vec<Move>),
     Nodes(u64),
+    SoftNodes {
+        soft_limit: u64,
+        hard_limit: u64,
+    },
     Mate {
         ply: usize,
     },
@@ -173,7 +177,7 @@ impl TimeManager {
             SearchLimit::Depth(_) | SearchLimit::Mate { .. } | SearchLimit::Infinite => {
                 stopped.load(Ordering::SeqCst)
             }
-            SearchLimit::Nodes(nodes) => {
+            SearchLimit::Nodes(nodes) | SearchLimit::SoftNodes { hard_limit: nodes, .. } => {
                 let past_limit = nodes_so_far >= nodes;
                 if past_limit {
                     stopped.store(true, Ordering::SeqCst);
@@ -209,6 +213,14 @@ impl TimeManager {
         }
     }
 
+    /// If we have searched enough nodes that we should stop upon completing a depth.
+    pub const fn is_past_soft_node_limit(&self, nodes_so_far: u64) -> bool {
+        match self.limit {
+            SearchLimit::SoftNodes { soft_limit, .. } => nodes_so_far >= soft_limit,
+            _ => false,
+        }
+    }
+
     pub fn time_since_start(&self) -> Duration {
         self.start_time.elapsed()
     }
@@ -140,7 +140,8 @@ impl TT {
     }
 
     pub fn clear(&self) {
-        self.table.iter().for_each(|x| x.store(Self::NULL_VALUE, Ordering::SeqCst));
+        // relaxed stores compile to a plain memset, which matters when the table is cleared before every search.
+        self.table.iter().for_each(|x| x.store(Self::NULL_VALUE, Ordering::Relaxed));
     }
 
     const fn pack_key(key: u64) -> u16 {
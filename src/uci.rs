@@ -617,7 +617,8 @@ pub fn main_loop(params: EvalParams, global_bench: bool) {
                 Ok(())
             }
             "nnuebench" => {
-                nnue::network::inference_benchmark(&thread_data[0].nnue);
+                nnue::network::inference_benchmark(&thread_data[0].nnue, &pos);
+                nnue::network::refresh_benchmark();
                 Ok(())
             }
             input if input.starts_with("setoption") => {